@@ -0,0 +1,130 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while reading rules and settings, or applying them
+#[derive(Debug)]
+pub enum DejunkerError {
+    /// A local file could not be read or written
+    Io { path: String, source: io::Error },
+    /// A file could not be fetched from a URL
+    Download { url: String, message: String },
+    /// A rules database, settings file or other input file is malformed
+    Parse { file: String, message: String },
+    /// A rule that is not defined in the rules database
+    UnknownRule(String),
+    /// A value a rule does not accept
+    InvalidValue { rule: String, value: String },
+    /// An exec entry with a subsystem we don't implement
+    UnsupportedSubsystem(String),
+    /// A rule or exec entry with a value type we don't implement
+    UnsupportedValueType(String),
+    /// Not enough rights (e.g. not elevated), or a registry path that cannot be reached
+    AccessDenied(String),
+    /// A registry operation failed, with the Win32 error code if there is one
+    Registry { message: String, code: Option<u32> },
+    /// A target user without a profile, or a profile that cannot be used
+    UserProfile(String),
+    /// Rules that do not have the value they were set to after applying them
+    Drift(Vec<String>),
+    /// An operation of a subsystem other than the registry (e.g. on a service) failed, with
+    /// the Win32 error code if there is one
+    System { message: String, code: Option<u32> },
+}
+
+impl DejunkerError {
+    /// A registry failure that did not come from the Win32 API
+    pub fn registry(message: impl Into<String>) -> Self {
+        DejunkerError::Registry {
+            message: message.into(),
+            code: None,
+        }
+    }
+
+    /// A subsystem failure that did not come from the Win32 API
+    pub fn system(message: impl Into<String>) -> Self {
+        DejunkerError::System {
+            message: message.into(),
+            code: None,
+        }
+    }
+
+    pub fn parse(file: &str, message: impl Into<String>) -> Self {
+        DejunkerError::Parse {
+            file: file.to_string(),
+            message: message.into(),
+        }
+    }
+
+    /// For use with map_err, tags an I/O error with the file it happened on
+    pub fn io(path: impl AsRef<std::path::Path>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.as_ref().display().to_string();
+        move |source| DejunkerError::Io { path, source }
+    }
+
+    /// Process exit code for the error. These are documented in README.md, keep them stable.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            DejunkerError::Io { .. } => 1,
+            // 2 is used by clap for command line errors
+            DejunkerError::Download { .. } => 3,
+            DejunkerError::Parse { .. } => 4,
+            DejunkerError::UnknownRule(_) => 5,
+            DejunkerError::InvalidValue { .. } => 6,
+            DejunkerError::UnsupportedSubsystem(_) => 7,
+            DejunkerError::UnsupportedValueType(_) => 8,
+            DejunkerError::AccessDenied(_) => 9,
+            DejunkerError::Registry { .. } => 10,
+            DejunkerError::UserProfile(_) => 11,
+            DejunkerError::Drift(_) => 12,
+            DejunkerError::System { .. } => 13,
+        }
+    }
+}
+
+impl fmt::Display for DejunkerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DejunkerError::Io { path, source } => write!(f, "{}: {}", path, source),
+            DejunkerError::Download { url, message } => {
+                write!(f, "Could not fetch '{}': {}", url, message)
+            }
+            DejunkerError::Parse { file, message } => {
+                write!(f, "Could not parse '{}': {}", file, message)
+            }
+            DejunkerError::UnknownRule(rule) => write!(f, "Unknown rule '{}'", rule),
+            DejunkerError::InvalidValue { rule, value } => {
+                write!(f, "Invalid value '{}' for rule '{}'", value, rule)
+            }
+            DejunkerError::UnsupportedSubsystem(subsystem) => {
+                write!(f, "Subsystem '{}' is not supported", subsystem)
+            }
+            DejunkerError::UnsupportedValueType(value_type) => {
+                write!(f, "Value type '{}' is not supported", value_type)
+            }
+            DejunkerError::AccessDenied(message) => write!(f, "Access denied: {}", message),
+            DejunkerError::Registry {
+                message,
+                code: Some(code),
+            } => write!(f, "{} (error {})", message, code),
+            DejunkerError::Registry { message, code: None } => write!(f, "{}", message),
+            DejunkerError::UserProfile(message) => write!(f, "User profile: {}", message),
+            DejunkerError::Drift(rules) => {
+                write!(f, "Rules not in the requested state after applying: {}", rules.join(", "))
+            }
+            DejunkerError::System {
+                message,
+                code: Some(code),
+            } => write!(f, "{} (error {})", message, code),
+            DejunkerError::System { message, code: None } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for DejunkerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DejunkerError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use log::warn;
+use std::collections::{HashMap, HashSet};
+
+use crate::files::db::Rule;
+use crate::files::pol::PolicyScope;
+use crate::files::settings;
+use crate::registry::recorder::{Change, RecordingRegistry};
+use crate::registry::{self, MemoryRegistry, RegistryValue, REG_DWORD, REG_QWORD, REG_SZ};
+use crate::subsystems::Subsystems;
+use crate::types::onoff::OnOffType;
+
+/// Base name of the generated ADMX and ADML files
+pub const POLICY_FILE_NAME: &str = "dejunker";
+
+const TARGET_NAMESPACE: &str = "RedSigil.Policies.Dejunker";
+const CATEGORY: &str = "Dejunker";
+const CATEGORY_DISPLAY_NAME: &str = "Windows dejunker";
+const SCHEMA_NAMESPACE: &str = "http://schemas.microsoft.com/GroupPolicy/2006/07/PolicyDefinitions";
+
+/// An ADMX file, and the matching language resources (ADML)
+pub struct PolicyDefinitions {
+    pub admx: String,
+    pub adml: String,
+}
+
+/// What a policy state does to a registry value (None: the value is deleted)
+type Write = Option<RegistryValue>;
+
+/// A registry value a policy manages, and what enabling and disabling the policy does to it
+struct PolicyValue {
+    key: String,
+    name: String,
+    enabled: Option<Write>,
+    disabled: Option<Write>,
+}
+
+/// A rule, as a group policy setting
+struct Policy<'a> {
+    rule: &'a Rule,
+    /// The name of the policy, and the id of its strings
+    id: String,
+    scope: PolicyScope,
+    values: Vec<PolicyValue>,
+}
+
+/// Render the rules of a database as group policy settings, each rule becoming a policy
+/// that sets the rule on when enabled and off when disabled. Rules that cannot be expressed
+/// as a policy are skipped. Rules whose names only differ in punctuation (e.g. a-b and a_b)
+/// get a numbered policy id.
+///
+/// * rules: the rules to render
+///
+pub fn format_policy_definitions(rules: &HashMap<String, Rule>) -> PolicyDefinitions {
+    let mut sorted_rules = rules.values().collect::<Vec<&Rule>>();
+    sorted_rules.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut policies = Vec::new();
+    let mut ids = HashSet::new();
+    for rule in sorted_rules {
+        match policy_from_rule(rules, rule) {
+            Ok(mut policy) => {
+                let id = (1..)
+                    .map(|n| match n {
+                        1 => policy.id.clone(),
+                        n => format!("{}_{}", policy.id, n),
+                    })
+                    .find(|id| !ids.contains(&id.to_lowercase()))
+                    .unwrap();
+                if id != policy.id {
+                    warn!("Rule {} is exported as policy {}, {} is taken by another rule", rule.name, id, policy.id);
+                }
+                ids.insert(id.to_lowercase());
+                policy.id = id;
+                policies.push(policy);
+            }
+            Err(e) => warn!("Rule {} was skipped: {}", rule.name, e),
+        }
+    }
+
+    PolicyDefinitions {
+        admx: format_admx(&policies),
+        adml: format_adml(&policies),
+    }
+}
+
+/// Find out what a rule writes when on and when off. Fails with the reason the rule cannot
+/// be expressed as a policy.
+fn policy_from_rule<'a>(rules: &HashMap<String, Rule>, rule: &'a Rule) -> Result<Policy<'a>, String> {
+    if rule.value.value_type.to_lowercase() != "onoff" {
+        return Err(format!(
+            "value type {} cannot be expressed as a policy, only OnOff rules can",
+            rule.value.value_type
+        ));
+    }
+
+    let record = |value: OnOffType| -> Result<Vec<Change>, String> {
+        let mut target = MemoryRegistry::new();
+        let mut recorder = RecordingRegistry::new(&mut target, false);
+        let name = &rule.name;
+        settings::execute_rule(&mut recorder, &mut Subsystems::none(), rules, name, &value.to_string(), false)
+            .map_err(|e| e.to_string())?;
+        Ok(recorder.into_changes())
+    };
+
+    let mut scope = None;
+    let mut values: Vec<PolicyValue> = Vec::new();
+    for (enabled, changes) in [(true, record(OnOffType::On)?), (false, record(OnOffType::Off)?)] {
+        for change in changes {
+            let (hive, key) = registry::get_path_components(change.path()).map_err(|e| e.to_string())?;
+            let change_scope = PolicyScope::from_hive(hive)
+                .ok_or_else(|| format!("{} cannot be managed through a policy", change.path()))?;
+            if scope.is_some_and(|scope| scope != change_scope) {
+                return Err("it writes to both HKCU and HKLM".to_string());
+            }
+            scope = Some(change_scope);
+
+            let (name, write) = match change {
+                Change::SetValue { path, name, value, .. } => {
+                    if ![REG_DWORD, REG_QWORD, REG_SZ].contains(&value.kind()) {
+                        return Err(format!("{} -> {} is not a number or a string", path, name));
+                    }
+                    (name, Some(value))
+                }
+                Change::DeleteValue { name, .. } => (name, None),
+                Change::DeleteKey { .. } => {
+                    return Err(format!("it deletes the key {}", change.path()));
+                }
+                // the policy creates the keys it writes to
+                Change::CreateKey { .. } => continue,
+            };
+
+            let index = match values.iter().position(|v| v.key == key && v.name == name) {
+                Some(index) => index,
+                None => {
+                    values.push(PolicyValue {
+                        key,
+                        name,
+                        enabled: None,
+                        disabled: None,
+                    });
+                    values.len() - 1
+                }
+            };
+            if enabled {
+                values[index].enabled = Some(write);
+            } else {
+                values[index].disabled = Some(write);
+            }
+        }
+    }
+
+    Ok(Policy {
+        rule,
+        id: policy_id(&rule.name),
+        scope: scope.ok_or("it does not write to the registry")?,
+        values,
+    })
+}
+
+fn format_admx(policies: &[Policy]) -> String {
+    let mut output = String::new();
+    output.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\r\n");
+    output.push_str(&format!(
+        "<policyDefinitions xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" revision=\"1.0\" schemaVersion=\"1.0\" xmlns=\"{}\">\r\n",
+        SCHEMA_NAMESPACE
+    ));
+    output.push_str("  <policyNamespaces>\r\n");
+    output.push_str(&format!(
+        "    <target prefix=\"{}\" namespace=\"{}\" />\r\n",
+        POLICY_FILE_NAME, TARGET_NAMESPACE
+    ));
+    output.push_str("    <using prefix=\"windows\" namespace=\"Microsoft.Policies.Windows\" />\r\n");
+    output.push_str("  </policyNamespaces>\r\n");
+    output.push_str("  <resources minRequiredRevision=\"1.0\" />\r\n");
+    output.push_str("  <categories>\r\n");
+    output.push_str(&format!(
+        "    <category name=\"{0}\" displayName=\"$(string.{0})\" />\r\n",
+        CATEGORY
+    ));
+    output.push_str("  </categories>\r\n");
+    output.push_str("  <policies>\r\n");
+
+    for policy in policies {
+        let id = &policy.id;
+        let class = match policy.scope {
+            PolicyScope::User => "User",
+            PolicyScope::Machine => "Machine",
+        };
+
+        // the first value written in both states is the one the policy state is read from
+        let main = policy
+            .values
+            .iter()
+            .position(|v| v.enabled.is_some() && v.disabled.is_some())
+            .unwrap_or(0);
+        let main_value = &policy.values[main];
+
+        output.push_str(&format!(
+            "    <policy name=\"{0}\" class=\"{1}\" displayName=\"$(string.{0})\" explainText=\"$(string.{0}_Help)\" key=\"{2}\"",
+            id,
+            class,
+            escape(&main_value.key)
+        ));
+        if main_value.enabled.is_some() && main_value.disabled.is_some() {
+            output.push_str(&format!(" valueName=\"{}\"", escape(&main_value.name)));
+        }
+        output.push_str(">\r\n");
+        output.push_str(&format!("      <parentCategory ref=\"{}\" />\r\n", CATEGORY));
+        output.push_str("      <supportedOn ref=\"windows:SUPPORTED_Windows_10_0\" />\r\n");
+
+        if let (Some(enabled), Some(disabled)) = (&main_value.enabled, &main_value.disabled) {
+            output.push_str(&format!("      <enabledValue>{}</enabledValue>\r\n", format_write(enabled)));
+            output.push_str(&format!("      <disabledValue>{}</disabledValue>\r\n", format_write(disabled)));
+        }
+
+        let others = policy.values.iter().enumerate().filter(|(index, v)| {
+            *index != main || v.enabled.is_none() || v.disabled.is_none()
+        });
+        let enabled_list: Vec<(&PolicyValue, &Write)> = others
+            .clone()
+            .filter_map(|(_, v)| v.enabled.as_ref().map(|write| (v, write)))
+            .collect();
+        let disabled_list: Vec<(&PolicyValue, &Write)> = others
+            .filter_map(|(_, v)| v.disabled.as_ref().map(|write| (v, write)))
+            .collect();
+
+        for (tag, list) in [("enabledList", enabled_list), ("disabledList", disabled_list)] {
+            if list.is_empty() {
+                continue;
+            }
+            output.push_str(&format!("      <{}>\r\n", tag));
+            for (value, write) in list {
+                output.push_str(&format!(
+                    "        <item key=\"{}\" valueName=\"{}\"><value>{}</value></item>\r\n",
+                    escape(&value.key),
+                    escape(&value.name),
+                    format_write(write)
+                ));
+            }
+            output.push_str(&format!("      </{}>\r\n", tag));
+        }
+
+        output.push_str("    </policy>\r\n");
+    }
+
+    output.push_str("  </policies>\r\n");
+    output.push_str("</policyDefinitions>\r\n");
+    output
+}
+
+fn format_adml(policies: &[Policy]) -> String {
+    let mut output = String::new();
+    output.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\r\n");
+    output.push_str(&format!(
+        "<policyDefinitionResources xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" revision=\"1.0\" schemaVersion=\"1.0\" xmlns=\"{}\">\r\n",
+        SCHEMA_NAMESPACE
+    ));
+    output.push_str(&format!("  <displayName>{}</displayName>\r\n", CATEGORY_DISPLAY_NAME));
+    output.push_str("  <description>Settings managed by the Windows dejunker rules database</description>\r\n");
+    output.push_str("  <resources>\r\n");
+    output.push_str("    <stringTable>\r\n");
+    output.push_str(&format!(
+        "      <string id=\"{}\">{}</string>\r\n",
+        CATEGORY, CATEGORY_DISPLAY_NAME
+    ));
+
+    for policy in policies {
+        let id = &policy.id;
+        output.push_str(&format!(
+            "      <string id=\"{}\">{}</string>\r\n",
+            id,
+            escape(&policy.rule.description)
+        ));
+        output.push_str(&format!(
+            "      <string id=\"{}_Help\">{}\r\n\r\nEnabled: --{2}=on\r\nDisabled: --{2}=off</string>\r\n",
+            id,
+            escape(&policy.rule.description),
+            escape(&policy.rule.name)
+        ));
+    }
+
+    output.push_str("    </stringTable>\r\n");
+    output.push_str("  </resources>\r\n");
+    output.push_str("</policyDefinitionResources>\r\n");
+    output
+}
+
+/// Value element for a policy state
+fn format_write(write: &Write) -> String {
+    match write {
+        None => "<delete />".to_string(),
+        Some(RegistryValue::Dword(value)) => format!("<decimal value=\"{}\" />", value),
+        Some(RegistryValue::Qword(value)) => format!("<longDecimal value=\"{}\" />", value),
+        Some(value) => format!("<string>{}</string>", escape(&value.to_string())),
+    }
+}
+
+/// Policy (and string) identifier for a rule name
+fn policy_id(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect()
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::db::{Exec, ExecAction};
+    use crate::test_support::{registry_exec, rule, rules};
+    use std::collections::BTreeMap;
+
+    const KEY: &str = "HKCU\\Software\\Microsoft\\Windows\\CurrentVersion\\AdvertisingInfo";
+
+    fn named_rule(name: &str, value_type: &str) -> Rule {
+        let mut rule = rule(name, value_type, vec![registry_exec(KEY, name, "u32")]);
+        rule.description = format!("Rule {}", name);
+        rule.value.choices = vec!["on".to_string(), "off".to_string()];
+        rule
+    }
+
+    fn policy_names(admx: &str) -> Vec<&str> {
+        admx.split("<policy name=\"").skip(1).filter_map(|policy| policy.split('"').next()).collect()
+    }
+
+    /// What a policy sets, the elements after its category and supported versions
+    fn policy_body(rule: Rule) -> String {
+        let admx = format_policy_definitions(&rules([rule])).admx;
+        let body = admx.split("<supportedOn ref=\"windows:SUPPORTED_Windows_10_0\" />\r\n").nth(1).unwrap();
+        body.split("    </policy>").next().unwrap().to_string()
+    }
+
+    #[test]
+    fn onoff_policies_write_one_and_zero() {
+        assert_eq!(
+            policy_body(named_rule("advertising-id", "OnOff")),
+            "      <enabledValue><decimal value=\"1\" /></enabledValue>\r\n      \
+             <disabledValue><decimal value=\"0\" /></disabledValue>\r\n"
+        );
+
+        let mut reversed = named_rule("advertising-id", "OnOff");
+        reversed.exec[0].reversed = Some(true);
+        assert_eq!(
+            policy_body(reversed),
+            "      <enabledValue><decimal value=\"0\" /></enabledValue>\r\n      \
+             <disabledValue><decimal value=\"1\" /></disabledValue>\r\n"
+        );
+    }
+
+    #[test]
+    fn deleted_values_are_deleted_by_the_policy() {
+        let mut rule = named_rule("advertising-id", "OnOff");
+        rule.exec[0].values = BTreeMap::from([
+            ("on".to_string(), ExecAction::Write("1".to_string())),
+            ("off".to_string(), ExecAction::DeleteValue),
+        ]);
+        assert_eq!(
+            policy_body(rule),
+            "      <enabledValue><decimal value=\"1\" /></enabledValue>\r\n      \
+             <disabledValue><delete /></disabledValue>\r\n"
+        );
+    }
+
+    #[test]
+    fn further_values_are_listed() {
+        let mut rule = named_rule("advertising-id", "OnOff");
+        rule.exec.push(Exec {
+            reversed: Some(true),
+            ..registry_exec(&format!("{}\\Policy", KEY), "Disabled", "u32")
+        });
+        assert_eq!(
+            policy_body(rule),
+            format!(
+                "      <enabledValue><decimal value=\"1\" /></enabledValue>\r\n      \
+                 <disabledValue><decimal value=\"0\" /></disabledValue>\r\n      \
+                 <enabledList>\r\n        \
+                 <item key=\"{0}\\Policy\" valueName=\"Disabled\"><value><decimal value=\"0\" /></value></item>\r\n      \
+                 </enabledList>\r\n      \
+                 <disabledList>\r\n        \
+                 <item key=\"{0}\\Policy\" valueName=\"Disabled\"><value><decimal value=\"1\" /></value></item>\r\n      \
+                 </disabledList>\r\n",
+                &KEY[5..]
+            )
+        );
+    }
+
+    #[test]
+    fn policy_ids_are_unique() {
+        let rules = rules([named_rule("a-b", "OnOff"), named_rule("a_b", "OnOff"), named_rule("a.b", "OnOff")]);
+        let definitions = format_policy_definitions(&rules);
+        assert_eq!(policy_names(&definitions.admx), vec!["a_b", "a_b_2", "a_b_3"]);
+        assert!(definitions.adml.contains("<string id=\"a_b_3_Help\">Rule a_b\r\n"));
+    }
+
+    #[test]
+    fn only_onoff_rules_are_policies() {
+        let rules = rules([named_rule("advertising-id", "OnOff"), named_rule("telemetry", "Enum")]);
+        let definitions = format_policy_definitions(&rules);
+        assert_eq!(policy_names(&definitions.admx), vec!["advertising_id"]);
+    }
+}
@@ -1,9 +1,20 @@
-use log::{debug, error};
-use reqwest::blocking::get;
+use log::debug;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
-use std::{collections::HashMap, fs::read_to_string, process};
-use yaml_rust2::YamlLoader;
+use std::collections::{BTreeMap, HashMap};
+use yaml_rust2::{Yaml, YamlLoader};
+
+use crate::error::DejunkerError;
+use crate::files::settings;
+use crate::firewall::{self, Direction};
+use crate::hosts;
+use crate::packages::{self, PackageScope};
+use crate::registry::RegistryView;
+use crate::services::StartType;
+use crate::tasks;
+use crate::types::onoff::OnOffType;
+use crate::types::state::RuleState;
 
 const DATABASE_ID: &str = "redsigil.dfckr.db.v1";
 
@@ -23,69 +34,157 @@ pub struct Rule {
     pub exec: Vec<Exec>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Value {
     #[serde(rename = "type")]
     pub value_type: String,
+    /// The values an enum rule can be set to
+    pub choices: Vec<String>,
+    /// The range of an integer rule
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    /// A regular expression the whole value of a string rule must match
+    pub pattern: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Value {
+    /// The values a rule of this type can be set to, None if the type is not supported
+    pub fn choices(&self) -> Option<Vec<String>> {
+        match self.value_type.to_lowercase().as_str() {
+            "onoff" => Some(vec![OnOffType::On.to_string(), OnOffType::Off.to_string()]),
+            "enum" => Some(self.choices.clone()),
+            _ => None,
+        }
+    }
+
+    /// Whether rules of this type can be evaluated and applied
+    pub fn is_supported(&self) -> bool {
+        matches!(
+            self.value_type.to_lowercase().as_str(),
+            "onoff" | "enum" | "integer" | "string"
+        )
+    }
+
+    /// Check a value a rule of this type is to be set to. Fails with the reason the value
+    /// is not accepted.
+    pub fn check(&self, value: &str) -> Result<(), String> {
+        match self.value_type.to_lowercase().as_str() {
+            "integer" => {
+                let number = value
+                    .parse::<i64>()
+                    .map_err(|_| format!("'{}' is not a whole number", value))?;
+                if let Some(min) = self.min.filter(|min| number < *min) {
+                    return Err(format!("{} is less than {}", number, min));
+                }
+                if let Some(max) = self.max.filter(|max| number > *max) {
+                    return Err(format!("{} is more than {}", number, max));
+                }
+                Ok(())
+            }
+            "string" => match &self.pattern {
+                Some(pattern) if !full_match(pattern)?.is_match(value) => {
+                    Err(format!("'{}' does not match {}", value, pattern))
+                }
+                _ => Ok(()),
+            },
+            _ => match self.choices() {
+                Some(choices) if choices.iter().any(|choice| choice == value) => Ok(()),
+                Some(choices) => Err(format!("'{}' is not one of {}", value, choices.join(", "))),
+                None => Err(format!("value type {} is not supported", self.value_type)),
+            },
+        }
+    }
+}
+
+/// A regular expression matching the whole of a string
+fn full_match(pattern: &str) -> Result<Regex, String> {
+    Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Exec {
     pub subsystem: String,
+    /// What the entry works on, depending on its subsystem: the registry key, the service
+    /// name, the task path, the package name, the firewall rule name (unused for hosts
+    /// entries)
     pub path: String,
     pub value: String,
     pub value_type: String,
     pub reversed: Option<bool>,
+    /// What each value of the rule does to the registry value. When empty, on writes 1 and
+    /// off writes 0 (the other way around if reversed).
+    pub values: BTreeMap<String, ExecAction>,
+    /// For multi_string entries, an item that is added to or removed from the list (instead of
+    /// writing the whole list)
+    pub item: Option<String>,
+    /// The registry view(s) the entry applies to, None for the view of the running program
+    /// (the 64-bit view)
+    pub view: Option<RegistryView>,
+    /// For service entries, stop the service when the rule sets it to manual or disabled
+    pub stop: Option<bool>,
+    /// For appx entries, the publisher of the packages (a publisher id, or the subject of its
+    /// certificate with wildcards), None for any
+    pub publisher: Option<String>,
+    /// For appx entries, where the packages are installed, None for both the current user and
+    /// the provisioned packages
+    pub scope: Option<PackageScope>,
+    /// For hosts entries, the hostnames blocked in the hosts file
+    pub hostnames: Vec<String>,
+    /// For firewall entries, the path of the program whose traffic is blocked, None for any
+    pub program: Option<String>,
+    /// For firewall entries, the short name of the service whose traffic is blocked, None for any
+    pub service: Option<String>,
+    /// For firewall entries, the addresses traffic is blocked to (or from), empty for any
+    pub remote_addresses: Vec<String>,
+    /// For firewall entries, the traffic that is blocked, None for outbound traffic
+    pub direction: Option<Direction>,
+}
+
+/// What setting a rule to one of its values does to the registry value of an exec entry
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ExecAction {
+    /// Write this data, in the type of the exec entry (the items of a multi_string are
+    /// separated by line breaks, binary data is hex). For entries with an item, the data is
+    /// whether the item is present or absent.
+    Write(String),
+    /// Delete the value
+    DeleteValue,
+    /// Delete the key of the exec entry, with all its values and subkeys. With backup, the
+    /// values are first copied to the backup key (see registry::BACKUP_KEY).
+    DeleteKey { backup: bool },
 }
 
 /// Read a rules database into memory
 /// 
 /// * path_or_url: the path of the rule file to read
 /// 
-pub fn read_database(path_or_url: &str) -> Result<RulesDatabase, Box<dyn std::error::Error>> {
-    let contents = if path_or_url.starts_with("http://") || path_or_url.starts_with("https://") {
-        match get(path_or_url) {
-            Ok(response) => response.text()?,
-            Err(e) => {
-                error!(
-                    "Could not fetch database from URL '{}': {}",
-                    path_or_url,
-                    e.to_string()
-                );
-                process::exit(1);
-            }
-        }
-    } else {
-        match read_to_string(path_or_url) {
-            Ok(contents) => contents,
-            Err(e) => {
-                error!(
-                    "Could not read database file '{}': {}",
-                    path_or_url,
-                    e.to_string()
-                );
-                process::exit(e.raw_os_error().unwrap_or(1));
-            }
-        }
-    };
+pub fn read_database(path_or_url: &str) -> Result<RulesDatabase, DejunkerError> {
+    let contents = super::read_file_or_url(path_or_url)?;
 
-    let docs = YamlLoader::load_from_str(&contents)?;
-    let doc = &docs[0];
+    let docs = YamlLoader::load_from_str(&contents)
+        .map_err(|e| DejunkerError::parse(path_or_url, e.to_string()))?;
+    let doc = docs
+        .first()
+        .ok_or_else(|| DejunkerError::parse(path_or_url, "the file is empty"))?;
 
     let file = doc["file"].as_str().unwrap_or("").to_string();
 
     debug!("Database type is {}", file);
     if file != DATABASE_ID {
-        panic!("Invalid database type when reading '{}'", file);
+        return Err(DejunkerError::parse(
+            path_or_url,
+            format!("not a rules database (file type is '{}', expected '{}')", file, DATABASE_ID),
+        ));
     }
 
-    let rules_yaml: &Vec<yaml_rust2::Yaml> = doc["rules"].as_vec().ok_or("Invalid rules format")?;
+    let rules_yaml: &Vec<yaml_rust2::Yaml> = doc["rules"].as_vec().ok_or_else(|| DejunkerError::parse(path_or_url, "missing 'rules' list"))?;
 
     let mut rules = HashMap::<String, Rule>::new();
     for rule_yaml in rules_yaml {
+        let rule_name = rule_yaml["arg"].as_str().unwrap_or("").to_string();
         let rule = Rule {
             id: rule_yaml["rule"].as_str().unwrap_or("").to_string(),
-            name: rule_yaml["arg"].as_str().unwrap_or("").to_string(),
+            name: rule_name.clone(),
             description: rule_yaml["description"].as_str().unwrap_or("").to_string(),
             admin_required: rule_yaml["admin_required"].as_bool().unwrap_or(false),
             value: Value {
@@ -93,20 +192,72 @@ pub fn read_database(path_or_url: &str) -> Result<RulesDatabase, Box<dyn std::er
                     .as_str()
                     .unwrap_or("")
                     .to_string(),
+                choices: rule_yaml["value"]["choices"]
+                    .as_vec()
+                    .unwrap_or(&vec![])
+                    .iter()
+                    .filter_map(yaml_scalar)
+                    .collect(),
+                min: rule_yaml["value"]["min"].as_i64(),
+                max: rule_yaml["value"]["max"].as_i64(),
+                pattern: rule_yaml["value"]["pattern"].as_str().map(|pattern| pattern.to_string()),
             },
             exec: rule_yaml["exec"]
                 .as_vec()
                 .unwrap_or(&vec![])
                 .iter()
-                .map(|exec_yaml| Exec {
-                    subsystem: exec_yaml["subsystem"].as_str().unwrap_or("").to_string(),
-                    path: exec_yaml["path"].as_str().unwrap_or("").to_string(),
-                    value: exec_yaml["value"].as_str().unwrap_or("").to_string(),
-                    value_type: exec_yaml["type"].as_str().unwrap_or("").to_string(),
-                    reversed: exec_yaml["reversed"].as_bool(),
+                .map(|exec_yaml| {
+                    Ok(Exec {
+                        subsystem: exec_yaml["subsystem"].as_str().unwrap_or("").to_string(),
+                        path: exec_yaml["path"].as_str().unwrap_or("").to_string(),
+                        value: exec_yaml["value"].as_str().unwrap_or("").to_string(),
+                        value_type: exec_yaml["type"].as_str().unwrap_or("").to_string(),
+                        reversed: exec_yaml["reversed"].as_bool(),
+                        values: read_exec_values(path_or_url, &rule_name, &exec_yaml["values"])?,
+                        item: exec_yaml["item"].as_str().map(|item| item.to_string()),
+                        view: read_exec_view(path_or_url, &exec_yaml["view"])?,
+                        stop: exec_yaml["stop"].as_bool(),
+                        publisher: exec_yaml["publisher"].as_str().map(|publisher| publisher.to_string()),
+                        scope: read_exec_scope(path_or_url, &exec_yaml["scope"])?,
+                        hostnames: exec_yaml["hostnames"]
+                            .as_vec()
+                            .into_iter()
+                            .flatten()
+                            .filter_map(yaml_scalar)
+                            .collect(),
+                        program: exec_yaml["program"].as_str().map(|program| program.to_string()),
+                        service: exec_yaml["service"].as_str().map(|service| service.to_string()),
+                        remote_addresses: exec_yaml["remote_addresses"]
+                            .as_vec()
+                            .into_iter()
+                            .flatten()
+                            .filter_map(yaml_scalar)
+                            .collect(),
+                        direction: read_exec_direction(path_or_url, &exec_yaml["direction"])?,
+                    })
                 })
-                .collect(),
+                .collect::<Result<Vec<Exec>, DejunkerError>>()?,
         };
+        if rule.value.value_type.to_lowercase() == "enum" {
+            check_choices(path_or_url, &rule)?;
+        }
+        for op in &rule.exec {
+            match op.subsystem.as_str() {
+                "registry" => check_registry_exec(path_or_url, &rule, op)?,
+                "service" => check_service_exec(path_or_url, &rule, op)?,
+                "scheduled_task" => check_task_exec(path_or_url, &rule, op)?,
+                "appx" => check_package_exec(path_or_url, &rule, op)?,
+                "hosts" => check_hosts_exec(path_or_url, &rule, op)?,
+                "firewall" => check_firewall_exec(path_or_url, &rule, op)?,
+                _ => {}
+            }
+        }
+        if let Some(Err(e)) = rule.value.pattern.as_deref().map(full_match) {
+            return Err(DejunkerError::parse(
+                path_or_url,
+                format!("rule {} has an invalid pattern: {}", rule.name, e),
+            ));
+        }
         debug!("Read rule {}", rule.name);
         rules.insert(rule.name.clone(), rule);
     }
@@ -116,3 +267,605 @@ pub fn read_database(path_or_url: &str) -> Result<RulesDatabase, Box<dyn std::er
 
     Ok(result)
 }
+
+/// An enum rule needs choices, and they must not be mistaken for the states a rule is
+/// reported in. Its registry entries must map every choice, as nothing else says what a
+/// choice writes.
+fn check_choices(path_or_url: &str, rule: &Rule) -> Result<(), DejunkerError> {
+    if rule.value.choices.is_empty() {
+        return Err(DejunkerError::parse(path_or_url, format!("rule {} has no choices", rule.name)));
+    }
+    if let Some(choice) = rule.value.choices.iter().find(|choice| RuleState::is_report_only(choice)) {
+        return Err(DejunkerError::parse(
+            path_or_url,
+            format!("rule {} cannot have '{}' as a choice", rule.name, choice),
+        ));
+    }
+    for op in rule.exec.iter().filter(|op| op.subsystem == "registry") {
+        if let Some(choice) = rule.value.choices.iter().find(|choice| !op.values.contains_key(*choice)) {
+            return Err(DejunkerError::parse(
+                path_or_url,
+                format!("rule {}: the entry for {} has no value for choice '{}'", rule.name, op.path, choice),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// An integer rule cannot go below zero when it is written as an unsigned number
+fn check_registry_exec(path_or_url: &str, rule: &Rule, op: &Exec) -> Result<(), DejunkerError> {
+    // the state of the entry could not tell two values that do the same apart
+    let actions = op.values.iter().collect::<Vec<_>>();
+    for (i, (first, action)) in actions.iter().enumerate() {
+        if let Some((second, _)) = actions[i + 1..].iter().find(|(_, other)| same_action(rule, op, action, other)) {
+            return Err(DejunkerError::parse(
+                path_or_url,
+                format!(
+                    "rule {}: values '{}' and '{}' do the same to {} -> {}",
+                    rule.name, first, second, op.path, op.value
+                ),
+            ));
+        }
+    }
+
+    let unsigned = matches!(op.value_type.as_str(), "u32" | "u64");
+    match rule.value.min {
+        Some(min) if unsigned && min < 0 && rule.value.value_type.to_lowercase() == "integer" => {
+            Err(DejunkerError::parse(
+                path_or_url,
+                format!(
+                    "rule {}: min is {}, but {} -> {} is {} and cannot be negative",
+                    rule.name, min, op.path, op.value, op.value_type
+                ),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Whether two actions of a registry exec entry leave the registry the same (e.g. writing 1
+/// and 01 to a DWORD)
+fn same_action(rule: &Rule, op: &Exec, first: &ExecAction, second: &ExecAction) -> bool {
+    match (first, second) {
+        (ExecAction::Write(first), ExecAction::Write(second)) => {
+            first == second
+                || matches!(
+                    (settings::exec_value(rule, op, first), settings::exec_value(rule, op, second)),
+                    (Ok(first), Ok(second)) if first == second
+                )
+        }
+        (ExecAction::DeleteValue, ExecAction::DeleteValue) => true,
+        (ExecAction::DeleteKey { .. }, ExecAction::DeleteKey { .. }) => true,
+        _ => false,
+    }
+}
+
+/// A service entry names a service, and maps the values of the rule to start types
+fn check_service_exec(path_or_url: &str, rule: &Rule, op: &Exec) -> Result<(), DejunkerError> {
+    if op.path.is_empty() {
+        return Err(DejunkerError::parse(
+            path_or_url,
+            format!("rule {} has a service entry without a service name (path)", rule.name),
+        ));
+    }
+    for action in op.values.values() {
+        match action {
+            ExecAction::Write(start_type) if StartType::from_name(start_type).is_some() => {}
+            ExecAction::Write(start_type) => {
+                return Err(DejunkerError::parse(
+                    path_or_url,
+                    format!(
+                        "rule {}: '{}' is not a start type, expected automatic, delayed, manual or disabled",
+                        rule.name, start_type
+                    ),
+                ));
+            }
+            _ => {
+                return Err(DejunkerError::parse(
+                    path_or_url,
+                    format!("rule {}: services can only be set to a start type", rule.name),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A task entry names tasks by their full path (wildcards only in the name), and maps the
+/// values of the rule to enabled or disabled
+fn check_task_exec(path_or_url: &str, rule: &Rule, op: &Exec) -> Result<(), DejunkerError> {
+    let (folder, name) = tasks::split_task_path(&op.path);
+    if !op.path.starts_with('\\') || name.is_empty() || folder.contains(['*', '?']) {
+        return Err(DejunkerError::parse(
+            path_or_url,
+            format!(
+                "rule {}: '{}' is not a task path, expected \\folder\\name (wildcards only in the name)",
+                rule.name, op.path
+            ),
+        ));
+    }
+    for action in op.values.values() {
+        match action {
+            ExecAction::Write(enabled) if tasks::parse_enabled(enabled).is_some() => {}
+            _ => {
+                return Err(DejunkerError::parse(
+                    path_or_url,
+                    format!("rule {}: tasks can only be enabled or disabled", rule.name),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// An appx entry names packages (wildcards allowed), and maps the values of the rule to
+/// present or absent
+fn check_package_exec(path_or_url: &str, rule: &Rule, op: &Exec) -> Result<(), DejunkerError> {
+    if op.path.is_empty() {
+        return Err(DejunkerError::parse(
+            path_or_url,
+            format!("rule {} has an appx entry without a package name (path)", rule.name),
+        ));
+    }
+    for action in op.values.values() {
+        match action {
+            ExecAction::Write(presence) if packages::parse_presence(presence).is_some() => {}
+            _ => {
+                return Err(DejunkerError::parse(
+                    path_or_url,
+                    format!("rule {}: packages can only be present or absent", rule.name),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A hosts entry lists hostnames, and maps the values of the rule to blocked or allowed
+fn check_hosts_exec(path_or_url: &str, rule: &Rule, op: &Exec) -> Result<(), DejunkerError> {
+    if op.hostnames.is_empty() {
+        return Err(DejunkerError::parse(
+            path_or_url,
+            format!("rule {} has a hosts entry without hostnames", rule.name),
+        ));
+    }
+    if let Some(hostname) = op.hostnames.iter().find(|hostname| !hosts::is_hostname(hostname)) {
+        return Err(DejunkerError::parse(
+            path_or_url,
+            format!("rule {}: '{}' is not a hostname", rule.name, hostname),
+        ));
+    }
+    for action in op.values.values() {
+        match action {
+            ExecAction::Write(blocked) if hosts::parse_blocked(blocked).is_some() => {}
+            _ => {
+                return Err(DejunkerError::parse(
+                    path_or_url,
+                    format!("rule {}: hostnames can only be blocked or allowed", rule.name),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A firewall entry names the firewall rule, which must be restricted to a program, a service
+/// or remote addresses (rather than block all traffic), and maps the values of the rule to
+/// blocked or allowed
+fn check_firewall_exec(path_or_url: &str, rule: &Rule, op: &Exec) -> Result<(), DejunkerError> {
+    if op.path.is_empty() {
+        return Err(DejunkerError::parse(
+            path_or_url,
+            format!("rule {} has a firewall entry without a firewall rule name (path)", rule.name),
+        ));
+    }
+    if op.program.is_none() && op.service.is_none() && op.remote_addresses.is_empty() {
+        return Err(DejunkerError::parse(
+            path_or_url,
+            format!(
+                "rule {}: firewall entries need a program, a service or remote addresses",
+                rule.name
+            ),
+        ));
+    }
+    for action in op.values.values() {
+        match action {
+            ExecAction::Write(blocked) if firewall::parse_blocked(blocked).is_some() => {}
+            _ => {
+                return Err(DejunkerError::parse(
+                    path_or_url,
+                    format!("rule {}: traffic can only be blocked or allowed", rule.name),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read the traffic direction of an exec entry (inbound or outbound), None if it has none
+fn read_exec_direction(path_or_url: &str, direction_yaml: &Yaml) -> Result<Option<Direction>, DejunkerError> {
+    match direction_yaml {
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        direction_yaml => {
+            let direction = yaml_scalar(direction_yaml).unwrap_or_default();
+            match Direction::from_name(&direction) {
+                Some(direction) => Ok(Some(direction)),
+                None => Err(DejunkerError::parse(
+                    path_or_url,
+                    format!("invalid direction '{}', expected inbound or outbound", direction),
+                )),
+            }
+        }
+    }
+}
+
+/// Read the package scope of an exec entry (user or provisioned), None if it has none
+fn read_exec_scope(path_or_url: &str, scope_yaml: &Yaml) -> Result<Option<PackageScope>, DejunkerError> {
+    match scope_yaml {
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        scope_yaml => {
+            let scope = yaml_scalar(scope_yaml).unwrap_or_default();
+            match PackageScope::from_name(&scope) {
+                Some(scope) => Ok(Some(scope)),
+                None => Err(DejunkerError::parse(
+                    path_or_url,
+                    format!("invalid scope '{}', expected user or provisioned", scope),
+                )),
+            }
+        }
+    }
+}
+
+/// Read the registry view of an exec entry (32, 64 or both), None if it has none
+fn read_exec_view(path_or_url: &str, view_yaml: &Yaml) -> Result<Option<RegistryView>, DejunkerError> {
+    match view_yaml {
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        view_yaml => {
+            let view = yaml_scalar(view_yaml).unwrap_or_default();
+            match RegistryView::from_name(&view) {
+                Some(view) => Ok(Some(view)),
+                None => Err(DejunkerError::parse(
+                    path_or_url,
+                    format!("invalid view '{}', expected 32, 64 or both", view),
+                )),
+            }
+        }
+    }
+}
+
+/// Read the value mappings of an exec entry. Numbers, strings and lists of strings (for
+/// multi_string values) are written, ~ (null) deletes the value. Other actions are mappings
+/// with an action field (delete-value, or delete-key with an optional backup flag).
+fn read_exec_values(
+    path_or_url: &str,
+    rule: &str,
+    values_yaml: &Yaml,
+) -> Result<BTreeMap<String, ExecAction>, DejunkerError> {
+    let mut values = BTreeMap::new();
+    let invalid = |what: String| DejunkerError::parse(path_or_url, format!("rule {}: {}", rule, what));
+
+    for (key, action) in values_yaml.as_hash().into_iter().flatten() {
+        let key = yaml_scalar(key).ok_or_else(|| invalid(format!("invalid value name {:?}", key)))?;
+        let action = match action {
+            Yaml::Null => ExecAction::DeleteValue,
+            Yaml::Hash(_) => match action["action"].as_str() {
+                Some("delete-value") => ExecAction::DeleteValue,
+                Some("delete-key") => ExecAction::DeleteKey {
+                    backup: action["backup"].as_bool().unwrap_or(false),
+                },
+                other => {
+                    return Err(invalid(format!(
+                        "invalid action '{}' for value '{}', expected delete-value or delete-key",
+                        other.unwrap_or_default(),
+                        key
+                    )));
+                }
+            },
+            Yaml::Array(items) => {
+                let items = items
+                    .iter()
+                    .map(yaml_scalar)
+                    .collect::<Option<Vec<String>>>()
+                    .ok_or_else(|| invalid(format!("invalid data for value '{}', list items must be strings", key)))?;
+                ExecAction::Write(items.join("\n"))
+            }
+            action => ExecAction::Write(
+                yaml_scalar(action)
+                    .ok_or_else(|| invalid(format!("invalid data for value '{}', quote it to make it a string", key)))?,
+            ),
+        };
+        values.insert(key, action);
+    }
+    Ok(values)
+}
+
+/// A number or string scalar, as a string
+pub(crate) fn yaml_scalar(yaml: &Yaml) -> Option<String> {
+    match yaml {
+        Yaml::String(value) => Some(value.clone()),
+        Yaml::Integer(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Write rules in the database format (the inverse of read_database)
+///
+/// * rules: the rules to write, in order
+///
+pub fn format_database(rules: &[Rule]) -> String {
+    let mut output = format!("file: {}\nrules:\n", DATABASE_ID);
+
+    for rule in rules {
+        output.push_str(&format!("  - rule: {}\n", yaml_string(&rule.id)));
+        output.push_str(&format!("    arg: {}\n", yaml_string(&rule.name)));
+        output.push_str(&format!("    description: {}\n", yaml_string(&rule.description)));
+        output.push_str(&format!("    admin_required: {}\n", rule.admin_required));
+        output.push_str("    value:\n");
+        output.push_str(&format!("      type: {}\n", yaml_string(&rule.value.value_type)));
+        if !rule.value.choices.is_empty() {
+            output.push_str("      choices:\n");
+            for choice in &rule.value.choices {
+                output.push_str(&format!("        - {}\n", yaml_string(choice)));
+            }
+        }
+        if let Some(min) = rule.value.min {
+            output.push_str(&format!("      min: {}\n", min));
+        }
+        if let Some(max) = rule.value.max {
+            output.push_str(&format!("      max: {}\n", max));
+        }
+        if let Some(pattern) = &rule.value.pattern {
+            output.push_str(&format!("      pattern: {}\n", yaml_string(pattern)));
+        }
+        output.push_str("    exec:\n");
+        for exec in &rule.exec {
+            output.push_str(&format!("      - subsystem: {}\n", yaml_string(&exec.subsystem)));
+            output.push_str(&format!("        path: {}\n", yaml_string(&exec.path)));
+            // only registry entries have a value (which can be the default value, "")
+            if exec.subsystem == "registry" || !exec.value.is_empty() {
+                output.push_str(&format!("        value: {}\n", yaml_string(&exec.value)));
+            }
+            if exec.subsystem == "registry" || !exec.value_type.is_empty() {
+                output.push_str(&format!("        type: {}\n", yaml_string(&exec.value_type)));
+            }
+            if let Some(reversed) = exec.reversed {
+                output.push_str(&format!("        reversed: {}\n", reversed));
+            }
+            if let Some(item) = &exec.item {
+                output.push_str(&format!("        item: {}\n", yaml_string(item)));
+            }
+            if let Some(view) = exec.view {
+                output.push_str(&format!("        view: {}\n", view));
+            }
+            if let Some(stop) = exec.stop {
+                output.push_str(&format!("        stop: {}\n", stop));
+            }
+            if let Some(publisher) = &exec.publisher {
+                output.push_str(&format!("        publisher: {}\n", yaml_string(publisher)));
+            }
+            if let Some(scope) = exec.scope {
+                output.push_str(&format!("        scope: {}\n", scope));
+            }
+            if !exec.hostnames.is_empty() {
+                output.push_str("        hostnames:\n");
+                for hostname in &exec.hostnames {
+                    output.push_str(&format!("          - {}\n", yaml_string(hostname)));
+                }
+            }
+            if let Some(program) = &exec.program {
+                output.push_str(&format!("        program: {}\n", yaml_string(program)));
+            }
+            if let Some(service) = &exec.service {
+                output.push_str(&format!("        service: {}\n", yaml_string(service)));
+            }
+            if !exec.remote_addresses.is_empty() {
+                output.push_str("        remote_addresses:\n");
+                for address in &exec.remote_addresses {
+                    output.push_str(&format!("          - {}\n", yaml_string(address)));
+                }
+            }
+            if let Some(direction) = exec.direction {
+                output.push_str(&format!("        direction: {}\n", direction));
+            }
+            if !exec.values.is_empty() {
+                output.push_str("        values:\n");
+                for (key, action) in &exec.values {
+                    let action = match action {
+                        ExecAction::Write(data) if exec.value_type == "multi_string" && exec.item.is_none() => {
+                            data.split('\n')
+                                .map(|item| format!("\n            - {}", yaml_string(item)))
+                                .collect()
+                        }
+                        // numbers as they are, if they are read back as the same string
+                        ExecAction::Write(data) if data.parse::<i64>().is_ok_and(|n| n.to_string() == *data) => {
+                            format!(" {}", data)
+                        }
+                        ExecAction::Write(data) => format!(" {}", yaml_string(data)),
+                        ExecAction::DeleteValue => " ~".to_string(),
+                        ExecAction::DeleteKey { backup } => {
+                            format!(" {{ action: delete-key, backup: {} }}", backup)
+                        }
+                    };
+                    output.push_str(&format!("          {}:{}\n", yaml_string(key), action));
+                }
+            }
+        }
+        output.push('\n');
+    }
+    output
+}
+
+/// A string as a YAML scalar, quoted only when it would not read back as the same string
+pub(crate) fn yaml_string(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value.trim() == value
+        && !value.starts_with(|c: char| "-?:,[]{}#&*!|>'\"%@`".contains(c))
+        && !value.contains(": ")
+        && !value.contains(" #")
+        && !value.ends_with(':')
+        && value.chars().all(|c| !c.is_control())
+        && !matches!(
+            value.to_lowercase().as_str(),
+            "true" | "false" | "yes" | "no" | "on" | "off" | "null" | "~"
+        )
+        // anything else the parser reads as a number (0x10, .inf, 1e3...) or a boolean
+        && YamlLoader::load_from_str(value).is_ok_and(|docs| docs == [Yaml::String(value.to_string())]);
+
+    if plain {
+        return value.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(name: &str, contents: &str) -> Result<RulesDatabase, DejunkerError> {
+        let path = std::env::temp_dir().join(format!("dejunker-{}-{}.yaml", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        let database = read_database(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        database
+    }
+
+    fn database(values: &str) -> String {
+        format!(
+            "file: {}\nrules:\n  - rule: advertising-id\n    arg: advertising-id\n    value:\n      type: OnOff\n    \
+             exec:\n      - subsystem: registry\n        path: HKCU\\Software\\AdvertisingInfo\n        \
+             value: Enabled\n        values:\n{}",
+            DATABASE_ID, values
+        )
+    }
+
+    #[test]
+    fn value_mappings_are_read() {
+        let database = read(
+            "values",
+            &database("          on: 1\n          off: ~\n          gone: { action: delete-key, backup: true }\n"),
+        )
+        .unwrap();
+        assert_eq!(
+            database.rules["advertising-id"].exec[0].values,
+            BTreeMap::from([
+                ("gone".to_string(), ExecAction::DeleteKey { backup: true }),
+                ("off".to_string(), ExecAction::DeleteValue),
+                ("on".to_string(), ExecAction::Write("1".to_string())),
+            ])
+        );
+    }
+
+    #[test]
+    fn value_mappings_that_cannot_be_read_are_errors() {
+        for (values, message) in [
+            ("          off: { action: remove }\n", "rule advertising-id: invalid action 'remove' for value 'off'"),
+            ("          off: true\n", "rule advertising-id: invalid data for value 'off'"),
+            ("          on: [1, { a: b }]\n", "rule advertising-id: invalid data for value 'on'"),
+        ] {
+            match read("invalid-values", &database(values)) {
+                Err(DejunkerError::Parse { message: error, .. }) => assert!(error.starts_with(message), "{}", error),
+                other => panic!("unexpected result {:?}", other.map(|database| database.rules.len())),
+            }
+        }
+    }
+
+    #[test]
+    fn values_that_do_the_same_are_errors() {
+        for (values, message) in [
+            ("          on: 1\n          off: 01\n", "rule advertising-id: values 'off' and 'on' do the same"),
+            ("          on: ~\n          off: ~\n", "rule advertising-id: values 'off' and 'on' do the same"),
+            (
+                "          on: { action: delete-key }\n          off: { action: delete-key, backup: true }\n",
+                "rule advertising-id: values 'off' and 'on' do the same",
+            ),
+        ] {
+            match read("same-values", &database(values)) {
+                Err(DejunkerError::Parse { message: error, .. }) => assert!(error.starts_with(message), "{}", error),
+                other => panic!("unexpected result {:?}", other.map(|database| database.rules.len())),
+            }
+        }
+        // deleting the value and deleting its key are told apart by whether the key is there
+        let database = database("          on: ~\n          off: { action: delete-key }\n");
+        assert!(read("other-values", &database).is_ok());
+    }
+
+    #[test]
+    fn enum_entries_map_every_choice() {
+        let enum_database = |values: &str| {
+            format!(
+                "file: {}\nrules:\n  - rule: taskbar-alignment\n    arg: taskbar-alignment\n    value:\n      type: enum\n      \
+                 choices: [left, center]\n    exec:\n      - subsystem: registry\n        \
+                 path: HKCU\\Software\\Advanced\n        value: TaskbarAl\n        type: i32\n        values:\n{}",
+                DATABASE_ID, values
+            )
+        };
+
+        let database = read("enum", &enum_database("          left: 0\n          center: 1\n")).unwrap();
+        assert_eq!(database.rules["taskbar-alignment"].exec[0].values.len(), 2);
+
+        match read("enum-unmapped", &enum_database("          left: 0\n")) {
+            Err(DejunkerError::Parse { message, .. }) => assert_eq!(
+                message,
+                "rule taskbar-alignment: the entry for HKCU\\Software\\Advanced has no value for choice 'center'"
+            ),
+            other => panic!("unexpected result {:?}", other.map(|database| database.rules.len())),
+        }
+    }
+
+    #[test]
+    fn unsigned_entries_cannot_take_negative_numbers() {
+        let integer_database = |min: i64| {
+            format!(
+                "file: {}\nrules:\n  - rule: menu-delay\n    arg: menu-delay\n    value:\n      type: integer\n      \
+                 min: {}\n    exec:\n      - subsystem: registry\n        path: HKCU\\Control Panel\\Desktop\n        \
+                 value: MenuShowDelay\n        type: u32\n",
+                DATABASE_ID, min
+            )
+        };
+
+        assert!(read("unsigned", &integer_database(0)).is_ok());
+        match read("unsigned-negative", &integer_database(-1)) {
+            Err(DejunkerError::Parse { message, .. }) => assert_eq!(
+                message,
+                "rule menu-delay: min is -1, but HKCU\\Control Panel\\Desktop -> MenuShowDelay is u32 and cannot be \
+                 negative"
+            ),
+            other => panic!("unexpected result {:?}", other.map(|database| database.rules.len())),
+        }
+    }
+
+    #[test]
+    fn strings_read_back_as_written() {
+        for value in [
+            "plain text", "HKCU\\Software\\Policies", "", " padded", "0x10", "0o17", "12", "-3", "1e3", ".inf",
+            "-.inf", ".NaN", "true", "False", "null", "Null", "~", "yes", "off", "- item", "a: b", "a #b", "#a",
+            "key:", "[a]", "{a}", "*alias", "'single'", "\"double\" \\ back", "line\nbreak", "tab\there",
+            "bell\u{7}", "nul\u{0}", "delete\u{7f}",
+        ] {
+            let docs = YamlLoader::load_from_str(&format!("value: {}", yaml_string(value))).unwrap();
+            assert_eq!(docs[0]["value"], Yaml::String(value.to_string()), "{:?}", yaml_string(value));
+        }
+    }
+
+    #[test]
+    fn strings_are_only_quoted_when_needed() {
+        assert_eq!(yaml_string("plain text"), "plain text");
+        assert_eq!(yaml_string("HKCU\\Software"), "HKCU\\Software");
+        assert_eq!(yaml_string("0x10"), "\"0x10\"");
+        assert_eq!(yaml_string("line\nbreak\u{1b}"), "\"line\\nbreak\\u001b\"");
+    }
+}
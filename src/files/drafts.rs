@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+
+use crate::files::db::{Exec, ExecAction, Rule, Value};
+use crate::files::settings;
+use crate::registry::recorder::Change;
+use crate::registry::{self, RegistryValue};
+
+/// A value assignment taken from a .reg file
+struct Assignment {
+    path: String,
+    name: String,
+    value: Option<RegistryValue>,
+}
+
+/// Draft rules for every value set by a .reg file, one rule per registry key, with the file
+/// taken as the "on" state. The drafts still need a name and a description.
+///
+/// * changes: the contents of the .reg file
+///
+pub fn draft_rules(changes: &[Change]) -> Vec<Rule> {
+    let mut rules: Vec<Rule> = Vec::new();
+
+    for assignment in assignments(changes).into_iter().filter(|a| a.value.is_some()) {
+        let exec = draft_single_exec(&assignment);
+        match rules.last_mut() {
+            Some(rule) if rule.exec[0].path.eq_ignore_ascii_case(&exec.path) => rule.exec.push(exec),
+            _ => rules.push(draft_rule(rules.len() + 1, vec![exec])),
+        }
+    }
+
+    for rule in rules.iter_mut() {
+        rule.admin_required = needs_admin(&rule.exec);
+    }
+    rules
+}
+
+/// A draft rule for the values that differ between two .reg exports, taken before and after
+/// turning a setting on. Values turned from 1 to 0 are marked as reversed, values changed in
+/// any other way get an explicit mapping of on and off.
+///
+/// * before: the .reg export with the setting off
+/// * after: the .reg export with the setting on
+///
+pub fn draft_rule_from_diff(before: &[Change], after: &[Change]) -> Option<Rule> {
+    let before = assignments(before);
+    let after = assignments(after);
+
+    let find = |list: &[Assignment], path: &str, name: &str| -> Option<RegistryValue> {
+        list.iter()
+            .find(|a| a.path.eq_ignore_ascii_case(path) && a.name.eq_ignore_ascii_case(name))
+            .and_then(|a| a.value.clone())
+    };
+
+    let mut exec: Vec<Exec> = Vec::new();
+    for assignment in after.iter() {
+        let previous = find(&before, &assignment.path, &assignment.name);
+        if previous != assignment.value {
+            exec.push(draft_diff_exec(assignment, previous.as_ref()));
+        }
+    }
+
+    // values that only exist before
+    for assignment in before.iter() {
+        if !after
+            .iter()
+            .any(|a| a.path.eq_ignore_ascii_case(&assignment.path) && a.name.eq_ignore_ascii_case(&assignment.name))
+        {
+            let removed = Assignment {
+                path: assignment.path.clone(),
+                name: assignment.name.clone(),
+                value: None,
+            };
+            exec.push(draft_diff_exec(&removed, assignment.value.as_ref()));
+        }
+    }
+
+    if exec.is_empty() {
+        return None;
+    }
+    let mut rule = draft_rule(1, exec);
+    rule.admin_required = needs_admin(&rule.exec);
+    Some(rule)
+}
+
+/// The final state of every value the .reg file mentions (None if it ends up deleted), in
+/// the order they first appear
+fn assignments(changes: &[Change]) -> Vec<Assignment> {
+    let mut result: Vec<Assignment> = Vec::new();
+    let mut index: HashMap<(String, String), usize> = HashMap::new();
+
+    for change in changes {
+        let (path, name, value) = match change {
+            Change::SetValue { path, name, value, .. } => (path, name, Some(value.clone())),
+            Change::DeleteValue { path, name, .. } => (path, name, None),
+            Change::DeleteKey { .. } | Change::CreateKey { .. } => continue,
+        };
+
+        let key = (path.to_lowercase(), name.to_lowercase());
+        match index.get(&key) {
+            Some(&position) => result[position].value = value,
+            None => {
+                index.insert(key, result.len());
+                result.push(Assignment {
+                    path: path.clone(),
+                    name: name.clone(),
+                    value,
+                });
+            }
+        }
+    }
+    result
+}
+
+fn draft_rule(number: usize, exec: Vec<Exec>) -> Rule {
+    let id = format!("draft-{}", number);
+    Rule {
+        id: id.clone(),
+        name: id,
+        description: "TODO".to_string(),
+        admin_required: false,
+        value: Value {
+            value_type: "OnOff".to_string(),
+            ..Value::default()
+        },
+        exec,
+    }
+}
+
+/// Exec entry for a value. When the previous value is known, an on value of zero over a
+/// non-zero previous value means the exec entry is reversed.
+fn draft_exec(assignment: &Assignment, previous: Option<&RegistryValue>) -> Exec {
+    let data = assignment.value.as_ref().or(previous);
+
+    let reversed = match (assignment.value.as_ref(), previous) {
+        (Some(RegistryValue::Dword(0)) | None, Some(RegistryValue::Dword(p))) if *p != 0 => Some(true),
+        _ => None,
+    };
+
+    Exec {
+        subsystem: "registry".to_string(),
+        path: assignment.path.clone(),
+        value: assignment.name.clone(),
+        value_type: data.map(exec_type).unwrap_or("i32").to_string(),
+        reversed,
+        ..Exec::default()
+    }
+}
+
+/// Exec entry for a value of a single .reg file. Anything but a 0/1 DWORD (0 is drafted as
+/// reversed) maps on to the exported data, off is left for the author of the rule to fill in.
+fn draft_single_exec(assignment: &Assignment) -> Exec {
+    let mut exec = draft_exec(assignment, None);
+
+    match assignment.value.as_ref() {
+        Some(RegistryValue::Dword(1)) | None => {}
+        Some(RegistryValue::Dword(0)) => exec.reversed = Some(true),
+        Some(value) => {
+            if let Ok(Some(data)) = settings::exec_data(&exec, value) {
+                exec.values.insert("on".to_string(), ExecAction::Write(data));
+            }
+        }
+    }
+    exec
+}
+
+/// Exec entry for a value that differs between the off and on exports. Anything but a 0/1
+/// toggle (where a missing value counts as 0) maps on and off to the exported data.
+fn draft_diff_exec(assignment: &Assignment, previous: Option<&RegistryValue>) -> Exec {
+    let mut exec = draft_exec(assignment, previous);
+
+    let toggle = matches!(
+        (assignment.value.as_ref(), previous),
+        (Some(RegistryValue::Dword(1)), None | Some(RegistryValue::Dword(0)))
+            | (None | Some(RegistryValue::Dword(0)), Some(RegistryValue::Dword(1)))
+    );
+    if !toggle {
+        exec.reversed = None;
+        for (key, value) in [("on", assignment.value.as_ref()), ("off", previous)] {
+            let action = match value {
+                None => ExecAction::DeleteValue,
+                Some(value) => match settings::exec_data(&exec, value) {
+                    Ok(Some(data)) => ExecAction::Write(data),
+                    _ => continue,
+                },
+            };
+            exec.values.insert(key.to_string(), action);
+        }
+    }
+    exec
+}
+
+/// The exec type (as used in the database) for a registry value
+fn exec_type(value: &RegistryValue) -> &'static str {
+    match value {
+        RegistryValue::Dword(_) => "i32",
+        RegistryValue::Qword(_) => "u64",
+        RegistryValue::String(_) => "string",
+        RegistryValue::ExpandString(_) => "expand_string",
+        RegistryValue::MultiString(_) => "multi_string",
+        RegistryValue::Binary(_) => "binary",
+        RegistryValue::Other { .. } => "unknown",
+    }
+}
+
+/// Everything outside of HKEY_CURRENT_USER needs admin rights to write, as do policies
+fn needs_admin(exec: &[Exec]) -> bool {
+    exec.iter().any(|op| {
+        let is_user = matches!(
+            registry::get_path_components(&op.path),
+            Ok((registry::Hive::CurrentUser, _))
+        );
+        !is_user || op.path.to_lowercase().contains("\\policies\\")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::regfile::parse_reg_file;
+    use crate::registry::{MemoryRegistry, RegistryBackend};
+    use crate::subsystems::Subsystems;
+    use std::collections::BTreeMap;
+
+    const KEY: &str = "HKEY_CURRENT_USER\\Software\\Microsoft\\Windows\\CurrentVersion\\Search";
+
+    #[test]
+    fn drafts_write_the_data_of_the_file_for_on() {
+        let contents = format!(
+            "Windows Registry Editor Version 5.00\r\n\r\n[{}]\r\n\"Enabled\"=dword:00000001\r\n\
+             \"Suggestions\"=dword:00000000\r\n\"Mode\"=dword:00000003\r\n\"Engine\"=\"Bing\"\r\n",
+            KEY
+        );
+        let rules = draft_rules(&parse_reg_file("search.reg", &contents).unwrap());
+        assert_eq!(rules.len(), 1);
+
+        let exec: Vec<(&str, Option<bool>, &BTreeMap<String, ExecAction>)> =
+            rules[0].exec.iter().map(|op| (op.value.as_str(), op.reversed, &op.values)).collect();
+        let empty = BTreeMap::new();
+        let on = |data: &str| BTreeMap::from([("on".to_string(), ExecAction::Write(data.to_string()))]);
+        assert_eq!(
+            exec,
+            vec![
+                ("Enabled", None, &empty),
+                ("Suggestions", Some(true), &empty),
+                ("Mode", None, &on("3")),
+                ("Engine", None, &on("Bing")),
+            ]
+        );
+
+        // setting the draft on writes back what the file has
+        let rules = HashMap::from([("draft-1".to_string(), rules[0].clone())]);
+        let mut registry = MemoryRegistry::new();
+        settings::execute_rule(&mut registry, &mut Subsystems::none(), &rules, "draft-1", "on", false).unwrap();
+        for (name, value) in [
+            ("Enabled", RegistryValue::Dword(1)),
+            ("Suggestions", RegistryValue::Dword(0)),
+            ("Mode", RegistryValue::Dword(3)),
+            ("Engine", RegistryValue::String("Bing".to_string())),
+        ] {
+            assert_eq!(registry.get_value(KEY, name).unwrap(), Some(value));
+        }
+    }
+}
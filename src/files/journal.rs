@@ -0,0 +1,634 @@
+use log::{debug, warn};
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use yaml_rust2::YamlLoader;
+
+use crate::error::DejunkerError;
+use crate::files::db::yaml_string;
+use crate::registry::recorder::Change;
+use crate::registry::{get_path_components, Hive, RegistryBackend, RegistryValue};
+use crate::services::recorder::ServiceChange;
+use crate::services::StartType;
+use crate::subsystems::{SubsystemChange, Subsystems};
+use crate::tasks::enabled_name;
+use crate::tasks::recorder::TaskChange;
+use crate::utils::{from_hex, to_hex};
+
+pub const FILE_MARKER: &str = "redsigil.dfckr.journal.v1";
+
+// the kinds of the entries that are not values
+const KIND_KEY: &str = "key";
+const KIND_SERVICE: &str = "service";
+const KIND_TASK: &str = "task";
+
+/// Something an apply changed, with what it was before
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum JournalEntry {
+    /// A registry value, None if it did not exist
+    Value {
+        path: String,
+        name: String,
+        previous: Option<RegistryValue>,
+    },
+    /// A registry key that did not exist
+    Key { path: String },
+    /// The start type of a service
+    Service { name: String, previous: StartType },
+    /// Whether a scheduled task was enabled
+    Task { path: String, previous: bool },
+}
+
+impl JournalEntry {
+    /// The registry path of the entry, None if it is not in the registry
+    pub fn registry_path(&self) -> Option<&str> {
+        match self {
+            JournalEntry::Value { path, .. } | JournalEntry::Key { path } => Some(path),
+            JournalEntry::Service { .. } | JournalEntry::Task { .. } => None,
+        }
+    }
+}
+
+/// A record of the registry values (and service start types and tasks) an apply changed, and
+/// what they were before, in the order they were changed
+#[derive(Debug, Clone)]
+pub struct Journal {
+    pub created: String,
+    pub entries: Vec<JournalEntry>,
+}
+
+impl Journal {
+    /// Journal the changes made through a RecordingRegistry, then those made through
+    /// RecordingSubsystems. A deleted key is journaled as the values it had, so undoing the
+    /// delete writes them back, a created key as a key entry, so undoing deletes it. Stopped
+    /// services, packages, the hosts file and firewall rules are not journaled.
+    pub fn from_changes(changes: &[Change], subsystem_changes: &[SubsystemChange]) -> Journal {
+        let subsystem_entries = subsystem_changes.iter().filter_map(|change| match change {
+            SubsystemChange::Service(ServiceChange::SetStartType { name, previous, .. }) => {
+                Some(JournalEntry::Service {
+                    name: name.clone(),
+                    previous: *previous,
+                })
+            }
+            SubsystemChange::Task(TaskChange::SetEnabled { path, previous, .. }) => Some(JournalEntry::Task {
+                path: path.clone(),
+                previous: *previous,
+            }),
+            _ => None,
+        });
+        let entries = changes
+            .iter()
+            .flat_map(|change| match change {
+                Change::SetValue {
+                    path,
+                    name,
+                    previous,
+                    ..
+                }
+                | Change::DeleteValue {
+                    path,
+                    name,
+                    previous,
+                } => vec![JournalEntry::Value {
+                    path: path.clone(),
+                    name: name.clone(),
+                    previous: previous.clone(),
+                }],
+                Change::DeleteKey { previous, .. } => previous
+                    .iter()
+                    .map(|value| JournalEntry::Value {
+                        path: value.path.clone(),
+                        name: value.name.clone(),
+                        previous: Some(value.value.clone()),
+                    })
+                    .collect(),
+                Change::CreateKey { path } => vec![JournalEntry::Key { path: path.clone() }],
+            })
+            .chain(subsystem_entries)
+            .collect();
+
+        Journal {
+            created: format_timestamp(SystemTime::now(), true),
+            entries,
+        }
+    }
+
+    /// The entries of one user (those at its key of HKEY_USERS), and the machine-wide ones
+    /// (those outside of HKEY_USERS, services and tasks) if asked for. To undo a journal one
+    /// user at a time (see users::for_each_user), without undoing the rest more than once.
+    ///
+    /// * key: the key of HKEY_USERS of the user (usually a SID)
+    /// * machine_wide: whether to keep the machine-wide entries
+    ///
+    pub fn of_user(&self, key: &str, machine_wide: bool) -> Result<Journal, DejunkerError> {
+        let mut entries = Vec::new();
+        for entry in &self.entries {
+            let user = entry.registry_path().map(user_key).transpose()?.flatten();
+            let keep = match user {
+                Some(user) => user.eq_ignore_ascii_case(key),
+                None => machine_wide,
+            };
+            if keep {
+                entries.push(entry.clone());
+            }
+        }
+        Ok(Journal {
+            created: self.created.clone(),
+            entries,
+        })
+    }
+}
+
+/// Write a journal to a new, timestamped file in a directory (created if needed)
+///
+/// * dir: the directory to write the journal to
+/// * journal: the journal to write
+///
+/// Returns the path of the journal file
+pub fn write_journal(dir: &Path, journal: &Journal) -> Result<PathBuf, DejunkerError> {
+    fs::create_dir_all(dir).map_err(DejunkerError::io(dir))?;
+
+    let stamp = format_timestamp(SystemTime::now(), false);
+    for attempt in 0.. {
+        let name = match attempt {
+            0 => format!("journal-{}.yaml", stamp),
+            n => format!("journal-{}-{}.yaml", stamp, n),
+        };
+        let path = dir.join(name);
+
+        // never overwrite an earlier journal
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(DejunkerError::io(&path)(e)),
+        };
+        file.write_all(format_journal(journal).as_bytes())
+            .map_err(DejunkerError::io(&path))?;
+        return Ok(path);
+    }
+    unreachable!()
+}
+
+/// The directory journals are written to unless another one is given, the same for every
+/// user and working directory so `--undo last` finds them
+pub fn default_dir() -> PathBuf {
+    if cfg!(windows) {
+        let root = std::env::var("ProgramData").unwrap_or_else(|_| "C:\\ProgramData".to_string());
+        PathBuf::from(root).join("dejunker\\journal")
+    } else {
+        PathBuf::from("/var/lib/dejunker/journal")
+    }
+}
+
+/// The newest journal written to a directory
+pub fn latest_journal(dir: &Path) -> Result<PathBuf, DejunkerError> {
+    let mut latest: Option<((String, u32), PathBuf)> = None;
+    for entry in fs::read_dir(dir).map_err(DejunkerError::io(dir))? {
+        let path = entry.map_err(DejunkerError::io(dir))?.path();
+        let order = path.file_name().and_then(|name| name.to_str()).and_then(journal_order);
+        if let Some(order) = order {
+            if latest.as_ref().is_none_or(|(newest, _)| order > *newest) {
+                latest = Some((order, path));
+            }
+        }
+    }
+    latest
+        .map(|(_, path)| path)
+        .ok_or_else(|| DejunkerError::io(dir)(io::Error::new(ErrorKind::NotFound, "no journal found")))
+}
+
+/// The timestamp and attempt of a journal file name as write_journal makes it, None for other
+/// files
+fn journal_order(name: &str) -> Option<(String, u32)> {
+    let stem = name.strip_prefix("journal-")?.strip_suffix(".yaml")?;
+    match stem.split_once('-') {
+        Some((stamp, attempt)) => Some((stamp.to_string(), attempt.parse().ok()?)),
+        None => Some((stem.to_string(), 0)),
+    }
+}
+
+/// Render a journal as YAML. Values are stored as their registry type and raw data, so they
+/// can be restored exactly. Entries other than values have a kind.
+pub fn format_journal(journal: &Journal) -> String {
+    let mut output = format!("file: {}\ncreated: {}\nentries:\n", FILE_MARKER, journal.created);
+
+    for entry in &journal.entries {
+        match entry {
+            JournalEntry::Value { path, name, previous } => {
+                output.push_str(&format!("  - path: {}\n", yaml_string(path)));
+                output.push_str(&format!("    value: {}\n", yaml_string(name)));
+                match previous {
+                    Some(value) => {
+                        output.push_str("    existed: true\n");
+                        output.push_str(&format!("    type: {}\n", value.kind()));
+                        output.push_str(&format!("    data: \"{}\"\n", to_hex(&value.to_raw())));
+                    }
+                    None => output.push_str("    existed: false\n"),
+                }
+            }
+            JournalEntry::Key { path } => {
+                output.push_str(&format!("  - kind: {}\n", KIND_KEY));
+                output.push_str(&format!("    path: {}\n", yaml_string(path)));
+            }
+            JournalEntry::Service { name, previous } => {
+                output.push_str(&format!("  - kind: {}\n", KIND_SERVICE));
+                output.push_str(&format!("    path: {}\n", yaml_string(name)));
+                output.push_str(&format!("    start_type: {}\n", previous.name()));
+            }
+            JournalEntry::Task { path, previous } => {
+                output.push_str(&format!("  - kind: {}\n", KIND_TASK));
+                output.push_str(&format!("    path: {}\n", yaml_string(path)));
+                output.push_str(&format!("    enabled: {}\n", previous));
+            }
+        }
+    }
+    output
+}
+
+/// Read a journal file
+///
+/// * path: the journal file
+///
+pub fn read_journal(path: &str) -> Result<Journal, DejunkerError> {
+    let contents = fs::read_to_string(path).map_err(DejunkerError::io(path))?;
+    parse_journal(path, &contents)
+}
+
+/// Parse the contents of a journal file, `path` is only used in errors
+pub fn parse_journal(path: &str, contents: &str) -> Result<Journal, DejunkerError> {
+    let docs = YamlLoader::load_from_str(contents).map_err(|e| DejunkerError::parse(path, e.to_string()))?;
+    let doc = docs
+        .first()
+        .ok_or_else(|| DejunkerError::parse(path, "the file is empty"))?;
+
+    let file = doc["file"].as_str().unwrap_or("");
+    if file != FILE_MARKER {
+        return Err(DejunkerError::parse(
+            path,
+            format!("not a journal (file type is '{}', expected '{}')", file, FILE_MARKER),
+        ));
+    }
+
+    let mut entries = Vec::new();
+    for (index, entry) in doc["entries"].as_vec().unwrap_or(&vec![]).iter().enumerate() {
+        let invalid = |field: &str| DejunkerError::parse(path, format!("entry {}: invalid '{}'", index + 1, field));
+        let key_path = entry["path"].as_str().ok_or_else(|| invalid("path"))?.to_string();
+
+        match entry["kind"].as_str() {
+            None => {
+                let previous = match entry["existed"].as_bool().ok_or_else(|| invalid("existed"))? {
+                    true => {
+                        let kind = entry["type"].as_i64().and_then(|kind| u32::try_from(kind).ok());
+                        let data = entry["data"].as_str().and_then(from_hex);
+                        Some(RegistryValue::from_raw(
+                            kind.ok_or_else(|| invalid("type"))?,
+                            &data.ok_or_else(|| invalid("data"))?,
+                        ))
+                    }
+                    false => None,
+                };
+                entries.push(JournalEntry::Value {
+                    path: key_path,
+                    name: entry["value"].as_str().ok_or_else(|| invalid("value"))?.to_string(),
+                    previous,
+                });
+            }
+            Some(KIND_KEY) => entries.push(JournalEntry::Key { path: key_path }),
+            Some(KIND_SERVICE) => {
+                let start_type = entry["start_type"].as_str().and_then(start_type_from_name);
+                entries.push(JournalEntry::Service {
+                    name: key_path,
+                    previous: start_type.ok_or_else(|| invalid("start_type"))?,
+                });
+            }
+            Some(KIND_TASK) => entries.push(JournalEntry::Task {
+                path: key_path,
+                previous: entry["enabled"].as_bool().ok_or_else(|| invalid("enabled"))?,
+            }),
+            Some(_) => return Err(invalid("kind")),
+        }
+    }
+
+    Ok(Journal {
+        created: doc["created"].as_str().unwrap_or("").to_string(),
+        entries,
+    })
+}
+
+/// Put every value of a journal back the way it was before the journaled apply. Values and
+/// keys that did not exist are deleted, and the values of deleted keys are written back
+/// (recreating the keys, except for the ones that had no values). Services get their start
+/// type back, and tasks are enabled or disabled again.
+///
+/// * registry: the registry the journaled apply was made to
+/// * subsystems: the services and tasks the journaled apply changed
+/// * journal: the journal to restore
+///
+pub fn undo_journal(
+    registry: &mut dyn RegistryBackend,
+    subsystems: &mut Subsystems,
+    journal: &Journal,
+) -> Result<(), DejunkerError> {
+    let mut unloaded = BTreeSet::new();
+    let mut unreachable = BTreeSet::new();
+
+    // newest first, so a value changed several times ends up with its oldest value
+    for entry in journal.entries.iter().rev() {
+        // values of other users (see --target-user) can only be restored while their hive is loaded
+        if let Some(user) = entry.registry_path().map(user_key).transpose()?.flatten() {
+            if !registry.key_exists(&format!("{}\\{}", Hive::Users, user))? {
+                unloaded.insert(user);
+                continue;
+            }
+        }
+
+        match entry {
+            JournalEntry::Value {
+                path,
+                name,
+                previous: Some(value),
+            } => {
+                debug!("Restoring {} -> {} to {}", path, name, value);
+                registry.set_value(path, name, value)?;
+            }
+            JournalEntry::Value {
+                path,
+                name,
+                previous: None,
+            } => {
+                debug!("Deleting {} -> {}, it did not exist", path, name);
+                registry.delete_value(path, name)?;
+            }
+            JournalEntry::Key { path } => {
+                debug!("Deleting key {}, it did not exist", path);
+                registry.delete_key(path)?;
+            }
+            JournalEntry::Service { name, previous } => match subsystems.services.as_deref_mut() {
+                Some(services) => {
+                    debug!("Restoring the start type of service {} to {}", name, previous);
+                    services.set_start_type(name, *previous)?;
+                }
+                None => {
+                    unreachable.insert("services");
+                }
+            },
+            JournalEntry::Task { path, previous } => match subsystems.tasks.as_deref_mut() {
+                Some(tasks) => {
+                    debug!("Restoring task {} to {}", path, enabled_name(*previous));
+                    tasks.set_enabled(path, *previous)?;
+                }
+                None => {
+                    unreachable.insert("scheduled tasks");
+                }
+            },
+        }
+    }
+
+    for subsystem in unreachable {
+        warn!("The {} of the journal were not restored, they are not accessible", subsystem);
+    }
+
+    for user in unloaded {
+        warn!(
+            "The values of user {} were not restored, the registry of the user is not loaded (use --target-user)",
+            user
+        );
+    }
+    Ok(())
+}
+
+/// Parse a start type as journaled, including those of drivers (see StartType::name)
+fn start_type_from_name(name: &str) -> Option<StartType> {
+    match name {
+        "boot" => Some(StartType::Boot),
+        "system" => Some(StartType::System),
+        _ => StartType::from_name(name),
+    }
+}
+
+/// The key of HKEY_USERS a path is in (e.g. the SID of a user), None for paths of other hives
+fn user_key(path: &str) -> Result<Option<String>, DejunkerError> {
+    Ok(match get_path_components(path)? {
+        (Hive::Users, sub_path) => sub_path.split('\\').next().filter(|key| !key.is_empty()).map(|key| key.to_string()),
+        _ => None,
+    })
+}
+
+/// A UTC timestamp, either ISO 8601 (2024-05-01T12:00:00Z) or compact for file names
+/// (20240501T120000Z)
+fn format_timestamp(time: SystemTime, extended: bool) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, time_of_day) = (seconds / 86400, seconds % 86400);
+    let (hour, minute, second) = (time_of_day / 3600, time_of_day / 60 % 60, time_of_day % 60);
+
+    // civil date from days since 1970-01-01 (proleptic Gregorian calendar)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    if extended {
+        format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second)
+    } else {
+        format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", year, month, day, hour, minute, second)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::db::{Exec, ExecAction, Rule};
+    use crate::files::settings::execute_rule;
+    use crate::registry::recorder::RecordingRegistry;
+    use crate::registry::{backup_path, MemoryRegistry};
+    use crate::subsystems::Subsystems;
+    use crate::services::ServiceManager;
+    use crate::subsystems::RecordingSubsystems;
+    use crate::tasks::TaskScheduler;
+    use crate::test_support::{registry_exec, rule, rules, Machine};
+    use std::collections::{BTreeMap, HashMap};
+
+    const KEY: &str = "HKCU\\Software\\Policies\\Microsoft\\Windows\\Explorer";
+
+    fn delete_key_rule() -> HashMap<String, Rule> {
+        let exec = Exec {
+            subsystem: "registry".to_string(),
+            path: KEY.to_string(),
+            values: BTreeMap::from([("off".to_string(), ExecAction::DeleteKey { backup: true })]),
+            ..Exec::default()
+        };
+        rules([rule("explorer-policies", "OnOff", vec![exec])])
+    }
+
+    #[test]
+    fn undo_deletes_created_keys() {
+        let rules = rules([rule(
+            "search-suggestions",
+            "OnOff",
+            vec![registry_exec(&format!("{}\\Search", KEY), "DisableSearchBoxSuggestions", "u32")],
+        )]);
+        let mut registry = MemoryRegistry::new();
+        registry.set_u32_value("HKCU\\Software", "Existing", 1).unwrap();
+
+        let mut recorder = RecordingRegistry::new(&mut registry, true);
+        execute_rule(&mut recorder, &mut Subsystems::none(), &rules, "search-suggestions", "on", false).unwrap();
+        let journal = Journal::from_changes(recorder.changes(), &[]);
+        assert_eq!(
+            journal.entries[0],
+            JournalEntry::Key {
+                path: "HKCU\\Software\\Policies".to_string()
+            }
+        );
+
+        let journal = parse_journal("journal.yaml", &format_journal(&journal)).unwrap();
+        undo_journal(&mut registry, &mut Subsystems::none(), &journal).unwrap();
+        assert!(!registry.key_exists("HKCU\\Software\\Policies").unwrap());
+        assert_eq!(registry.get_value("HKCU\\Software", "Existing").unwrap(), Some(RegistryValue::Dword(1)));
+    }
+
+    #[test]
+    fn undo_restores_services_and_tasks() {
+        const TASK: &str = "\\Microsoft\\Windows\\Autochk\\Proxy";
+        let rules = rules([
+            rule(
+                "diagtrack",
+                "OnOff",
+                vec![Exec {
+                    subsystem: "service".to_string(),
+                    path: "DiagTrack".to_string(),
+                    values: BTreeMap::from([
+                        ("on".to_string(), ExecAction::Write("automatic".to_string())),
+                        ("off".to_string(), ExecAction::Write("disabled".to_string())),
+                    ]),
+                    ..Exec::default()
+                }],
+            ),
+            rule(
+                "autochk",
+                "OnOff",
+                vec![Exec {
+                    subsystem: "scheduled_task".to_string(),
+                    path: TASK.to_string(),
+                    ..Exec::default()
+                }],
+            ),
+        ]);
+        let mut machine = Machine::new();
+        machine.services.insert("DiagTrack", StartType::Delayed, false);
+        machine.tasks.insert(TASK, true);
+
+        let (registry, mut subsystems) = machine.split();
+        let mut recording = RecordingSubsystems::new(&mut subsystems, true);
+        execute_rule(registry, &mut recording.subsystems(), &rules, "diagtrack", "off", false).unwrap();
+        execute_rule(registry, &mut recording.subsystems(), &rules, "autochk", "off", false).unwrap();
+        let journal = Journal::from_changes(&[], &recording.into_changes());
+        assert_eq!(
+            journal.entries,
+            [
+                JournalEntry::Service {
+                    name: "DiagTrack".to_string(),
+                    previous: StartType::Delayed
+                },
+                JournalEntry::Task {
+                    path: TASK.to_string(),
+                    previous: true
+                }
+            ]
+        );
+
+        let journal = parse_journal("journal.yaml", &format_journal(&journal)).unwrap();
+        let (registry, mut subsystems) = machine.split();
+        undo_journal(registry, &mut subsystems, &journal).unwrap();
+        assert_eq!(machine.services.query("DiagTrack").unwrap().unwrap().start_type, StartType::Delayed);
+        assert!(machine.tasks.tasks("\\Microsoft\\Windows\\Autochk").unwrap()[0].enabled);
+    }
+
+    #[test]
+    fn journals_are_undone_one_user_at_a_time() {
+        let value = |path: &str| JournalEntry::Value {
+            path: path.to_string(),
+            name: "Enabled".to_string(),
+            previous: None,
+        };
+        let journal = Journal {
+            created: String::new(),
+            entries: vec![
+                value("HKEY_USERS\\S-1-5-21-1000-1001\\Software"),
+                value("HKEY_USERS\\S-1-5-21-1000-1002\\Software"),
+                value("HKLM\\SOFTWARE\\Policies"),
+                JournalEntry::Task {
+                    path: "\\Microsoft\\Windows\\Autochk\\Proxy".to_string(),
+                    previous: true,
+                },
+            ],
+        };
+
+        let first = journal.of_user("S-1-5-21-1000-1002", true).unwrap();
+        assert_eq!(first.entries, journal.entries[1..]);
+        let later = journal.of_user("s-1-5-21-1000-1001", false).unwrap();
+        assert_eq!(later.entries, journal.entries[..1]);
+    }
+
+    #[test]
+    fn the_latest_journal_is_the_newest_attempt_of_the_newest_run() {
+        let dir = std::env::temp_dir().join(format!("dejunker-journals-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "journal-20260101T100000Z.yaml",
+            "journal-20260102T100000Z.yaml",
+            "journal-20260102T100000Z-2.yaml",
+            "journal-20260102T100000Z-10.yaml",
+            "notes.yaml",
+        ] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let latest = latest_journal(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(latest.unwrap(), dir.join("journal-20260102T100000Z-10.yaml"));
+    }
+
+    #[test]
+    fn undo_recreates_deleted_keys_and_keeps_the_backup() {
+        let mut registry = MemoryRegistry::new();
+        registry.set_u32_value(KEY, "DisableSearchBoxSuggestions", 1).unwrap();
+        registry.set_string_value(&format!("{}\\Sub", KEY), "Name", "data").unwrap();
+
+        let mut recorder = RecordingRegistry::new(&mut registry, true);
+        execute_rule(&mut recorder, &mut Subsystems::none(), &delete_key_rule(), "explorer-policies", "off", false)
+            .unwrap();
+        let journal = Journal::from_changes(recorder.changes(), &[]);
+        assert!(!registry.key_exists(KEY).unwrap());
+
+        // the journal survives being written and read back
+        let dir = std::env::temp_dir().join(format!("dejunker-journal-{}", std::process::id()));
+        let path = write_journal(&dir, &journal).unwrap();
+        let journal = read_journal(path.to_str().unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        undo_journal(&mut registry, &mut Subsystems::none(), &journal).unwrap();
+        assert_eq!(
+            registry.get_value(KEY, "DisableSearchBoxSuggestions").unwrap(),
+            Some(RegistryValue::Dword(1))
+        );
+        assert_eq!(
+            registry.get_value(&format!("{}\\Sub", KEY), "Name").unwrap(),
+            Some(RegistryValue::String("data".to_string()))
+        );
+        let backup = backup_path(KEY).unwrap();
+        assert_eq!(
+            registry.get_value(&backup, "DisableSearchBoxSuggestions").unwrap(),
+            Some(RegistryValue::Dword(1))
+        );
+        assert_eq!(
+            registry.get_value(&format!("{}\\Sub", backup), "Name").unwrap(),
+            Some(RegistryValue::String("data".to_string()))
+        );
+    }
+}
@@ -1,2 +1,31 @@
+use reqwest::blocking::get;
+use std::fs::read_to_string;
+
+use crate::error::DejunkerError;
+
+pub mod admx;
 pub mod db;
+pub mod drafts;
+pub mod journal;
+pub mod pol;
+pub mod regfile;
 pub mod settings;
+
+/// Read the contents of a local file, or of a file served over http(s)
+///
+/// * path_or_url: the path or the URL of the file
+///
+pub fn read_file_or_url(path_or_url: &str) -> Result<String, DejunkerError> {
+    if path_or_url.starts_with("http://") || path_or_url.starts_with("https://") {
+        let download_error = |e: reqwest::Error| DejunkerError::Download {
+            url: path_or_url.to_string(),
+            message: e.to_string(),
+        };
+        get(path_or_url)
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .map_err(download_error)
+    } else {
+        read_to_string(path_or_url).map_err(DejunkerError::io(path_or_url))
+    }
+}
@@ -0,0 +1,185 @@
+use crate::error::DejunkerError;
+use crate::registry::recorder::Change;
+use crate::registry::{self, Hive, RegistryValue};
+
+/// Signature ("PReg") and version at the start of every Registry.pol file
+const POL_SIGNATURE: u32 = 0x67655250;
+const POL_VERSION: u32 = 1;
+
+// special value names, interpreted by the group policy registry extension
+const DELETE_VALUE_PREFIX: &str = "**del.";
+const DELETE_KEYS: &str = "**DeleteKeys";
+
+/// The two sides of a group policy object, each with its own Registry.pol
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PolicyScope {
+    User,
+    Machine,
+}
+
+impl PolicyScope {
+    /// The scope a hive is deployed through, if any
+    pub fn from_hive(hive: Hive) -> Option<PolicyScope> {
+        match hive {
+            Hive::CurrentUser => Some(PolicyScope::User),
+            Hive::LocalMachine => Some(PolicyScope::Machine),
+            _ => None,
+        }
+    }
+
+    /// Name of the GPO directory holding the Registry.pol of this scope
+    pub fn directory(&self) -> &'static str {
+        match self {
+            PolicyScope::User => "User",
+            PolicyScope::Machine => "Machine",
+        }
+    }
+}
+
+/// Render the registry changes belonging to one scope as a Registry.pol (PReg) file. Changes
+/// to other scopes are left out, the order of the changes is otherwise preserved.
+///
+/// * changes: the changes to render
+/// * scope: the side of the GPO to render
+///
+pub fn format_pol_file(changes: &[Change], scope: PolicyScope) -> Result<Vec<u8>, DejunkerError> {
+    let mut output = Vec::new();
+    output.extend_from_slice(&POL_SIGNATURE.to_le_bytes());
+    output.extend_from_slice(&POL_VERSION.to_le_bytes());
+
+    for change in changes {
+        let (hive, key) = registry::get_path_components(change.path())?;
+        if PolicyScope::from_hive(hive) != Some(scope) {
+            continue;
+        }
+
+        match change {
+            Change::SetValue { name, value, .. } => {
+                write_entry(&mut output, &key, name, value);
+            }
+            Change::DeleteValue { name, .. } => {
+                let name = format!("{}{}", DELETE_VALUE_PREFIX, name);
+                write_entry(&mut output, &key, &name, &RegistryValue::String(String::new()));
+            }
+            // keys are deleted through their parent
+            Change::DeleteKey { .. } => {
+                let (parent, child) = key
+                    .rsplit_once('\\')
+                    .ok_or_else(|| {
+                        DejunkerError::registry(format!("Cannot delete top level key {} through a policy", change.path()))
+                    })?;
+                write_entry(&mut output, parent, DELETE_KEYS, &RegistryValue::String(child.to_string()));
+            }
+            // keys are created by writing values below them
+            Change::CreateKey { .. } => {}
+        }
+    }
+
+    Ok(output)
+}
+
+/// The scope a change is deployed through, None if it cannot be deployed through a group
+/// policy object
+pub fn change_scope(change: &Change) -> Option<PolicyScope> {
+    let (hive, _) = registry::get_path_components(change.path()).ok()?;
+    PolicyScope::from_hive(hive)
+}
+
+/// A single [key;value;type;size;data] entry. Everything but the type, size and data is
+/// NUL terminated UTF-16LE.
+fn write_entry(output: &mut Vec<u8>, key: &str, name: &str, value: &RegistryValue) {
+    let data = value.to_raw();
+
+    push_char(output, '[');
+    output.extend_from_slice(&registry::encode_utf16(key));
+    push_char(output, ';');
+    output.extend_from_slice(&registry::encode_utf16(name));
+    push_char(output, ';');
+    output.extend_from_slice(&value.kind().to_le_bytes());
+    push_char(output, ';');
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    push_char(output, ';');
+    output.extend_from_slice(&data);
+    push_char(output, ']');
+}
+
+fn push_char(output: &mut Vec<u8>, c: char) {
+    output.extend_from_slice(&(c as u16).to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "HKLM\\SOFTWARE\\Policies\\dejunker";
+
+    /// NUL terminated UTF-16LE, as every string of the file
+    fn wide(text: &str) -> Vec<u8> {
+        text.encode_utf16().chain([0]).flat_map(|c| c.to_le_bytes()).collect()
+    }
+
+    /// A [key;value;type;size;data] record
+    fn record(key: &str, name: &str, kind: u32, data: &[u8]) -> Vec<u8> {
+        [
+            b"[\0".to_vec(),
+            wide(key),
+            b";\0".to_vec(),
+            wide(name),
+            b";\0".to_vec(),
+            kind.to_le_bytes().to_vec(),
+            b";\0".to_vec(),
+            (data.len() as u32).to_le_bytes().to_vec(),
+            b";\0".to_vec(),
+            data.to_vec(),
+            b"]\0".to_vec(),
+        ]
+        .concat()
+    }
+
+    fn set(path: &str, name: &str, value: RegistryValue) -> Change {
+        Change::SetValue {
+            path: path.to_string(),
+            name: name.to_string(),
+            previous: None,
+            value,
+        }
+    }
+
+    #[test]
+    fn entries_are_written_byte_for_byte() {
+        let changes = [
+            set(KEY, "Dword", RegistryValue::Dword(1)),
+            set(KEY, "Qword", RegistryValue::Qword(2)),
+            set(KEY, "String", RegistryValue::String("ab".to_string())),
+            set(KEY, "Expand", RegistryValue::ExpandString("%x%".to_string())),
+            set(KEY, "Multi", RegistryValue::MultiString(vec!["a".to_string(), "b".to_string()])),
+            set(KEY, "Binary", RegistryValue::Binary(vec![0xde, 0xad])),
+            Change::DeleteValue {
+                path: KEY.to_string(),
+                name: "Gone".to_string(),
+                previous: None,
+            },
+            Change::DeleteKey {
+                path: format!("{}\\Old", KEY),
+                previous: Vec::new(),
+            },
+            // the other side of the GPO
+            set("HKCU\\Software\\Policies\\dejunker", "User", RegistryValue::Dword(1)),
+        ];
+
+        let key = "SOFTWARE\\Policies\\dejunker";
+        let expected = [
+            b"PReg\x01\0\0\0".to_vec(),
+            record(key, "Dword", 4, &[1, 0, 0, 0]),
+            record(key, "Qword", 11, &[2, 0, 0, 0, 0, 0, 0, 0]),
+            record(key, "String", 1, b"a\0b\0\0\0"),
+            record(key, "Expand", 2, b"%\0x\0%\0\0\0"),
+            record(key, "Multi", 7, b"a\0\0\0b\0\0\0\0\0"),
+            record(key, "Binary", 3, &[0xde, 0xad]),
+            record(key, "**del.Gone", 1, b"\0\0"),
+            record(key, "**DeleteKeys", 1, b"O\0l\0d\0\0\0"),
+        ]
+        .concat();
+        assert_eq!(format_pol_file(&changes, PolicyScope::Machine).unwrap(), expected);
+    }
+}
@@ -0,0 +1,374 @@
+use crate::error::DejunkerError;
+use crate::registry::recorder::Change;
+use crate::registry::{self, RegistryValue, REG_BINARY, REG_EXPAND_SZ, REG_MULTI_SZ, REG_SZ};
+
+pub const REG_FILE_HEADER: &str = "Windows Registry Editor Version 5.00";
+
+// the header of the older (Windows 9x and NT 4) format, with ANSI rather than UTF-16 strings
+const REGEDIT4_HEADER: &str = "REGEDIT4";
+
+// regedit wraps long hex data at this column
+const HEX_LINE_WIDTH: usize = 77;
+
+/// Render registry changes as a .reg script (text). Consecutive changes to the same key are
+/// grouped under a single key header, the order of the changes is otherwise preserved.
+///
+/// * changes: the changes to render
+///
+pub fn format_reg_file(changes: &[Change]) -> Result<String, DejunkerError> {
+    let mut output = format!("{}\r\n", REG_FILE_HEADER);
+    let mut current_key: Option<String> = None;
+
+    for change in changes {
+        // keys are created by writing values below them
+        if let Change::CreateKey { .. } = change {
+            continue;
+        }
+
+        let key = full_key_path(change.path())?;
+
+        if let Change::DeleteKey { .. } = change {
+            output.push_str(&format!("\r\n[-{}]\r\n", key));
+            current_key = None;
+            continue;
+        }
+
+        if current_key.as_deref() != Some(key.as_str()) {
+            output.push_str(&format!("\r\n[{}]\r\n", key));
+            current_key = Some(key);
+        }
+
+        match change {
+            Change::SetValue { name, value, .. } => {
+                output.push_str(&format_value(name, value));
+            }
+            Change::DeleteValue { name, .. } => {
+                output.push_str(&format!("{}=-", format_name(name)));
+            }
+            Change::DeleteKey { .. } | Change::CreateKey { .. } => unreachable!(),
+        }
+        output.push_str("\r\n");
+    }
+
+    Ok(output)
+}
+
+/// Encode a .reg script the way regedit writes it (UTF-16LE with a byte order mark)
+pub fn encode_reg_file(contents: &str) -> Vec<u8> {
+    [0xFEFFu16]
+        .into_iter()
+        .chain(contents.encode_utf16())
+        .flat_map(|c| c.to_le_bytes())
+        .collect()
+}
+
+/// Registry path with the long form of the hive name, which is the only one regedit accepts
+fn full_key_path(path: &str) -> Result<String, DejunkerError> {
+    let (hive, sub_path) = registry::get_path_components(path)?;
+    if sub_path.is_empty() {
+        Ok(hive.name().to_string())
+    } else {
+        Ok(format!("{}\\{}", hive.name(), sub_path))
+    }
+}
+
+/// Value name as written in a .reg file ("@" is the default value)
+fn format_name(name: &str) -> String {
+    if name.is_empty() {
+        "@".to_string()
+    } else {
+        format!("\"{}\"", escape(name))
+    }
+}
+
+/// A value assignment line (possibly wrapped over several lines, for hex data)
+fn format_value(name: &str, value: &RegistryValue) -> String {
+    let prefix = format!("{}=", format_name(name));
+
+    match value {
+        RegistryValue::Dword(data) => format!("{}dword:{:08x}", prefix, data),
+        // strings containing line breaks can only be expressed as hex
+        RegistryValue::String(data) if !data.contains(['\r', '\n']) => {
+            format!("{}\"{}\"", prefix, escape(data))
+        }
+        _ => {
+            let type_tag = match value.kind() {
+                REG_BINARY => "hex:".to_string(),
+                kind => format!("hex({:x}):", kind),
+            };
+            format_hex(&format!("{}{}", prefix, type_tag), &value.to_raw())
+        }
+    }
+}
+
+/// Hex data, comma separated and wrapped like regedit does
+fn format_hex(prefix: &str, data: &[u8]) -> String {
+    let mut output = prefix.to_string();
+    let mut line_length = output.len();
+
+    for (index, byte) in data.iter().enumerate() {
+        let last = index + 1 == data.len();
+        let item = if last {
+            format!("{:02x}", byte)
+        } else {
+            format!("{:02x},", byte)
+        };
+
+        if line_length + item.len() > HEX_LINE_WIDTH {
+            output.push_str("\\\r\n  ");
+            line_length = 2;
+        }
+        output.push_str(&item);
+        line_length += item.len();
+    }
+    output
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Decode the contents of a .reg file. regedit writes UTF-16LE with a byte order mark, older
+/// (REGEDIT4) files are ANSI.
+pub fn decode_reg_file(data: &[u8]) -> String {
+    if let Some(wide) = data.strip_prefix(&[0xFF, 0xFE]) {
+        registry::decode_utf16(wide)
+    } else if let Some(utf8) = data.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        String::from_utf8_lossy(utf8).to_string()
+    } else {
+        match std::str::from_utf8(data) {
+            Ok(text) => text.to_string(),
+            Err(_) => data.iter().map(|&c| c as char).collect(),
+        }
+    }
+}
+
+/// Parse a .reg script into the registry changes it describes, in file order
+///
+/// * file: name of the .reg file, for error messages
+/// * contents: the (decoded) text of the .reg file
+///
+pub fn parse_reg_file(file: &str, contents: &str) -> Result<Vec<Change>, DejunkerError> {
+    let mut changes = Vec::new();
+    let mut current_key: Option<String> = None;
+    let mut header_seen = false;
+    let mut ansi = false;
+
+    for (number, line) in logical_lines(contents) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        if !header_seen {
+            if line != REG_FILE_HEADER && line != REGEDIT4_HEADER {
+                return Err(DejunkerError::parse(
+                    file,
+                    format!("line {}: not a .reg file (missing '{}' header)", number, REG_FILE_HEADER),
+                ));
+            }
+            header_seen = true;
+            ansi = line == REGEDIT4_HEADER;
+            continue;
+        }
+
+        if let Some(key) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            match key.strip_prefix('-') {
+                Some(deleted) => {
+                    changes.push(Change::DeleteKey {
+                        path: deleted.to_string(),
+                        previous: Vec::new(),
+                    });
+                    current_key = None;
+                }
+                None => current_key = Some(key.to_string()),
+            }
+            continue;
+        }
+
+        let path = current_key
+            .clone()
+            .ok_or_else(|| DejunkerError::parse(file, format!("line {}: value outside of a key", number)))?;
+        let (name, data) = parse_assignment(line)
+            .ok_or_else(|| DejunkerError::parse(file, format!("line {}: invalid value '{}'", number, line)))?;
+
+        if data == "-" {
+            changes.push(Change::DeleteValue {
+                path,
+                name,
+                previous: None,
+            });
+        } else {
+            let value = parse_data(data, ansi)
+                .ok_or_else(|| DejunkerError::parse(file, format!("line {}: invalid data '{}'", number, data)))?;
+            changes.push(Change::SetValue {
+                path,
+                name,
+                previous: None,
+                value,
+            });
+        }
+    }
+
+    if !header_seen {
+        return Err(DejunkerError::parse(file, "the file is empty"));
+    }
+    Ok(changes)
+}
+
+/// Lines of the file with their (1-based) numbers, hex data continued over several lines
+/// (ending with a backslash) is joined back into a single line
+fn logical_lines(contents: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    let mut continued = false;
+
+    for (index, line) in contents.lines().enumerate() {
+        let text = if continued { line.trim_start() } else { line };
+        let (text, continues) = match text.trim_end().strip_suffix('\\') {
+            Some(text) if !text.trim_start().starts_with('[') => (text, true),
+            _ => (text, false),
+        };
+
+        match lines.last_mut() {
+            Some((_, last)) if continued => last.push_str(text),
+            _ => lines.push((index + 1, text.to_string())),
+        }
+        continued = continues;
+    }
+    lines
+}
+
+/// Split a `"name"=data` or `@=data` line
+fn parse_assignment(line: &str) -> Option<(String, &str)> {
+    let (name, rest) = match line.strip_prefix('@') {
+        Some(rest) => (String::new(), rest),
+        None => parse_quoted(line)?,
+    };
+    Some((name, rest.trim_start().strip_prefix('=')?.trim()))
+}
+
+/// Parse a quoted, backslash escaped string at the start of `text`, returning the string
+/// and whatever follows the closing quote
+fn parse_quoted(text: &str) -> Option<(String, &str)> {
+    let mut chars = text.strip_prefix('"')?.char_indices();
+    let mut value = String::new();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => value.push(chars.next()?.1),
+            '"' => return Some((value, &text[index + 2..])),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Parse the data of a value. The strings of hex data are UTF-16LE, or ANSI in REGEDIT4 files.
+fn parse_data(data: &str, ansi: bool) -> Option<RegistryValue> {
+    if data.starts_with('"') {
+        let (value, rest) = parse_quoted(data)?;
+        return rest.trim().is_empty().then_some(RegistryValue::String(value));
+    }
+    if let Some(hex) = data.strip_prefix("dword:") {
+        return u32::from_str_radix(hex.trim(), 16).ok().map(RegistryValue::Dword);
+    }
+
+    let (kind, hex) = if let Some(hex) = data.strip_prefix("hex:") {
+        (REG_BINARY, hex)
+    } else {
+        let rest = data.strip_prefix("hex(")?;
+        let (kind, hex) = rest.split_once("):")?;
+        (u32::from_str_radix(kind, 16).ok()?, hex)
+    };
+
+    let bytes = hex
+        .split(',')
+        .map(str::trim)
+        .filter(|byte| !byte.is_empty())
+        .map(|byte| u8::from_str_radix(byte, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some(match ansi {
+        true => decode_ansi(kind, &bytes),
+        false => RegistryValue::from_raw(kind, &bytes),
+    })
+}
+
+/// A value of REGEDIT4 hex data, where strings are NUL terminated ANSI
+fn decode_ansi(kind: u32, data: &[u8]) -> RegistryValue {
+    let text: String = data.iter().map(|&c| c as char).collect();
+    let mut strings = text.split('\0').map(|item| item.to_string());
+    match kind {
+        REG_SZ => RegistryValue::String(strings.next().unwrap_or_default()),
+        REG_EXPAND_SZ => RegistryValue::ExpandString(strings.next().unwrap_or_default()),
+        // the list ends with an empty string
+        REG_MULTI_SZ => RegistryValue::MultiString(strings.take_while(|item| !item.is_empty()).collect()),
+        _ => RegistryValue::from_raw(kind, data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "HKEY_CURRENT_USER\\Software\\dejunker";
+
+    fn set(name: &str, value: RegistryValue) -> Change {
+        Change::SetValue {
+            path: KEY.to_string(),
+            name: name.to_string(),
+            previous: None,
+            value,
+        }
+    }
+
+    fn values() -> Vec<Change> {
+        vec![
+            set("Dword", RegistryValue::Dword(0x12345678)),
+            set("Qword", RegistryValue::Qword(0x0102030405060708)),
+            set("", RegistryValue::String("say \"hi\" to C:\\".to_string())),
+            set("Expand", RegistryValue::ExpandString("%SystemRoot%\\web".to_string())),
+            set("Multi", RegistryValue::MultiString(vec!["one".to_string(), "two".to_string()])),
+            // long enough to be continued over several lines
+            set("Binary", RegistryValue::Binary((0..=40).collect())),
+        ]
+    }
+
+    #[test]
+    fn changes_read_back_as_written() {
+        let mut changes = values();
+        changes.push(Change::DeleteValue {
+            path: KEY.to_string(),
+            name: "Gone".to_string(),
+            previous: None,
+        });
+        changes.push(Change::DeleteKey {
+            path: format!("{}\\Old", KEY),
+            previous: Vec::new(),
+        });
+
+        let contents = format_reg_file(&changes).unwrap();
+        assert!(contents.contains(",\\\r\n  "), "{}", contents);
+        let decoded = decode_reg_file(&encode_reg_file(&contents));
+        assert_eq!(parse_reg_file("test.reg", &decoded).unwrap(), changes);
+    }
+
+    #[test]
+    fn regedit4_strings_are_ansi() {
+        let contents = "REGEDIT4\r\n\r\n\
+            [HKEY_CURRENT_USER\\Software\\dejunker]\r\n\
+            \"Dword\"=dword:12345678\r\n\
+            \"Qword\"=hex(b):08,07,06,05,04,03,02,01\r\n\
+            @=\"say \\\"hi\\\" to C:\\\\\"\r\n\
+            \"Expand\"=hex(2):25,53,79,73,74,65,6d,52,6f,6f,74,25,5c,77,65,62,00\r\n\
+            \"Multi\"=hex(7):6f,6e,65,00,74,77,6f,00,00\r\n\
+            \"Binary\"=hex:00,01,02,03,04,05,06,07,08,09,0a,0b,0c,0d,0e,0f,10,11,12,13,14,\\\r\n\
+            \x20 15,16,17,18,19,1a,1b,1c,1d,1e,1f,20,21,22,23,24,25,26,27,28\r\n";
+        let changes = parse_reg_file("test.reg", &decode_reg_file(contents.as_bytes())).unwrap();
+        assert_eq!(changes, values());
+
+        // and they are written back as UTF-16
+        let contents = format_reg_file(&changes).unwrap();
+        assert!(contents.starts_with(REG_FILE_HEADER));
+        assert_eq!(parse_reg_file("test.reg", &contents).unwrap(), values());
+    }
+}
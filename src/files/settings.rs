@@ -1,13 +1,19 @@
-use log::{debug, warn};
-use reqwest::blocking::get;
-use std::collections::HashMap;
-use std::error::Error;
-use std::fs::File;
-use std::io::Read;
+use log::{debug, info, warn};
+use std::collections::{BTreeSet, HashMap};
 use yaml_rust2::YamlLoader;
 
+use crate::error::DejunkerError;
+use crate::files::db::{yaml_scalar, yaml_string, Exec, ExecAction, Rule};
+use crate::firewall::{self, Direction, Firewall, FirewallRule};
+use crate::hosts::{self, HostsFile};
+use crate::packages::{self, PackageManager, PackageScope};
 use crate::types::onoff::OnOffType;
-use crate::{registry, utils};
+use crate::types::state::{EntryState, ExecState, RuleState};
+use crate::registry::{self, Hive, RegistryBackend, RegistryValue};
+use crate::services::{ServiceManager, StartType};
+use crate::subsystems::Subsystems;
+use crate::tasks::{self, TaskScheduler};
+use crate::utils;
 
 #[derive(Debug, Clone)]
 pub struct Settings {
@@ -17,113 +23,1170 @@ pub struct Settings {
 
 pub const FILE_MARKER: &str = "redsigil.dfckr.settings.v1";
 
+// the data of exec entries with an item
+const ITEM_PRESENT: &str = "present";
+const ITEM_ABSENT: &str = "absent";
+
+// what the state of a service, task, appx, hosts or firewall entry is about, in place of a
+// registry value name
+const SERVICE_START_TYPE: &str = "start type";
+const TASK_STATE: &str = "state";
+const PACKAGE_INSTALLED: &str = "installed";
+const PACKAGE_PROVISIONED: &str = "provisioned";
+const HOSTS_ENTRY: &str = "hosts file";
+const FIREWALL_RULE: &str = "firewall rule";
+
 /// Read a settings file into memory
 ///
 /// * path_or_url: the path of the settings file to read
 ///
-pub fn read_settings_file(path_or_url: &str) -> Result<Settings, Box<dyn Error>> {
+pub fn read_settings_file(path_or_url: &str) -> Result<Settings, DejunkerError> {
     // Read the content from either a local file or a URL
-    let contents = if path_or_url.starts_with("http://") || path_or_url.starts_with("https://") {
-        get(path_or_url)?.text()?
-    } else {
-        let mut file = File::open(path_or_url)?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
-        contents
-    };
+    let contents = super::read_file_or_url(path_or_url)?;
 
     // Parse the YAML content
-    let docs = YamlLoader::load_from_str(&contents)?;
-    let doc = &docs[0];
+    let docs = YamlLoader::load_from_str(&contents)
+        .map_err(|e| DejunkerError::parse(path_or_url, e.to_string()))?;
+    let doc = docs
+        .first()
+        .ok_or_else(|| DejunkerError::parse(path_or_url, "the file is empty"))?;
 
     // Extract file and settings from the YAML
     let file = doc["file"]
         .as_str()
-        .ok_or("Invalid input format, file is missing 'file' field")?
+        .ok_or_else(|| DejunkerError::parse(path_or_url, "missing 'file' field"))?
         .to_string();
 
     debug!("Settings file type is {}", file);
     if file != FILE_MARKER {
-        panic!("Invalid database type when reading '{}'", file);
+        return Err(DejunkerError::parse(
+            path_or_url,
+            format!("not a settings file (file type is '{}', expected '{}')", file, FILE_MARKER),
+        ));
     }
 
     let settings_yaml = doc["settings"]
         .as_hash()
-        .ok_or("Invalid input format, file is missing 'settings' field")?;
+        .ok_or_else(|| DejunkerError::parse(path_or_url, "missing 'settings' field"))?;
     let mut settings = HashMap::new();
 
     for (key, value) in settings_yaml {
-        if let (Some(key), Some(value)) = (key.as_str(), value.as_str()) {
-            settings.insert(key.to_string(), value.to_string());
+        // integer rules have numbers as values
+        if let (Some(key), Some(value)) = (key.as_str(), yaml_scalar(value)) {
+            settings.insert(key.to_string(), value);
         }
     }
 
     Ok(Settings { file, settings })
 }
 
+/// Apply all the settings of a settings file, in a stable (sorted) order
+///
+/// * registry: the registry to apply the settings to
+/// * subsystems: the backends of the other subsystems
+/// * rules: the list of known rules
+/// * settings: the settings to apply
+/// * skip_inaccessible: skip rules that cannot be applied (no admin rights, or not accessible
+///   through the registry or another subsystem), instead of failing
+///
+pub fn apply_settings(
+    registry: &mut dyn RegistryBackend,
+    subsystems: &mut Subsystems,
+    rules: &HashMap<String, Rule>,
+    settings: &Settings,
+    skip_inaccessible: bool,
+) -> Result<(), DejunkerError> {
+    let mut sorted_settings = settings.settings.iter().collect::<Vec<_>>();
+    sorted_settings.sort();
+    for (key, value) in sorted_settings {
+        execute_rule(registry, subsystems, rules, key, value, skip_inaccessible)?;
+    }
+    Ok(())
+}
+
 /// Execute a rule from the settings file
 ///
+/// * registry: the registry to apply the rule to
+/// * subsystems: the backends of the other subsystems
 /// * rules: the list of known rules
 /// * rule_name: the name of the rule to execute
 /// * desired_value: the value to set the rule to
+/// * skip_inaccessible: skip the rule if it cannot be applied, instead of failing
 ///
 pub fn execute_rule(
-    rules: &HashMap<String, crate::db::Rule>,
+    registry: &mut dyn RegistryBackend,
+    subsystems: &mut Subsystems,
+    rules: &HashMap<String, Rule>,
     rule_name: &str,
     desired_value: &str,
     skip_inaccessible: bool
-) -> Result<(), Box<dyn std::error::Error>> {
-    let rule = &rules[rule_name];
+) -> Result<(), DejunkerError> {
+    let rule = rules
+        .get(rule_name)
+        .ok_or_else(|| DejunkerError::UnknownRule(rule_name.to_string()))?;
+
+    // settings files written from a registry can carry states that cannot be applied
+    if RuleState::is_report_only(desired_value) {
+        info!("Rule {} is {}, leaving it as is.", rule_name, desired_value);
+        return Ok(());
+    }
 
-    if rule.admin_required && utils::is_elevated() == false {
+    if rule.admin_required && registry.requires_elevation() && !utils::is_elevated() {
 
         match skip_inaccessible  {
             true => {
                 warn!("Rule {} was skipped, operation requires admin rights.", rule_name);
             },
             false => {
-                return Err(format!("Rule {} requires admin rights.", rule_name).into());
+                return Err(DejunkerError::AccessDenied(format!("rule {} requires admin rights", rule_name)));
             }
         }
        
         return Ok(());
     }
 
-    for op in &rule.exec {
+    if let Some(reason) = unreachable_entry(registry, subsystems, rule) {
+        if skip_inaccessible {
+            warn!("Rule {} was skipped, {}.", rule_name, reason);
+            return Ok(());
+        }
+        return Err(DejunkerError::AccessDenied(format!(
+            "rule {} cannot be applied, {}",
+            rule_name, reason
+        )));
+    }
+
+    if !rule.value.is_supported() {
+        return Err(DejunkerError::UnsupportedValueType(rule.value.value_type.clone()));
+    }
+    if let Err(reason) = rule.value.check(desired_value) {
+        warn!("Rule {}: {}", rule_name, reason);
+        return Err(DejunkerError::InvalidValue {
+            rule: rule_name.to_string(),
+            value: desired_value.to_string(),
+        });
+    }
+
+    for op in &view_entries(rule)? {
+        if subsystems.user_entries_only && !is_user_entry(op) {
+            debug!("Skipping {} {}, it was applied for an earlier user", op.subsystem, op.path);
+            continue;
+        }
         match op.subsystem.as_str() {
             "registry" => {
-                //let value = crate::registry::read_value(&op.path, &op.value, &op.value_type)?;
-
-                match op.value_type.as_str() {
-                    "i32" | "u32" => {
-                        let desired_value = desired_value
-                            .parse::<OnOffType>()
-                            .expect(format!("Invalid value type for {}", rule_name).as_str());
-
-                        let value = if op.reversed == Some(true) {
-                            desired_value.flipped()
-                        } else {
-                            desired_value
-                        }
-                        .as_u32();
+                let action = exec_action(rule, op, desired_value).ok_or_else(|| DejunkerError::InvalidValue {
+                    rule: rule_name.to_string(),
+                    value: desired_value.to_string(),
+                })?;
 
+                match action {
+                    ExecAction::Write(presence) if op.item.is_some() => {
+                        set_item(registry, rule, op, &presence)?;
+                    }
+                    ExecAction::Write(data) => {
+                        let value = exec_value(rule, op, &data)?;
                         debug!("Setting {} -> {} to {}", op.path, op.value, value);
-                        registry::set_u32_value(op.path.as_str(), op.value.as_str(), value)?;
+                        registry.set_value(&op.path, &op.value, &value)?;
                     }
-                    _ => {
-                        return Err(format!(
-                            "Unsupported value type '{}' for '{}'",
-                            op.value_type, op.path
-                        )
-                        .into());
+                    ExecAction::DeleteValue => {
+                        debug!("Deleting {} -> {}", op.path, op.value);
+                        registry.delete_value(&op.path, &op.value)?;
+                    }
+                    ExecAction::DeleteKey { backup } => {
+                        if backup && registry.key_exists(&op.path)? {
+                            debug!("Backing up {} to {}", op.path, registry::backup_path(&op.path)?);
+                            registry.back_up_key(&op.path)?;
+                        }
+                        debug!("Deleting {}", op.path);
+                        registry.delete_key(&op.path)?;
                     }
                 }
             }
+            "service" => {
+                let start_type = SERVICE_MAPPING.required(rule_name, rule, op, desired_value)?;
+                let services = subsystems.services.as_deref_mut().ok_or_else(|| not_accessible(op))?;
+                set_service(services, op, start_type)?;
+            }
+            "scheduled_task" => {
+                let enabled = TASK_MAPPING.required(rule_name, rule, op, desired_value)?;
+                let scheduler = subsystems.tasks.as_deref_mut().ok_or_else(|| not_accessible(op))?;
+                set_tasks(scheduler, op, enabled)?;
+            }
+            "appx" => {
+                let present = PACKAGE_MAPPING.required(rule_name, rule, op, desired_value)?;
+                let manager = subsystems.packages.as_deref_mut().ok_or_else(|| not_accessible(op))?;
+                set_packages(manager, op, present)?;
+            }
+            "hosts" => {
+                let blocked = HOSTS_MAPPING.required(rule_name, rule, op, desired_value)?;
+                let hosts = subsystems.hosts.as_deref_mut().ok_or_else(|| not_accessible(op))?;
+                set_hosts(hosts, op, blocked)?;
+            }
+            "firewall" => {
+                let blocked = FIREWALL_MAPPING.required(rule_name, rule, op, desired_value)?;
+                let firewall = subsystems.firewall.as_deref_mut().ok_or_else(|| not_accessible(op))?;
+                set_firewall(firewall, op, blocked)?;
+            }
             _ => {
-                return Err(format!("Unsupported subsystem '{}'", op.subsystem).into());
+                return Err(DejunkerError::UnsupportedSubsystem(op.subsystem.clone()));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether an exec entry is applied per user (to HKEY_CURRENT_USER), rather than to the
+/// whole machine
+fn is_user_entry(op: &Exec) -> bool {
+    op.subsystem == "registry" && matches!(registry::get_path_components(&op.path), Ok((Hive::CurrentUser, _)))
+}
+
+/// Evaluate the current state of a rule. Values that do not exist are reported as not
+/// configured rather than assumed to be 0.
+///
+/// * registry: the registry to read the current values from
+/// * subsystems: the backends of the other subsystems
+/// * rule: the rule to evaluate
+///
+pub fn evaluate_rule(
+    registry: &dyn RegistryBackend,
+    subsystems: &Subsystems,
+    rule: &Rule,
+) -> Result<RuleState, DejunkerError> {
+    let mut entries = Vec::new();
+
+    for op in &view_entries(rule)? {
+        match op.subsystem.as_str() {
+            "registry" => {
+                let state = if op.item.is_some() {
+                    item_state(rule, op, registry.get_value(&op.path, &op.value)?)?
+                } else if !op.values.is_empty() {
+                    mapped_state(registry, rule, op)?
+                } else if rule.value.value_type.to_lowercase() == "onoff" {
+                    match op.value_type.as_str() {
+                        "i32" | "u32" => match registry.read_value(&op.path, &op.value, &op.value_type)? {
+                            Some(value) => {
+                                let value = OnOffType::from_string(value.as_str());
+                                let value = if op.reversed.unwrap_or(false) { value.flipped() } else { value };
+                                EntryState::Value(value.to_string())
+                            }
+                            None => EntryState::NotConfigured,
+                        },
+                        _ => {
+                            return Err(DejunkerError::UnsupportedValueType(op.value_type.clone()));
+                        }
+                    }
+                } else {
+                    // the value of the rule is the data itself
+                    match registry.get_value(&op.path, &op.value)? {
+                        Some(current) => match exec_data(op, &current)? {
+                            Some(data) => EntryState::Value(data),
+                            None => EntryState::Unknown(current.to_string()),
+                        },
+                        None => EntryState::NotConfigured,
+                    }
+                };
+
+                entries.push(ExecState {
+                    path: op.path.clone(),
+                    value: op.value.clone(),
+                    state,
+                });
+            }
+            "service" => {
+                let services = subsystems.services.as_deref().ok_or_else(|| not_accessible(op))?;
+                entries.push(ExecState {
+                    path: op.path.clone(),
+                    value: SERVICE_START_TYPE.to_string(),
+                    state: service_state(services, rule, op)?,
+                });
+            }
+            "scheduled_task" => {
+                let scheduler = subsystems.tasks.as_deref().ok_or_else(|| not_accessible(op))?;
+                entries.extend(task_states(scheduler, rule, op)?);
+            }
+            "appx" => {
+                let manager = subsystems.packages.as_deref().ok_or_else(|| not_accessible(op))?;
+                entries.extend(package_states(manager, rule, op)?);
+            }
+            "hosts" => {
+                let hosts = subsystems.hosts.as_deref().ok_or_else(|| not_accessible(op))?;
+                entries.extend(hosts_states(hosts, rule, op)?);
+            }
+            "firewall" => {
+                let firewall = subsystems.firewall.as_deref().ok_or_else(|| not_accessible(op))?;
+                entries.push(ExecState {
+                    path: op.path.clone(),
+                    value: FIREWALL_RULE.to_string(),
+                    state: firewall_state(firewall, rule, op)?,
+                });
+            }
+            _ => {
+                return Err(DejunkerError::UnsupportedSubsystem(op.subsystem.clone()));
+            }
+        }
+    }
+
+    Ok(RuleState::from_entries(entries))
+}
+
+/// Why a rule cannot be reached (e.g. a registry path outside of the mounted hives, or a
+/// subsystem without a backend), None if all its exec entries can be reached
+///
+/// * registry: the registry the rule would be read from or applied to
+/// * subsystems: the backends of the other subsystems
+/// * rule: the rule
+///
+pub fn unreachable_entry(registry: &dyn RegistryBackend, subsystems: &Subsystems, rule: &Rule) -> Option<String> {
+    rule.exec.iter().find_map(|op| subsystems.unreachable(registry, op))
+}
+
+/// The exec entries of a rule, with each registry entry repeated for every path its view
+/// covers (see registry::view_paths)
+fn view_entries(rule: &Rule) -> Result<Vec<Exec>, DejunkerError> {
+    let mut entries = Vec::new();
+    for op in &rule.exec {
+        if op.subsystem != "registry" || op.view.is_none() {
+            entries.push(op.clone());
+            continue;
+        }
+        for path in registry::view_paths(&op.path, op.view)? {
+            entries.push(Exec {
+                path,
+                view: None,
+                ..op.clone()
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Format the state of a rule as a value in a settings file
+///
+/// * rule: the rule
+/// * state: the state the rule is in
+///
+pub fn format_setting(rule: &Rule, state: &RuleState) -> String {
+    match state {
+        // quoted where needed, so e.g. "on" or "123" are read back as strings
+        RuleState::Value(value) if rule.value.value_type.to_lowercase() == "string" => yaml_string(value),
+        state => state.to_string(),
+    }
+}
+
+/// What setting a rule to a value does to the registry value of an exec entry. None if the
+/// exec entry has no mapping for the value.
+fn exec_action(rule: &Rule, op: &Exec, desired: &str) -> Option<ExecAction> {
+    if !op.values.is_empty() {
+        return op.values.get(desired).cloned();
+    }
+
+    match rule.value.value_type.to_lowercase().as_str() {
+        // on adds the item, off removes it
+        "onoff" if op.item.is_some() => {
+            let desired = desired.parse::<OnOffType>().ok()?;
+            let value = if op.reversed == Some(true) { desired.flipped() } else { desired };
+            let presence = if value == OnOffType::On { ITEM_PRESENT } else { ITEM_ABSENT };
+            Some(ExecAction::Write(presence.to_string()))
+        }
+        "onoff" => {
+            let desired = desired.parse::<OnOffType>().ok()?;
+            let value = if op.reversed == Some(true) { desired.flipped() } else { desired };
+            Some(ExecAction::Write(value.as_u32().to_string()))
+        }
+        // written as they are
+        "integer" | "string" => Some(ExecAction::Write(desired.to_string())),
+        _ => None,
+    }
+}
+
+/// The value of the rule an exec entry with explicit mappings is in. A value or key that is
+/// absent is in the value that deletes it, if there is one.
+fn mapped_state(registry: &dyn RegistryBackend, rule: &Rule, op: &Exec) -> Result<EntryState, DejunkerError> {
+    let current = registry.get_value(&op.path, &op.value)?;
+    let deletes_key = op.values.values().any(|action| matches!(action, ExecAction::DeleteKey { .. }));
+    let key_exists = !deletes_key || registry.key_exists(&op.path)?;
+
+    for (key, action) in &op.values {
+        let matches = match action {
+            ExecAction::Write(data) => current.as_ref() == Some(&exec_value(rule, op, data)?),
+            ExecAction::DeleteValue => current.is_none(),
+            ExecAction::DeleteKey { .. } => !key_exists,
+        };
+        if matches {
+            return Ok(EntryState::Value(key.clone()));
+        }
+    }
+
+    Ok(match current {
+        Some(data) => EntryState::Unknown(data.to_string()),
+        // the key is still there, that is not what any value of the rule leaves behind
+        None if deletes_key && key_exists => EntryState::Unknown("the key exists".to_string()),
+        None => EntryState::NotConfigured,
+    })
+}
+
+/// Add the item of an exec entry to its multi-string value, or remove it, leaving the other
+/// items as they are. Items are compared case insensitively.
+fn set_item(registry: &mut dyn RegistryBackend, rule: &Rule, op: &Exec, presence: &str) -> Result<(), DejunkerError> {
+    let item = op.item.as_deref().unwrap_or("");
+    let current = registry.get_value(&op.path, &op.value)?;
+    let mut items = match (op.value_type.as_str(), &current) {
+        ("multi_string", Some(RegistryValue::MultiString(items))) => items.clone(),
+        ("multi_string", Some(other)) => {
+            return Err(DejunkerError::registry(format!(
+                "Registry value {}\\{} has type {}, expected a list of strings",
+                op.path,
+                op.value,
+                other.kind()
+            )));
+        }
+        ("multi_string", None) => Vec::new(),
+        _ => return Err(DejunkerError::UnsupportedValueType(op.value_type.clone())),
+    };
+
+    match presence {
+        ITEM_PRESENT if !items.iter().any(|i| i.eq_ignore_ascii_case(item)) => items.push(item.to_string()),
+        ITEM_PRESENT => {}
+        ITEM_ABSENT => items.retain(|i| !i.eq_ignore_ascii_case(item)),
+        _ => {
+            return Err(DejunkerError::InvalidValue {
+                rule: rule.name.clone(),
+                value: presence.to_string(),
+            });
+        }
+    }
+
+    // an item that is not there does not need a list to not be in
+    let value = RegistryValue::MultiString(items);
+    if current.as_ref() == Some(&value) || (current.is_none() && presence == ITEM_ABSENT) {
+        debug!("{} -> {}: {} is already {}", op.path, op.value, item, presence);
+        return Ok(());
+    }
+    debug!("Setting {} -> {} to {}", op.path, op.value, value);
+    registry.set_value(&op.path, &op.value, &value)
+}
+
+/// The value of the rule an exec entry with an item is in, given the current data. A list
+/// that does not exist does not have the item either.
+fn item_state(rule: &Rule, op: &Exec, current: Option<RegistryValue>) -> Result<EntryState, DejunkerError> {
+    if op.value_type != "multi_string" {
+        return Err(DejunkerError::UnsupportedValueType(op.value_type.clone()));
+    }
+
+    let item = op.item.as_deref().unwrap_or("");
+    let items = match &current {
+        Some(RegistryValue::MultiString(items)) => items.as_slice(),
+        Some(other) => return Ok(EntryState::Unknown(other.to_string())),
+        None => &[],
+    };
+    let presence = match items.iter().any(|i| i.eq_ignore_ascii_case(item)) {
+        true => ITEM_PRESENT,
+        false => ITEM_ABSENT,
+    };
+
+    let value = rule
+        .value
+        .choices()
+        .unwrap_or_default()
+        .into_iter()
+        .find(|value| exec_action(rule, op, value) == Some(ExecAction::Write(presence.to_string())));
+    Ok(match value {
+        Some(value) => EntryState::Value(value),
+        None => EntryState::Unknown(presence.to_string()),
+    })
+}
+
+/// The registry value an exec entry stores for some data (as written in the database)
+pub(crate) fn exec_value(rule: &Rule, op: &Exec, data: &str) -> Result<RegistryValue, DejunkerError> {
+    let invalid = || DejunkerError::InvalidValue {
+        rule: rule.name.clone(),
+        value: data.to_string(),
+    };
+
+    match op.value_type.as_str() {
+        // DWORDs and QWORDs are unsigned, negative numbers are only accepted for i32 and i64
+        // (they would read back as large positive numbers for u32 and u64)
+        "i32" => data
+            .parse::<i64>()
+            .ok()
+            .filter(|number| (i32::MIN as i64..=u32::MAX as i64).contains(number))
+            .map(|number| RegistryValue::Dword(number as u32))
+            .ok_or_else(invalid),
+        "u32" => data.parse::<u32>().map(RegistryValue::Dword).map_err(|_| invalid()),
+        "i64" => data
+            .parse::<i64>()
+            .map(|number| number as u64)
+            .or_else(|_| data.parse::<u64>())
+            .map(RegistryValue::Qword)
+            .map_err(|_| invalid()),
+        "u64" => data.parse::<u64>().map(RegistryValue::Qword).map_err(|_| invalid()),
+        "string" => Ok(RegistryValue::String(data.to_string())),
+        "expand_string" => Ok(RegistryValue::ExpandString(data.to_string())),
+        "multi_string" if data.is_empty() => Ok(RegistryValue::MultiString(Vec::new())),
+        "multi_string" => Ok(RegistryValue::MultiString(data.split('\n').map(|item| item.to_string()).collect())),
+        "binary" => utils::from_hex(data).map(RegistryValue::Binary).ok_or_else(invalid),
+        _ => Err(DejunkerError::UnsupportedValueType(op.value_type.clone())),
+    }
+}
+
+/// The data (as written in the database) of the registry value of an exec entry, the inverse
+/// of exec_value. None if the value is not of the type of the exec entry.
+pub(crate) fn exec_data(op: &Exec, value: &RegistryValue) -> Result<Option<String>, DejunkerError> {
+    let data = match (op.value_type.as_str(), value) {
+        ("i32", RegistryValue::Dword(number)) => Some((*number as i32).to_string()),
+        ("u32", RegistryValue::Dword(number)) => Some(number.to_string()),
+        ("i64", RegistryValue::Qword(number)) => Some((*number as i64).to_string()),
+        ("u64", RegistryValue::Qword(number)) => Some(number.to_string()),
+        ("string", RegistryValue::String(data)) | ("expand_string", RegistryValue::ExpandString(data)) => {
+            Some(data.clone())
+        }
+        ("multi_string", RegistryValue::MultiString(items)) => Some(items.join("\n")),
+        ("binary", RegistryValue::Binary(data)) => Some(utils::to_hex(data)),
+        ("i32" | "u32" | "i64" | "u64" | "string" | "expand_string" | "multi_string" | "binary", _) => None,
+        _ => return Err(DejunkerError::UnsupportedValueType(op.value_type.clone())),
+    };
+    Ok(data)
+}
+
+/// How the values of a rule map to the state of a service, task, appx, hosts or firewall
+/// entry. With mappings in the exec entry, a value maps to the state its data names. Without,
+/// on and off map to two states (the other way around if reversed), and the value of other
+/// rules is the name of the state itself.
+struct StateMapping<T> {
+    parse: fn(&str) -> Option<T>,
+    name: fn(T) -> &'static str,
+    on: T,
+    off: T,
+}
+
+/// Start types of services, on is automatic and off is disabled
+const SERVICE_MAPPING: StateMapping<StartType> = StateMapping {
+    parse: StartType::from_name,
+    name: |start_type| start_type.name(),
+    on: StartType::Automatic,
+    off: StartType::Disabled,
+};
+
+/// Whether tasks are enabled, on enables and off disables
+const TASK_MAPPING: StateMapping<bool> = StateMapping {
+    parse: tasks::parse_enabled,
+    name: tasks::enabled_name,
+    on: true,
+    off: false,
+};
+
+/// Whether packages are present, on is present and off is absent
+const PACKAGE_MAPPING: StateMapping<bool> = StateMapping {
+    parse: packages::parse_presence,
+    name: packages::presence_name,
+    on: true,
+    off: false,
+};
+
+/// Whether hostnames are blocked, on allows and off blocks
+const HOSTS_MAPPING: StateMapping<bool> = StateMapping {
+    parse: hosts::parse_blocked,
+    name: hosts::blocked_name,
+    on: false,
+    off: true,
+};
+
+/// Whether traffic is blocked, on allows and off blocks
+const FIREWALL_MAPPING: StateMapping<bool> = StateMapping {
+    parse: firewall::parse_blocked,
+    name: firewall::blocked_name,
+    on: false,
+    off: true,
+};
+
+impl<T: Copy + PartialEq> StateMapping<T> {
+    /// The state setting a rule to a value gives an exec entry. None if the value has no
+    /// mapping.
+    fn target(&self, rule: &Rule, op: &Exec, desired: &str) -> Option<T> {
+        if !op.values.is_empty() {
+            return match op.values.get(desired)? {
+                ExecAction::Write(state) => (self.parse)(state),
+                _ => None,
+            };
+        }
+
+        match rule.value.value_type.to_lowercase().as_str() {
+            "onoff" => {
+                let desired = desired.parse::<OnOffType>().ok()?;
+                let value = if op.reversed == Some(true) { desired.flipped() } else { desired };
+                match value {
+                    OnOffType::On => Some(self.on),
+                    OnOffType::Off => Some(self.off),
+                }
+            }
+            _ => (self.parse)(desired),
+        }
+    }
+
+    /// The state setting a rule to a value gives an exec entry, an error if the value has no
+    /// mapping
+    fn required(&self, rule_name: &str, rule: &Rule, op: &Exec, desired: &str) -> Result<T, DejunkerError> {
+        self.target(rule, op, desired).ok_or_else(|| DejunkerError::InvalidValue {
+            rule: rule_name.to_string(),
+            value: desired.to_string(),
+        })
+    }
+
+    /// The value of the rule an exec entry in a state is in, None if no value maps to it
+    fn value_of(&self, rule: &Rule, op: &Exec, state: T) -> Option<String> {
+        match rule.value.choices() {
+            Some(choices) => choices
+                .into_iter()
+                .find(|value| self.target(rule, op, value) == Some(state)),
+            // the value of the rule is the state itself
+            None => Some((self.name)(state).to_string()).filter(|value| self.target(rule, op, value) == Some(state)),
+        }
+    }
+
+    /// The state of an exec entry in a state, unknown if no value of the rule maps to it
+    fn entry_state(&self, rule: &Rule, op: &Exec, state: T) -> EntryState {
+        match self.value_of(rule, op, state) {
+            Some(value) => EntryState::Value(value),
+            None => EntryState::Unknown((self.name)(state).to_string()),
+        }
+    }
+}
+
+/// Set the start type of the service of an exec entry, and stop it if the entry asks for it
+/// and the service is no longer to run. A service that is not installed is left alone.
+fn set_service(services: &mut dyn ServiceManager, op: &Exec, start_type: StartType) -> Result<(), DejunkerError> {
+    let Some(status) = services.query(&op.path)? else {
+        match start_type {
+            StartType::Disabled => debug!("Service {} is not installed", op.path),
+            _ => warn!("Service {} is not installed, it cannot be set to {}", op.path, start_type),
+        }
+        return Ok(());
+    };
+
+    if status.start_type != start_type {
+        debug!("Setting the start type of service {} to {}", op.path, start_type);
+        services.set_start_type(&op.path, start_type)?;
+    }
+    if op.stop == Some(true) && status.running && !start_type.starts_with_windows() {
+        debug!("Stopping service {}", op.path);
+        services.stop(&op.path)?;
+    }
+    Ok(())
+}
+
+/// The value of the rule the service of an exec entry is in, from its start type. A service
+/// that is not installed is in the value that disables it, if there is one. Whether the
+/// service is running does not matter.
+fn service_state(services: &dyn ServiceManager, rule: &Rule, op: &Exec) -> Result<EntryState, DejunkerError> {
+    let start_type = services.query(&op.path)?.map(|status| status.start_type);
+    let wanted = start_type.unwrap_or(StartType::Disabled);
+
+    Ok(match (SERVICE_MAPPING.value_of(rule, op, wanted), start_type) {
+        (Some(value), _) => EntryState::Value(value),
+        (None, Some(start_type)) => EntryState::Unknown(start_type.to_string()),
+        (None, None) => EntryState::NotConfigured,
+    })
+}
+
+/// Enable or disable the tasks an exec entry matches. Tasks that are not there are left alone.
+fn set_tasks(scheduler: &mut dyn TaskScheduler, op: &Exec, enabled: bool) -> Result<(), DejunkerError> {
+    let found = tasks::find_tasks(scheduler, &op.path)?;
+    if found.is_empty() {
+        match enabled {
+            true => warn!("No scheduled task matches {}, nothing to enable", op.path),
+            false => debug!("No scheduled task matches {}", op.path),
+        }
+    }
+
+    for task in found.iter().filter(|task| task.enabled != enabled) {
+        debug!("Setting scheduled task {} to {}", task.path, tasks::enabled_name(enabled));
+        scheduler.set_enabled(&task.path, enabled)?;
+    }
+    Ok(())
+}
+
+/// The value of the rule each task an exec entry matches is in. When no task matches, the
+/// entry is in the value that disables its tasks, if there is one.
+fn task_states(scheduler: &dyn TaskScheduler, rule: &Rule, op: &Exec) -> Result<Vec<ExecState>, DejunkerError> {
+    let found = tasks::find_tasks(scheduler, &op.path)?;
+    if found.is_empty() {
+        return Ok(vec![ExecState {
+            path: op.path.clone(),
+            value: TASK_STATE.to_string(),
+            state: TASK_MAPPING.value_of(rule, op, false).map_or(EntryState::NotConfigured, EntryState::Value),
+        }]);
+    }
+
+    Ok(found
+        .into_iter()
+        .map(|task| ExecState {
+            state: TASK_MAPPING.entry_state(rule, op, task.enabled),
+            path: task.path,
+            value: TASK_STATE.to_string(),
+        })
+        .collect())
+}
+
+/// Remove the packages an exec entry matches, for the current user and/or from the
+/// provisioned packages. Packages cannot be installed, when they are to be present the ones
+/// that are missing are left alone.
+fn set_packages(manager: &mut dyn PackageManager, op: &Exec, present: bool) -> Result<(), DejunkerError> {
+    for scope in package_scopes(op) {
+        let found = packages::find_packages(manager, scope, &op.path, op.publisher.as_deref())?;
+        if present {
+            if found.is_empty() {
+                warn!("No {} package matches {}, it cannot be installed", scope, op.path);
             }
+            continue;
+        }
+
+        for package in &found {
+            debug!("Removing {} package {}", scope, package.full_name);
+            manager.remove(scope, package)?;
         }
     }
+    Ok(())
+}
+
+/// The value of the rule an exec entry is in, for each of its scopes: whether a package it
+/// matches is there
+fn package_states(manager: &dyn PackageManager, rule: &Rule, op: &Exec) -> Result<Vec<ExecState>, DejunkerError> {
+    package_scopes(op)
+        .into_iter()
+        .map(|scope| {
+            let present = !packages::find_packages(manager, scope, &op.path, op.publisher.as_deref())?.is_empty();
+            Ok(ExecState {
+                path: op.path.clone(),
+                value: match scope {
+                    PackageScope::User => PACKAGE_INSTALLED,
+                    PackageScope::Provisioned => PACKAGE_PROVISIONED,
+                }
+                .to_string(),
+                state: PACKAGE_MAPPING.entry_state(rule, op, present),
+            })
+        })
+        .collect()
+}
+
+/// The scopes of an appx exec entry, both when it does not name one
+fn package_scopes(op: &Exec) -> Vec<PackageScope> {
+    match op.scope {
+        Some(scope) => vec![scope],
+        None => PackageScope::ALL.to_vec(),
+    }
+}
+
+/// Add the hostnames of an exec entry to the managed block of the hosts file, or remove them
+/// from it. The hosts file is only written when the block changes.
+fn set_hosts(hosts: &mut dyn HostsFile, op: &Exec, blocked: bool) -> Result<(), DejunkerError> {
+    let contents = hosts.read()?;
+    let current: BTreeSet<String> = hosts::blocked_hosts(&contents)?.into_iter().collect();
+
+    let mut updated = current.clone();
+    for hostname in op.hostnames.iter().map(|hostname| hostname.to_lowercase()) {
+        if blocked {
+            updated.insert(hostname);
+        } else {
+            updated.remove(&hostname);
+        }
+    }
+    if updated != current {
+        debug!("Setting {} to {} in the hosts file", op.hostnames.join(", "), hosts::blocked_name(blocked));
+        let updated: Vec<String> = updated.into_iter().collect();
+        hosts.write(&hosts::with_blocked_hosts(&contents, &updated)?)?;
+    }
+    Ok(())
+}
+
+/// The value of the rule each hostname of an exec entry is in, from whether it is in the
+/// managed block of the hosts file
+fn hosts_states(hosts: &dyn HostsFile, rule: &Rule, op: &Exec) -> Result<Vec<ExecState>, DejunkerError> {
+    let blocked = hosts::blocked_hosts(&hosts.read()?)?;
+    Ok(op
+        .hostnames
+        .iter()
+        .map(|hostname| {
+            let is_blocked = blocked.contains(&hostname.to_lowercase());
+            ExecState {
+                path: hostname.clone(),
+                value: HOSTS_ENTRY.to_string(),
+                state: HOSTS_MAPPING.entry_state(rule, op, is_blocked),
+            }
+        })
+        .collect())
+}
+
+/// The firewall rule blocking the traffic of an exec entry, named after its path
+fn firewall_rule(op: &Exec) -> FirewallRule {
+    FirewallRule {
+        name: op.path.clone(),
+        program: op.program.clone(),
+        service: op.service.clone(),
+        remote_addresses: op.remote_addresses.clone(),
+        direction: op.direction.unwrap_or(Direction::Outbound),
+        block: true,
+        enabled: true,
+    }
+}
 
+/// Add the firewall rule of an exec entry, or remove it. A rule with the same name that
+/// filters other traffic is replaced.
+fn set_firewall(firewall: &mut dyn Firewall, op: &Exec, blocked: bool) -> Result<(), DejunkerError> {
+    let wanted = firewall_rule(op);
+    match firewall.rule(&op.path)? {
+        Some(current) if blocked && current.same_filter(&wanted) => {}
+        Some(_) if blocked => {
+            debug!("Replacing firewall rule {}", op.path);
+            firewall.remove(&op.path)?;
+            firewall.add(&wanted)?;
+        }
+        Some(_) => {
+            debug!("Removing firewall rule {}", op.path);
+            firewall.remove(&op.path)?;
+        }
+        None if blocked => {
+            debug!("Adding firewall rule {}", op.path);
+            firewall.add(&wanted)?;
+        }
+        None => {}
+    }
     Ok(())
 }
+
+/// The value of the rule an exec entry is in, from whether its firewall rule is there. A
+/// firewall rule with the same name that filters other traffic is unknown.
+fn firewall_state(firewall: &dyn Firewall, rule: &Rule, op: &Exec) -> Result<EntryState, DejunkerError> {
+    let blocked = match firewall.rule(&op.path)? {
+        Some(current) if current.same_filter(&firewall_rule(op)) => true,
+        Some(_) => return Ok(EntryState::Unknown("different rule".to_string())),
+        None => false,
+    };
+
+    Ok(FIREWALL_MAPPING.entry_state(rule, op, blocked))
+}
+
+/// The error for an entry of a subsystem that is evaluated or applied without its backend
+fn not_accessible(op: &Exec) -> DejunkerError {
+    match op.subsystem.as_str() {
+        "hosts" => DejunkerError::AccessDenied("the hosts file is not accessible".to_string()),
+        _ => DejunkerError::AccessDenied(format!("{} {} is not accessible", op.subsystem, op.path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::db::Value;
+    use crate::test_support::{registry_exec, rule, rules, Machine};
+    use std::collections::BTreeMap;
+
+    const KEY: &str = "HKCU\\Software\\Microsoft\\Windows\\CurrentVersion\\AdvertisingInfo";
+
+    const RULE: &str = "advertising-id";
+
+    fn onoff_rule(reversed: Option<bool>) -> HashMap<String, Rule> {
+        registry_rule(
+            "OnOff",
+            Exec {
+                reversed,
+                ..registry_exec(KEY, "Enabled", "u32")
+            },
+        )
+    }
+
+    fn registry_rule(value_type: &str, exec: Exec) -> HashMap<String, Rule> {
+        rules([rule(RULE, value_type, vec![exec])])
+    }
+
+    #[test]
+    fn onoff_rules_write_one_and_zero() {
+        let rules = onoff_rule(None);
+        let mut machine = Machine::new();
+        assert_eq!(machine.state(&rules, RULE), RuleState::NotConfigured);
+
+        machine.set(&rules, RULE, "off").unwrap();
+        assert_eq!(machine.registry.get_value(KEY, "Enabled").unwrap(), Some(RegistryValue::Dword(0)));
+        assert_eq!(machine.state(&rules, RULE), RuleState::Value("off".to_string()));
+
+        machine.set(&rules, RULE, "on").unwrap();
+        assert_eq!(machine.registry.get_value(KEY, "Enabled").unwrap(), Some(RegistryValue::Dword(1)));
+        assert_eq!(machine.state(&rules, RULE), RuleState::Value("on".to_string()));
+
+        assert!(matches!(machine.set(&rules, RULE, "maybe"), Err(DejunkerError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn reversed_rules_write_the_opposite() {
+        let rules = onoff_rule(Some(true));
+        let mut machine = Machine::new();
+
+        machine.set(&rules, RULE, "off").unwrap();
+        assert_eq!(machine.registry.get_value(KEY, "Enabled").unwrap(), Some(RegistryValue::Dword(1)));
+        assert_eq!(machine.state(&rules, RULE), RuleState::Value("off".to_string()));
+
+        // anything but 0 is on, before reversing
+        machine.registry.set_u32_value(KEY, "Enabled", 2).unwrap();
+        assert_eq!(machine.state(&rules, RULE), RuleState::Value("off".to_string()));
+        machine.registry.set_u32_value(KEY, "Enabled", 0).unwrap();
+        assert_eq!(machine.state(&rules, RULE), RuleState::Value("on".to_string()));
+    }
+
+    #[test]
+    fn missing_values_are_in_the_value_that_deletes_them() {
+        let rules = registry_rule(
+            "OnOff",
+            Exec {
+                values: BTreeMap::from([
+                    ("on".to_string(), ExecAction::DeleteValue),
+                    ("off".to_string(), ExecAction::Write("0".to_string())),
+                ]),
+                ..registry_exec(KEY, "Enabled", "u32")
+            },
+        );
+        let mut machine = Machine::new();
+        assert_eq!(machine.state(&rules, RULE), RuleState::Value("on".to_string()));
+
+        machine.set(&rules, RULE, "off").unwrap();
+        assert_eq!(machine.state(&rules, RULE), RuleState::Value("off".to_string()));
+        machine.set(&rules, RULE, "on").unwrap();
+        assert_eq!(machine.registry.get_value(KEY, "Enabled").unwrap(), None);
+
+        // data no value maps to
+        machine.registry.set_u32_value(KEY, "Enabled", 5).unwrap();
+        assert!(matches!(machine.state(&rules, RULE), RuleState::Mixed(_)));
+    }
+
+    #[test]
+    fn values_are_read_back_as_written() {
+        let cases = [
+            ("integer", "u32", "4294967295", RegistryValue::Dword(u32::MAX)),
+            ("integer", "i32", "-1", RegistryValue::Dword(u32::MAX)),
+            ("integer", "i64", "-2", RegistryValue::Qword(u64::MAX - 1)),
+            ("integer", "u64", "4294967296", RegistryValue::Qword(1 << 32)),
+            ("string", "string", "Contoso", RegistryValue::String("Contoso".to_string())),
+            (
+                "string",
+                "expand_string",
+                "%SystemRoot%\\web",
+                RegistryValue::ExpandString("%SystemRoot%\\web".to_string()),
+            ),
+        ];
+
+        for (rule_type, value_type, value, written) in cases {
+            let rules = registry_rule(
+                rule_type,
+                registry_exec(KEY, "Id", value_type),
+            );
+            let mut machine = Machine::new();
+            machine.set(&rules, RULE, value).unwrap();
+            assert_eq!(machine.registry.get_value(KEY, "Id").unwrap(), Some(written));
+            assert_eq!(machine.state(&rules, RULE), RuleState::Value(value.to_string()));
+        }
+    }
+
+    #[test]
+    fn negative_numbers_are_not_written_as_unsigned() {
+        for value_type in ["u32", "u64"] {
+            let rules = registry_rule("integer", registry_exec(KEY, "Id", value_type));
+            let mut machine = Machine::new();
+            assert!(matches!(machine.set(&rules, RULE, "-1"), Err(DejunkerError::InvalidValue { .. })));
+            assert_eq!(machine.registry.get_value(KEY, "Id").unwrap(), None);
+
+            // what is written reads back as the same number
+            machine.set(&rules, RULE, "7").unwrap();
+            let op = &rules[RULE].exec[0];
+            let written = machine.registry.get_value(KEY, "Id").unwrap().unwrap();
+            assert_eq!(exec_value(&rules[RULE], op, "7").unwrap(), written);
+            assert_eq!(exec_data(op, &written).unwrap(), Some("7".to_string()));
+        }
+    }
+
+    #[test]
+    fn binary_data_is_read_back_as_written() {
+        let rules = registry_rule("string", registry_exec(KEY, "Id", "binary"));
+        let op = &rules[RULE].exec[0];
+        let value = exec_value(&rules[RULE], op, "00ff10").unwrap();
+        assert_eq!(value, RegistryValue::Binary(vec![0x00, 0xff, 0x10]));
+        assert_eq!(exec_data(op, &value).unwrap(), Some("00ff10".to_string()));
+        assert!(matches!(exec_value(&rules[RULE], op, "0g"), Err(DejunkerError::InvalidValue { .. })));
+
+        let mut machine = Machine::new();
+        machine.set(&rules, RULE, "00ff10").unwrap();
+        assert_eq!(machine.registry.get_value(KEY, "Id").unwrap(), Some(value));
+        assert_eq!(machine.state(&rules, RULE), RuleState::Value("00ff10".to_string()));
+    }
+
+    #[test]
+    fn items_are_added_and_removed_leaving_the_others_in_order() {
+        let rules = registry_rule(
+            "OnOff",
+            Exec {
+                item: Some("Suggestions".to_string()),
+                ..registry_exec(KEY, "HiddenItems", "multi_string")
+            },
+        );
+        let items = |items: &[&str]| Some(RegistryValue::MultiString(items.iter().map(|i| i.to_string()).collect()));
+        let mut machine = Machine::new();
+
+        // a missing list does not have the item, and removing it does not create the list
+        assert_eq!(machine.state(&rules, RULE), RuleState::Value("off".to_string()));
+        machine.set(&rules, RULE, "off").unwrap();
+        assert_eq!(machine.registry.get_value(KEY, "HiddenItems").unwrap(), None);
+        machine.set(&rules, RULE, "on").unwrap();
+        assert_eq!(machine.registry.get_value(KEY, "HiddenItems").unwrap(), items(&["Suggestions"]));
+
+        let value = RegistryValue::MultiString(vec!["Ads".to_string(), "News".to_string()]);
+        machine.registry.set_value(KEY, "HiddenItems", &value).unwrap();
+        assert_eq!(machine.state(&rules, RULE), RuleState::Value("off".to_string()));
+        machine.set(&rules, RULE, "on").unwrap();
+        assert_eq!(machine.registry.get_value(KEY, "HiddenItems").unwrap(), items(&["Ads", "News", "Suggestions"]));
+        assert_eq!(machine.state(&rules, RULE), RuleState::Value("on".to_string()));
+        // already there, whatever its case
+        machine.registry.set_value(KEY, "HiddenItems", &items(&["Ads", "suggestions", "News"]).unwrap()).unwrap();
+        machine.set(&rules, RULE, "on").unwrap();
+        assert_eq!(machine.registry.get_value(KEY, "HiddenItems").unwrap(), items(&["Ads", "suggestions", "News"]));
+
+        machine.set(&rules, RULE, "off").unwrap();
+        assert_eq!(machine.registry.get_value(KEY, "HiddenItems").unwrap(), items(&["Ads", "News"]));
+        assert_eq!(machine.state(&rules, RULE), RuleState::Value("off".to_string()));
+
+        // a value that is not a list is left alone
+        machine.registry.set_u32_value(KEY, "HiddenItems", 1).unwrap();
+        assert!(matches!(machine.state(&rules, RULE), RuleState::Mixed(_)));
+        assert!(machine.set(&rules, RULE, "on").is_err());
+        assert_eq!(machine.registry.get_value(KEY, "HiddenItems").unwrap(), Some(RegistryValue::Dword(1)));
+    }
+
+    #[test]
+    fn machine_wide_entries_are_left_out_for_later_users() {
+        let policy = "HKLM\\SOFTWARE\\Policies\\Microsoft\\Windows\\AdvertisingInfo";
+        let mut rules = onoff_rule(None);
+        rules.get_mut(RULE).unwrap().exec.push(Exec {
+            reversed: Some(true),
+            ..registry_exec(policy, "DisabledByGroupPolicy", "u32")
+        });
+        let mut machine = Machine::new();
+        machine.user_entries_only = true;
+
+        machine.set(&rules, RULE, "off").unwrap();
+        assert_eq!(machine.registry.get_value(KEY, "Enabled").unwrap(), Some(RegistryValue::Dword(0)));
+        assert_eq!(machine.registry.get_value(policy, "DisabledByGroupPolicy").unwrap(), None);
+    }
+
+    #[test]
+    fn entries_that_disagree_are_mixed() {
+        let policy = "HKLM\\SOFTWARE\\Policies\\Microsoft\\Windows\\AdvertisingInfo";
+        let mut rules = onoff_rule(None);
+        rules.get_mut(RULE).unwrap().exec.push(Exec {
+            reversed: Some(true),
+            ..registry_exec(policy, "DisabledByGroupPolicy", "u32")
+        });
+        let mut machine = Machine::new();
+        assert_eq!(machine.state(&rules, RULE), RuleState::NotConfigured);
+
+        // only some of the entries are configured
+        machine.registry.set_u32_value(KEY, "Enabled", 0).unwrap();
+        match machine.state(&rules, RULE) {
+            RuleState::Mixed(entries) => {
+                let states: Vec<&EntryState> = entries.iter().map(|entry| &entry.state).collect();
+                assert_eq!(states, vec![&EntryState::Value("off".to_string()), &EntryState::NotConfigured]);
+            }
+            other => panic!("unexpected state {:?}", other),
+        }
+
+        // the entries disagree
+        machine.registry.set_u32_value(policy, "DisabledByGroupPolicy", 0).unwrap();
+        match machine.state(&rules, RULE) {
+            RuleState::Mixed(entries) => {
+                let states: Vec<&EntryState> = entries.iter().map(|entry| &entry.state).collect();
+                assert_eq!(
+                    states,
+                    vec![&EntryState::Value("off".to_string()), &EntryState::Value("on".to_string())]
+                );
+            }
+            other => panic!("unexpected state {:?}", other),
+        }
+
+        machine.set(&rules, RULE, "off").unwrap();
+        assert_eq!(machine.state(&rules, RULE), RuleState::Value("off".to_string()));
+    }
+
+    #[test]
+    fn enum_rules_only_take_their_choices() {
+        const DATA_COLLECTION: &str = "HKLM\\SOFTWARE\\Policies\\Microsoft\\Windows\\DataCollection";
+        let mut rules = registry_rule(
+            "Enum",
+            Exec {
+                values: BTreeMap::from([
+                    ("security".to_string(), ExecAction::Write("0".to_string())),
+                    ("basic".to_string(), ExecAction::Write("1".to_string())),
+                    ("full".to_string(), ExecAction::Write("3".to_string())),
+                ]),
+                ..registry_exec(DATA_COLLECTION, "AllowTelemetry", "u32")
+            },
+        );
+        rules.get_mut(RULE).unwrap().value.choices =
+            vec!["security".to_string(), "basic".to_string(), "full".to_string()];
+        let path = DATA_COLLECTION;
+        let mut machine = Machine::new();
+
+        machine.set(&rules, RULE, "basic").unwrap();
+        assert_eq!(machine.registry.get_value(path, "AllowTelemetry").unwrap(), Some(RegistryValue::Dword(1)));
+        assert_eq!(machine.state(&rules, RULE), RuleState::Value("basic".to_string()));
+
+        assert!(matches!(machine.set(&rules, RULE, "enhanced"), Err(DejunkerError::InvalidValue { .. })));
+        assert!(matches!(machine.set(&rules, RULE, "on"), Err(DejunkerError::InvalidValue { .. })));
+        assert_eq!(machine.registry.get_value(path, "AllowTelemetry").unwrap(), Some(RegistryValue::Dword(1)));
+
+        // data none of the choices maps to
+        machine.registry.set_u32_value(path, "AllowTelemetry", 2).unwrap();
+        match machine.state(&rules, RULE) {
+            RuleState::Mixed(entries) => assert!(matches!(entries[0].state, EntryState::Unknown(_))),
+            other => panic!("unexpected state {:?}", other),
+        }
+    }
+
+    #[test]
+    fn integers_and_strings_are_checked_before_writing() {
+        let integer = Value {
+            value_type: "Integer".to_string(),
+            min: Some(1),
+            max: Some(60),
+            ..Value::default()
+        };
+        assert_eq!(integer.check("1"), Ok(()));
+        assert_eq!(integer.check("60"), Ok(()));
+        assert_eq!(integer.check("0"), Err("0 is less than 1".to_string()));
+        assert_eq!(integer.check("61"), Err("61 is more than 60".to_string()));
+        assert_eq!(integer.check("ten"), Err("'ten' is not a whole number".to_string()));
+
+        let string = Value {
+            value_type: "String".to_string(),
+            pattern: Some("[A-Z]{2}".to_string()),
+            ..Value::default()
+        };
+        assert_eq!(string.check("FR"), Ok(()));
+        // the whole string has to match
+        assert_eq!(string.check("FRA"), Err("'FRA' does not match [A-Z]{2}".to_string()));
+
+        let mut rules = registry_rule(
+            "Integer",
+            registry_exec(KEY, "Timeout", "u32"),
+        );
+        rules.get_mut(RULE).unwrap().value = integer;
+        let mut machine = Machine::new();
+        machine.set(&rules, RULE, "30").unwrap();
+        assert!(matches!(machine.set(&rules, RULE, "61"), Err(DejunkerError::InvalidValue { .. })));
+        assert_eq!(machine.registry.get_value(KEY, "Timeout").unwrap(), Some(RegistryValue::Dword(30)));
+
+        let mut rules = registry_rule(
+            "String",
+            registry_exec(KEY, "Country", "string"),
+        );
+        rules.get_mut(RULE).unwrap().value = string;
+        assert!(matches!(machine.set(&rules, RULE, "france"), Err(DejunkerError::InvalidValue { .. })));
+        assert_eq!(machine.registry.get_value(KEY, "Country").unwrap(), None);
+    }
+}
@@ -0,0 +1,38 @@
+use super::{Firewall, FirewallRule};
+use crate::error::DejunkerError;
+use std::collections::BTreeMap;
+use std::result::Result;
+
+/// Firewall rules that only exist in memory, indexed by their lowercase name
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFirewall {
+    rules: BTreeMap<String, FirewallRule>,
+}
+
+impl MemoryFirewall {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Firewall for MemoryFirewall {
+    fn rule(&self, name: &str) -> Result<Option<FirewallRule>, DejunkerError> {
+        Ok(self.rules.get(&name.to_lowercase()).cloned())
+    }
+
+    fn add(&mut self, rule: &FirewallRule) -> Result<(), DejunkerError> {
+        let key = rule.name.to_lowercase();
+        if self.rules.contains_key(&key) {
+            return Err(DejunkerError::system(format!("Firewall rule {} already exists", rule.name)));
+        }
+        self.rules.insert(key, rule.clone());
+        Ok(())
+    }
+
+    fn remove(&mut self, name: &str) -> Result<(), DejunkerError> {
+        self.rules
+            .remove(&name.to_lowercase())
+            .map(|_| ())
+            .ok_or_else(|| DejunkerError::system(format!("Firewall rule {} does not exist", name)))
+    }
+}
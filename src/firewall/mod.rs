@@ -0,0 +1,299 @@
+use crate::error::DejunkerError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::result::Result;
+
+mod memory;
+pub mod recorder;
+#[cfg(windows)]
+mod win32;
+
+pub use memory::MemoryFirewall;
+#[cfg(windows)]
+pub use win32::Win32Firewall;
+
+/// The data of firewall entries, as written in the rules database
+pub const BLOCKED: &str = "blocked";
+pub const ALLOWED: &str = "allowed";
+
+/// The traffic a firewall rule filters
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+impl Direction {
+    /// Parse a direction as written in the rules database (inbound or outbound)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "inbound" => Some(Direction::Inbound),
+            "outbound" => Some(Direction::Outbound),
+            _ => None,
+        }
+    }
+
+    /// The name of the direction, as written in the rules database
+    pub fn name(&self) -> &'static str {
+        match self {
+            Direction::Inbound => "inbound",
+            Direction::Outbound => "outbound",
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A rule of the firewall, with the conditions dejunker knows about. Traffic matching all of
+/// them is filtered.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FirewallRule {
+    /// The name the rule is found by
+    pub name: String,
+    /// The path of the program whose traffic is filtered, None for any program
+    pub program: Option<String>,
+    /// The short name of the service whose traffic is filtered, None for any service
+    pub service: Option<String>,
+    /// The addresses (or ranges, or subnets) at the other end, empty for any
+    pub remote_addresses: Vec<String>,
+    pub direction: Direction,
+    /// Whether matching traffic is blocked (or allowed)
+    pub block: bool,
+    pub enabled: bool,
+}
+
+impl FirewallRule {
+    /// Whether another rule filters the same traffic the same way. Names are not compared,
+    /// paths and service names are compared case insensitively, and addresses in their
+    /// canonical form (see normalize_address) in any order.
+    pub fn same_filter(&self, other: &FirewallRule) -> bool {
+        let same = |a: &Option<String>, b: &Option<String>| match (a, b) {
+            (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        let addresses = |rule: &FirewallRule| {
+            let mut addresses: Vec<String> =
+                rule.remote_addresses.iter().map(|address| normalize_address(address)).collect();
+            addresses.sort();
+            addresses.dedup();
+            addresses
+        };
+
+        same(&self.program, &other.program)
+            && same(&self.service, &other.service)
+            && addresses(self) == addresses(other)
+            && self.direction == other.direction
+            && self.block == other.block
+            && self.enabled == other.enabled
+    }
+}
+
+/// The canonical form of a remote address of a firewall rule, the way Windows reports it back
+/// aside: subnets with a prefix length (`10.0.0.0/255.0.0.0` is `10.0.0.0/8`), single
+/// addresses without one (`13.107.4.50/32` is `13.107.4.50`), ranges of a single address as
+/// that address, IPv6 addresses compressed and keywords (e.g. LocalSubnet) in lower case.
+pub fn normalize_address(address: &str) -> String {
+    let address = address.trim().to_lowercase();
+    if let Some((first, last)) = address.split_once('-') {
+        let (first, last) = (normalize_address(first), normalize_address(last));
+        return if first == last { first } else { format!("{}-{}", first, last) };
+    }
+
+    let (ip, prefix) = match address.split_once('/') {
+        Some((ip, mask)) => (ip, Some(mask)),
+        None => (address.as_str(), None),
+    };
+    let Ok(ip) = ip.parse::<IpAddr>() else {
+        return address;
+    };
+    let bits = if ip.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        None => bits,
+        Some(prefix) => match prefix.parse::<u32>().ok().or_else(|| mask_length(prefix)) {
+            Some(prefix) if prefix <= bits => prefix,
+            _ => return address,
+        },
+    };
+    match prefix == bits {
+        true => ip.to_string(),
+        false => format!("{}/{}", ip, prefix),
+    }
+}
+
+/// The prefix length of an IPv4 subnet mask (e.g. 16 for 255.255.0.0), None if it is not a
+/// contiguous mask
+fn mask_length(mask: &str) -> Option<u32> {
+    let mask = u32::from(mask.parse::<Ipv4Addr>().ok()?);
+    let length = mask.leading_ones();
+    (mask.checked_shl(length).unwrap_or(0) == 0).then_some(length)
+}
+
+/// Access to the rules of a firewall. Rules are found by name, case insensitively.
+pub trait Firewall {
+    /// The rule with a name, None if there is no such rule
+    fn rule(&self, name: &str) -> Result<Option<FirewallRule>, DejunkerError>;
+
+    /// Add a rule
+    fn add(&mut self, rule: &FirewallRule) -> Result<(), DejunkerError>;
+
+    /// Remove the rule with a name
+    fn remove(&mut self, name: &str) -> Result<(), DejunkerError>;
+}
+
+/// Parse whether traffic is blocked, as written in the rules database (blocked or allowed)
+pub fn parse_blocked(name: &str) -> Option<bool> {
+    match name.to_lowercase().as_str() {
+        BLOCKED => Some(true),
+        ALLOWED => Some(false),
+        _ => None,
+    }
+}
+
+/// The name of a blocked state, as written in the rules database
+pub fn blocked_name(blocked: bool) -> &'static str {
+    if blocked { BLOCKED } else { ALLOWED }
+}
+
+/// The firewall of the machine we are running on.
+#[cfg(windows)]
+pub fn local_firewall() -> Result<Box<dyn Firewall>, DejunkerError> {
+    Ok(Box::new(Win32Firewall::new()?))
+}
+
+/// The firewall of the machine we are running on. There is no Windows firewall outside of
+/// Windows.
+#[cfg(not(windows))]
+pub fn local_firewall() -> Result<Box<dyn Firewall>, DejunkerError> {
+    Err(DejunkerError::system("The firewall is only available on Windows"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::db::{Exec, Rule};
+    use crate::test_support::{rule, rules, Machine};
+    use crate::types::state::{EntryState, RuleState};
+    use std::collections::HashMap;
+
+    const NAME: &str = "dejunker - CompatTelRunner";
+    const PROGRAM: &str = "%SystemRoot%\\System32\\CompatTelRunner.exe";
+
+    fn firewall_rule(remote_addresses: &[&str]) -> HashMap<String, Rule> {
+        let exec = Exec {
+            subsystem: "firewall".to_string(),
+            path: NAME.to_string(),
+            program: Some(PROGRAM.to_string()),
+            remote_addresses: remote_addresses.iter().map(|address| address.to_string()).collect(),
+            ..Exec::default()
+        };
+        rules([Rule {
+            admin_required: true,
+            ..rule("compattelrunner", "OnOff", vec![exec])
+        }])
+    }
+
+    fn blocking(program: &str, remote_addresses: &[&str]) -> FirewallRule {
+        FirewallRule {
+            name: NAME.to_string(),
+            program: Some(program.to_string()),
+            service: None,
+            remote_addresses: remote_addresses.iter().map(|address| address.to_string()).collect(),
+            direction: Direction::Outbound,
+            block: true,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn addresses_are_normalized_as_windows_reports_them() {
+        for (address, normalized) in [
+            ("10.0.0.0/255.0.0.0", "10.0.0.0/8"),
+            ("10.0.0.0/8", "10.0.0.0/8"),
+            ("172.16.0.0/255.240.0.0", "172.16.0.0/12"),
+            ("13.107.4.50/255.255.255.255", "13.107.4.50"),
+            ("13.107.4.50/32", "13.107.4.50"),
+            (" 13.107.4.50 ", "13.107.4.50"),
+            ("10.0.0.1-10.0.0.9", "10.0.0.1-10.0.0.9"),
+            ("10.0.0.1/255.255.255.255-10.0.0.1", "10.0.0.1"),
+            ("2001:DB8:0:0::1/128", "2001:db8::1"),
+            ("2001:db8::/32", "2001:db8::/32"),
+            ("LocalSubnet", "localsubnet"),
+            ("DefaultGateway", "defaultgateway"),
+            // not a contiguous mask, or too long a prefix: left as it is
+            ("10.0.0.0/255.0.255.0", "10.0.0.0/255.0.255.0"),
+            ("10.0.0.0/33", "10.0.0.0/33"),
+        ] {
+            assert_eq!(normalize_address(address), normalized, "{}", address);
+        }
+    }
+
+    #[test]
+    fn rules_are_compared_on_what_they_filter() {
+        let rule = blocking(PROGRAM, &["13.107.4.50", "10.0.0.0/8"]);
+        assert!(rule.same_filter(&blocking(&PROGRAM.to_uppercase(), &["10.0.0.0/8", "13.107.4.50"])));
+        assert!(rule.same_filter(&blocking(PROGRAM, &["10.0.0.0/255.0.0.0", "13.107.4.50/255.255.255.255"])));
+        assert!(rule.same_filter(&FirewallRule {
+            name: "other name".to_string(),
+            ..rule.clone()
+        }));
+
+        assert!(!rule.same_filter(&blocking(PROGRAM, &["13.107.4.50"])));
+        assert!(!rule.same_filter(&FirewallRule {
+            direction: Direction::Inbound,
+            ..rule.clone()
+        }));
+        assert!(!rule.same_filter(&FirewallRule {
+            enabled: false,
+            ..rule.clone()
+        }));
+        assert!(!rule.same_filter(&FirewallRule {
+            service: Some("DiagTrack".to_string()),
+            ..rule.clone()
+        }));
+    }
+
+    #[test]
+    fn off_adds_an_outbound_blocking_rule() {
+        let rules = firewall_rule(&["13.107.4.50"]);
+        let mut machine = Machine::new();
+        assert_eq!(machine.state(&rules, "compattelrunner"), RuleState::Value("on".to_string()));
+
+        machine.set(&rules, "compattelrunner", "off").unwrap();
+        assert_eq!(machine.firewall.rule(NAME).unwrap(), Some(blocking(PROGRAM, &["13.107.4.50"])));
+        assert_eq!(machine.state(&rules, "compattelrunner"), RuleState::Value("off".to_string()));
+
+        machine.set(&rules, "compattelrunner", "on").unwrap();
+        assert_eq!(machine.firewall.rule(NAME).unwrap(), None);
+    }
+
+    #[test]
+    fn a_different_rule_with_the_name_is_replaced() {
+        let rules = firewall_rule(&[]);
+        let mut machine = Machine::new();
+        machine.firewall.add(&blocking("C:\\Windows\\System32\\other.exe", &[])).unwrap();
+        match machine.state(&rules, "compattelrunner") {
+            RuleState::Mixed(entries) => {
+                assert_eq!(entries[0].state, EntryState::Unknown("different rule".to_string()))
+            }
+            other => panic!("unexpected state {:?}", other),
+        }
+
+        machine.set(&rules, "compattelrunner", "off").unwrap();
+        assert_eq!(machine.firewall.rule(NAME).unwrap(), Some(blocking(PROGRAM, &[])));
+    }
+
+    #[test]
+    fn plans_leave_the_firewall_alone() {
+        let rules = firewall_rule(&[]);
+        let mut machine = Machine::new();
+        let plan = machine.plan(&rules, "compattelrunner", "off");
+        let changes: Vec<String> = plan.subsystem_changes.iter().map(|change| change.to_string()).collect();
+        assert_eq!(changes, vec![format!("firewall rule {}: added", NAME)]);
+        assert_eq!(machine.firewall.rule(NAME).unwrap(), None);
+    }
+}
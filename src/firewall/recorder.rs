@@ -0,0 +1,72 @@
+use super::{Firewall, FirewallRule};
+use crate::error::DejunkerError;
+use std::fmt;
+use std::result::Result;
+
+/// A modification made to the rules of the firewall
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum FirewallChange {
+    Add { name: String },
+    Remove { name: String },
+}
+
+impl fmt::Display for FirewallChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FirewallChange::Add { name } => write!(f, "firewall rule {}: added", name),
+            FirewallChange::Remove { name } => write!(f, "firewall rule {}: removed", name),
+        }
+    }
+}
+
+/// Wraps another firewall, and keeps a record of every modification made through it. Queries
+/// go to the wrapped firewall. When `apply` is false, modifications are only recorded and the
+/// firewall rules are left alone.
+pub struct RecordingFirewall<'a> {
+    inner: &'a mut dyn Firewall,
+    apply: bool,
+    changes: Vec<FirewallChange>,
+}
+
+impl<'a> RecordingFirewall<'a> {
+    pub fn new(inner: &'a mut dyn Firewall, apply: bool) -> Self {
+        RecordingFirewall {
+            inner,
+            apply,
+            changes: Vec::new(),
+        }
+    }
+
+    /// All modifications, in the order they were made
+    pub fn changes(&self) -> &[FirewallChange] {
+        &self.changes
+    }
+
+    pub fn into_changes(self) -> Vec<FirewallChange> {
+        self.changes
+    }
+}
+
+impl Firewall for RecordingFirewall<'_> {
+    fn rule(&self, name: &str) -> Result<Option<FirewallRule>, DejunkerError> {
+        self.inner.rule(name)
+    }
+
+    fn add(&mut self, rule: &FirewallRule) -> Result<(), DejunkerError> {
+        if self.apply {
+            self.inner.add(rule)?;
+        }
+        self.changes.push(FirewallChange::Add {
+            name: rule.name.clone(),
+        });
+        Ok(())
+    }
+
+    fn remove(&mut self, name: &str) -> Result<(), DejunkerError> {
+        if self.apply {
+            self.inner.remove(name)?;
+        }
+        self.changes.push(FirewallChange::Remove { name: name.to_string() });
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use super::{Direction, Firewall, FirewallRule};
+use crate::error::DejunkerError;
+use crate::utils::{error_code, system_error};
+use log::debug;
+use std::result::Result;
+use windows::core::BSTR;
+use windows::Win32::Foundation::{ERROR_FILE_NOT_FOUND, VARIANT_BOOL};
+use windows::Win32::NetworkManagement::WindowsFirewall::{
+    INetFwPolicy2, INetFwRule, INetFwRules, NetFwPolicy2, NetFwRule, NET_FW_ACTION_ALLOW, NET_FW_ACTION_BLOCK,
+    NET_FW_RULE_DIR_IN, NET_FW_RULE_DIR_OUT,
+};
+use windows::Win32::System::Com::{CoCreateInstance, CoInitializeEx, CLSCTX_INPROC_SERVER, COINIT_MULTITHREADED};
+
+// the rules added by dejunker are grouped together in the firewall console
+const GROUPING: &str = "dejunker";
+
+// how "any address" is reported back
+const ANY_ADDRESS: &str = "*";
+
+/// The firewall of the machine we are running on, accessed through the Windows Firewall COM
+/// API. Changing its rules needs an elevated process.
+pub struct Win32Firewall {
+    rules: INetFwRules,
+}
+
+impl Win32Firewall {
+    /// Open the firewall policy
+    pub fn new() -> Result<Self, DejunkerError> {
+        unsafe {
+            // fails harmlessly if COM was already initialized on this thread
+            _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+            let policy: INetFwPolicy2 = CoCreateInstance(&NetFwPolicy2, None, CLSCTX_INPROC_SERVER)
+                .map_err(|e| system_error("Failed to open the firewall policy".to_string(), &e))?;
+            let rules = policy
+                .Rules()
+                .map_err(|e| system_error("Failed to open the firewall rules".to_string(), &e))?;
+            Ok(Win32Firewall { rules })
+        }
+    }
+}
+
+impl Firewall for Win32Firewall {
+    fn rule(&self, name: &str) -> Result<Option<FirewallRule>, DejunkerError> {
+        let rule = match unsafe { self.rules.Item(&BSTR::from(name)) } {
+            Ok(rule) => rule,
+            Err(e) if error_code(&e) == ERROR_FILE_NOT_FOUND => return Ok(None),
+            Err(e) => return Err(system_error(format!("Failed to open firewall rule {}", name), &e)),
+        };
+        let failed = |e: windows::core::Error| system_error(format!("Failed to read firewall rule {}", name), &e);
+
+        unsafe {
+            let optional = |value: BSTR| Some(value.to_string()).filter(|value| !value.is_empty());
+            let remote_addresses = rule.RemoteAddresses().map_err(failed)?.to_string();
+            Ok(Some(FirewallRule {
+                name: rule.Name().map_err(failed)?.to_string(),
+                program: optional(rule.ApplicationName().map_err(failed)?),
+                service: optional(rule.ServiceName().map_err(failed)?),
+                remote_addresses: remote_addresses
+                    .split(',')
+                    .filter(|address| !address.is_empty() && *address != ANY_ADDRESS)
+                    .map(super::normalize_address)
+                    .collect(),
+                direction: match rule.Direction().map_err(failed)? {
+                    NET_FW_RULE_DIR_IN => Direction::Inbound,
+                    _ => Direction::Outbound,
+                },
+                block: rule.Action().map_err(failed)? == NET_FW_ACTION_BLOCK,
+                enabled: rule.Enabled().map_err(failed)?.as_bool(),
+            }))
+        }
+    }
+
+    fn add(&mut self, rule: &FirewallRule) -> Result<(), DejunkerError> {
+        let failed = |e: windows::core::Error| system_error(format!("Failed to add firewall rule {}", rule.name), &e);
+
+        unsafe {
+            let native: INetFwRule = CoCreateInstance(&NetFwRule, None, CLSCTX_INPROC_SERVER).map_err(failed)?;
+            native.SetName(&BSTR::from(rule.name.as_str())).map_err(failed)?;
+            native.SetGrouping(&BSTR::from(GROUPING)).map_err(failed)?;
+            if let Some(program) = &rule.program {
+                native.SetApplicationName(&BSTR::from(program.as_str())).map_err(failed)?;
+            }
+            if let Some(service) = &rule.service {
+                native.SetServiceName(&BSTR::from(service.as_str())).map_err(failed)?;
+            }
+            if !rule.remote_addresses.is_empty() {
+                native
+                    .SetRemoteAddresses(&BSTR::from(rule.remote_addresses.join(",")))
+                    .map_err(failed)?;
+            }
+            native
+                .SetDirection(match rule.direction {
+                    Direction::Inbound => NET_FW_RULE_DIR_IN,
+                    Direction::Outbound => NET_FW_RULE_DIR_OUT,
+                })
+                .map_err(failed)?;
+            native
+                .SetAction(if rule.block { NET_FW_ACTION_BLOCK } else { NET_FW_ACTION_ALLOW })
+                .map_err(failed)?;
+            native.SetEnabled(VARIANT_BOOL::from(rule.enabled)).map_err(failed)?;
+            self.rules.Add(&native).map_err(failed)?;
+        }
+        debug!("Added firewall rule {}", rule.name);
+        Ok(())
+    }
+
+    fn remove(&mut self, name: &str) -> Result<(), DejunkerError> {
+        unsafe { self.rules.Remove(&BSTR::from(name)) }
+            .map_err(|e| system_error(format!("Failed to remove firewall rule {}", name), &e))?;
+        debug!("Removed firewall rule {}", name);
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+use super::HostsFile;
+use crate::error::DejunkerError;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::result::Result;
+
+/// A hosts file on disk, e.g. that of the machine we are running on (see default_path), or
+/// that of an offline image. Writing the hosts file of Windows needs an elevated process.
+pub struct FileHosts {
+    path: PathBuf,
+}
+
+impl FileHosts {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileHosts { path: path.into() }
+    }
+}
+
+impl HostsFile for FileHosts {
+    fn read(&self) -> Result<String, DejunkerError> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => Ok(contents),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(DejunkerError::io(&self.path)(e)),
+        }
+    }
+
+    fn write(&mut self, contents: &str) -> Result<(), DejunkerError> {
+        match fs::write(&self.path, contents) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => Err(DejunkerError::AccessDenied(format!(
+                "the hosts file {} cannot be written",
+                self.path.display()
+            ))),
+            Err(e) => Err(DejunkerError::io(&self.path)(e)),
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use super::HostsFile;
+use crate::error::DejunkerError;
+use std::result::Result;
+
+/// A hosts file that only exists in memory
+#[derive(Debug, Clone, Default)]
+pub struct MemoryHosts {
+    contents: String,
+}
+
+impl MemoryHosts {
+    pub fn new(contents: &str) -> Self {
+        MemoryHosts {
+            contents: contents.to_string(),
+        }
+    }
+}
+
+impl HostsFile for MemoryHosts {
+    fn read(&self) -> Result<String, DejunkerError> {
+        Ok(self.contents.clone())
+    }
+
+    fn write(&mut self, contents: &str) -> Result<(), DejunkerError> {
+        self.contents = contents.to_string();
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+use crate::error::DejunkerError;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::result::Result;
+
+mod file;
+mod memory;
+pub mod recorder;
+
+pub use file::FileHosts;
+pub use memory::MemoryHosts;
+
+/// The data of hosts entries, as written in the rules database
+pub const BLOCKED: &str = "blocked";
+pub const ALLOWED: &str = "allowed";
+
+/// The lines around the part of the hosts file managed by dejunker
+pub const BEGIN_MARKER: &str = "# BEGIN dejunker";
+pub const END_MARKER: &str = "# END dejunker";
+
+/// The address blocked hostnames resolve to
+pub const SINKHOLE: &str = "0.0.0.0";
+
+#[cfg(windows)]
+const DEFAULT_LINE_ENDING: &str = "\r\n";
+#[cfg(not(windows))]
+const DEFAULT_LINE_ENDING: &str = "\n";
+
+/// Access to a hosts file, as a whole. Only the managed block (between BEGIN_MARKER and
+/// END_MARKER) is ever changed by dejunker, see blocked_hosts and with_blocked_hosts.
+pub trait HostsFile {
+    /// The contents of the hosts file. A hosts file that does not exist is empty.
+    fn read(&self) -> Result<String, DejunkerError>;
+
+    /// Replace the contents of the hosts file
+    fn write(&mut self, contents: &str) -> Result<(), DejunkerError>;
+}
+
+/// Parse whether a hostname is blocked, as written in the rules database (blocked or allowed)
+pub fn parse_blocked(name: &str) -> Option<bool> {
+    match name.to_lowercase().as_str() {
+        BLOCKED => Some(true),
+        ALLOWED => Some(false),
+        _ => None,
+    }
+}
+
+/// The name of a blocked state, as written in the rules database
+pub fn blocked_name(blocked: bool) -> &'static str {
+    if blocked { BLOCKED } else { ALLOWED }
+}
+
+/// Whether a string can be written in the hosts file as a hostname
+pub fn is_hostname(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['.', '-'])
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+}
+
+/// The hostnames in the managed block of a hosts file, lowercase and in the order of the file
+///
+/// * contents: the contents of the hosts file
+///
+pub fn blocked_hosts(contents: &str) -> Result<Vec<String>, DejunkerError> {
+    let Some(block) = find_block(contents)? else {
+        return Ok(Vec::new());
+    };
+
+    let mut hosts = Vec::new();
+    for line in contents[block.inner].lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        // the address, then any number of hostnames
+        hosts.extend(line.split_whitespace().skip(1).map(|host| host.to_lowercase()));
+    }
+    Ok(hosts)
+}
+
+/// The contents of a hosts file with the managed block listing these hostnames (and nothing
+/// else). Every line outside of the block is kept as it is. Without hostnames, the block is
+/// removed. A new block is added at the end of the file, with the line endings of the file.
+///
+/// * contents: the contents of the hosts file
+/// * hosts: the hostnames to block, in order
+///
+pub fn with_blocked_hosts(contents: &str, hosts: &[String]) -> Result<String, DejunkerError> {
+    let line_ending = if contents.contains("\r\n") {
+        "\r\n"
+    } else if contents.contains('\n') {
+        "\n"
+    } else {
+        DEFAULT_LINE_ENDING
+    };
+
+    let mut block = String::new();
+    if !hosts.is_empty() {
+        block.push_str(BEGIN_MARKER);
+        block.push_str(line_ending);
+        for host in hosts {
+            block.push_str(&format!("{} {}{}", SINKHOLE, host, line_ending));
+        }
+        block.push_str(END_MARKER);
+        block.push_str(line_ending);
+    }
+
+    match find_block(contents)? {
+        Some(found) => {
+            // an end marker on the last line may not have a line ending
+            if !contents[..found.whole.end].ends_with('\n') {
+                block.truncate(block.len() - line_ending.len());
+            }
+            Ok(format!("{}{}{}", &contents[..found.whole.start], block, &contents[found.whole.end..]))
+        }
+        None if block.is_empty() => Ok(contents.to_string()),
+        None if contents.is_empty() || contents.ends_with('\n') => Ok(format!("{}{}", contents, block)),
+        None => Ok(format!("{}{}{}", contents, line_ending, block)),
+    }
+}
+
+/// Where the managed block of a hosts file is, in bytes
+struct Block {
+    /// From the beginning of the begin marker to the end of the end marker line
+    whole: Range<usize>,
+    /// The lines between the markers
+    inner: Range<usize>,
+}
+
+/// Find the managed block of a hosts file, None if it has none
+fn find_block(contents: &str) -> Result<Option<Block>, DejunkerError> {
+    let mut offset = 0;
+    let mut begin: Option<(usize, usize)> = None;
+
+    for line in contents.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        match (begin, line.trim()) {
+            (None, BEGIN_MARKER) => begin = Some((start, offset)),
+            (Some((block_start, inner_start)), END_MARKER) => {
+                return Ok(Some(Block {
+                    whole: block_start..offset,
+                    inner: inner_start..start,
+                }));
+            }
+            _ => {}
+        }
+    }
+
+    match begin {
+        Some(_) => Err(DejunkerError::system(format!(
+            "The hosts file has a '{}' line without '{}'",
+            BEGIN_MARKER, END_MARKER
+        ))),
+        None => Ok(None),
+    }
+}
+
+/// The hosts file of the machine we are running on
+pub fn default_path() -> PathBuf {
+    if cfg!(windows) {
+        let root = std::env::var("SystemRoot").unwrap_or_else(|_| "C:\\Windows".to_string());
+        PathBuf::from(root).join("System32\\drivers\\etc\\hosts")
+    } else {
+        PathBuf::from("/etc/hosts")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::db::{Exec, Rule};
+    use crate::test_support::{rule, rules, Machine};
+    use crate::types::state::{EntryState, RuleState};
+
+    const HOSTS: &str = "# Copyright (c) 1993-2009 Microsoft Corp.\r\n#\r\n127.0.0.1 localhost\r\n::1 localhost\r\n";
+
+    fn hosts_rule(name: &str, hostnames: &[&str]) -> Rule {
+        let exec = Exec {
+            subsystem: "hosts".to_string(),
+            hostnames: hostnames.iter().map(|host| host.to_string()).collect(),
+            ..Exec::default()
+        };
+        rule(name, "OnOff", vec![exec])
+    }
+
+    fn machine(hosts: &str) -> Machine {
+        let mut machine = Machine::new();
+        machine.hosts = MemoryHosts::new(hosts);
+        machine
+    }
+
+    #[test]
+    fn the_block_is_added_with_the_line_endings_of_the_file() {
+        let hosts = vec!["vortex.data.microsoft.com".to_string()];
+        assert_eq!(
+            with_blocked_hosts(HOSTS, &hosts).unwrap(),
+            format!(
+                "{}# BEGIN dejunker\r\n0.0.0.0 vortex.data.microsoft.com\r\n# END dejunker\r\n",
+                HOSTS
+            )
+        );
+        // a last line without line ending is ended first
+        assert_eq!(
+            with_blocked_hosts("127.0.0.1 localhost", &hosts).unwrap(),
+            "127.0.0.1 localhost\n# BEGIN dejunker\n0.0.0.0 vortex.data.microsoft.com\n# END dejunker\n"
+        );
+    }
+
+    #[test]
+    fn only_the_block_is_changed() {
+        let contents = "# mine\n10.0.0.1 nas\n# BEGIN dejunker\n0.0.0.0 a.example\n# END dejunker\n10.0.0.2 printer # office";
+        assert_eq!(blocked_hosts(contents).unwrap(), vec!["a.example"]);
+
+        let changed = with_blocked_hosts(contents, &["a.example".to_string(), "b.example".to_string()]).unwrap();
+        assert_eq!(
+            changed,
+            "# mine\n10.0.0.1 nas\n# BEGIN dejunker\n0.0.0.0 a.example\n0.0.0.0 b.example\n# END dejunker\n\
+             10.0.0.2 printer # office"
+        );
+        assert_eq!(
+            with_blocked_hosts(&changed, &[]).unwrap(),
+            "# mine\n10.0.0.1 nas\n10.0.0.2 printer # office"
+        );
+    }
+
+    #[test]
+    fn an_unterminated_block_is_an_error() {
+        assert!(blocked_hosts("# BEGIN dejunker\n0.0.0.0 a.example\n").is_err());
+        assert!(with_blocked_hosts("# BEGIN dejunker\n", &[]).is_err());
+    }
+
+    #[test]
+    fn rules_share_the_block() {
+        let rules = rules([
+            hosts_rule("telemetry", &["vortex.data.microsoft.com", "settings-win.data.microsoft.com"]),
+            hosts_rule("ads", &["ads.msn.com"]),
+        ]);
+        let mut machine = machine(HOSTS);
+        assert_eq!(machine.state(&rules, "telemetry"), RuleState::Value("on".to_string()));
+
+        machine.set(&rules, "telemetry", "off").unwrap();
+        machine.set(&rules, "ads", "off").unwrap();
+        assert_eq!(machine.state(&rules, "telemetry"), RuleState::Value("off".to_string()));
+        assert_eq!(
+            blocked_hosts(&machine.hosts.read().unwrap()).unwrap(),
+            vec!["ads.msn.com", "settings-win.data.microsoft.com", "vortex.data.microsoft.com"]
+        );
+
+        machine.set(&rules, "telemetry", "on").unwrap();
+        assert_eq!(blocked_hosts(&machine.hosts.read().unwrap()).unwrap(), vec!["ads.msn.com"]);
+        machine.set(&rules, "ads", "on").unwrap();
+        assert_eq!(machine.hosts.read().unwrap(), HOSTS);
+    }
+
+    #[test]
+    fn hostnames_outside_of_the_block_do_not_count() {
+        let rules = rules([hosts_rule("telemetry", &["vortex.data.microsoft.com", "settings-win.data.microsoft.com"])]);
+        let mut machine = machine(&format!(
+            "0.0.0.0 settings-win.data.microsoft.com\n{}\n0.0.0.0 vortex.data.microsoft.com\n{}\n",
+            BEGIN_MARKER, END_MARKER
+        ));
+
+        match machine.state(&rules, "telemetry") {
+            RuleState::Mixed(entries) => {
+                let states: Vec<(&str, &EntryState)> =
+                    entries.iter().map(|entry| (entry.path.as_str(), &entry.state)).collect();
+                assert_eq!(
+                    states,
+                    vec![
+                        ("vortex.data.microsoft.com", &EntryState::Value("off".to_string())),
+                        ("settings-win.data.microsoft.com", &EntryState::Value("on".to_string())),
+                    ]
+                );
+            }
+            other => panic!("unexpected state {:?}", other),
+        }
+    }
+
+    #[test]
+    fn plans_leave_the_hosts_file_alone() {
+        let rules = rules([hosts_rule("ads", &["ads.msn.com"])]);
+        let mut machine = machine(HOSTS);
+        let plan = machine.plan(&rules, "ads", "off");
+        let changes: Vec<String> = plan.subsystem_changes.iter().map(|change| change.to_string()).collect();
+        assert_eq!(changes, vec!["hosts ads.msn.com: blocked"]);
+        assert_eq!(machine.hosts.read().unwrap(), HOSTS);
+    }
+}
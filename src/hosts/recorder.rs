@@ -0,0 +1,70 @@
+use super::HostsFile;
+use crate::error::DejunkerError;
+use std::fmt;
+use std::result::Result;
+
+/// A modification made to the managed block of the hosts file
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum HostsChange {
+    SetBlocked { hostname: String, blocked: bool },
+}
+
+impl fmt::Display for HostsChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostsChange::SetBlocked { hostname, blocked } => {
+                write!(f, "hosts {}: {}", hostname, super::blocked_name(*blocked))
+            }
+        }
+    }
+}
+
+/// Wraps another hosts file, and keeps a record of every hostname blocked or allowed through
+/// it. Reads go to the wrapped hosts file. When `apply` is false, modifications are only
+/// recorded and the hosts file is left alone.
+pub struct RecordingHosts<'a> {
+    inner: &'a mut dyn HostsFile,
+    apply: bool,
+    changes: Vec<HostsChange>,
+}
+
+impl<'a> RecordingHosts<'a> {
+    pub fn new(inner: &'a mut dyn HostsFile, apply: bool) -> Self {
+        RecordingHosts {
+            inner,
+            apply,
+            changes: Vec::new(),
+        }
+    }
+
+    /// All modifications, in the order they were made
+    pub fn changes(&self) -> &[HostsChange] {
+        &self.changes
+    }
+
+    pub fn into_changes(self) -> Vec<HostsChange> {
+        self.changes
+    }
+}
+
+impl HostsFile for RecordingHosts<'_> {
+    fn read(&self) -> Result<String, DejunkerError> {
+        self.inner.read()
+    }
+
+    fn write(&mut self, contents: &str) -> Result<(), DejunkerError> {
+        let previous = super::blocked_hosts(&self.inner.read()?)?;
+        let blocked = super::blocked_hosts(contents)?;
+        if self.apply {
+            self.inner.write(contents)?;
+        }
+
+        let added = blocked.iter().filter(|host| !previous.contains(host)).map(|host| (host, true));
+        let removed = previous.iter().filter(|host| !blocked.contains(host)).map(|host| (host, false));
+        self.changes.extend(added.chain(removed).map(|(hostname, blocked)| HostsChange::SetBlocked {
+            hostname: hostname.clone(),
+            blocked,
+        }));
+        Ok(())
+    }
+}
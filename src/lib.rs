@@ -0,0 +1,28 @@
+//! The dejunker engine: a database of rules (known settings and the registry values behind
+//! them), settings files choosing a value for some of these rules, and the code evaluating
+//! and applying rules against a registry.
+//!
+//! The dejunker executable is a command line front end for this library.
+
+pub mod error;
+pub mod files;
+pub mod firewall;
+pub mod hosts;
+pub mod packages;
+pub mod plan;
+pub mod registry;
+pub mod services;
+pub mod subsystems;
+pub mod tasks;
+pub mod types;
+pub mod verify;
+mod utils;
+#[cfg(test)]
+mod test_support;
+
+pub use error::DejunkerError;
+pub use files::db::{read_database, Exec, ExecAction, Rule, RulesDatabase, Value};
+pub use files::settings::{apply_settings, evaluate_rule, execute_rule, read_settings_file, Settings};
+pub use subsystems::Subsystems;
+pub use types::onoff::OnOffType;
+pub use types::state::{EntryState, ExecState, RuleState};
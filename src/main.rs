@@ -1,14 +1,20 @@
-use clap::{Arg, ArgGroup, Command};
-use files::db::{self, Exec, Rule};
-use log::{debug, error};
-use std::{collections::HashMap, env, fs::File, io::Write, process};
-
-mod files;
-mod registry;
-mod types;
-mod utils;
-
-use types::onoff::OnOffType;
+use clap::builder::PossibleValuesParser;
+use clap::{Arg, ArgAction, ArgGroup, ArgMatches, Command};
+use dejunker::files::{self, db, settings};
+use dejunker::files::pol::PolicyScope;
+use dejunker::firewall::{self, Firewall};
+use dejunker::hosts::{self, FileHosts};
+use dejunker::packages::{self, PackageManager};
+use dejunker::{plan, verify};
+use dejunker::registry::recorder::{Change, RecordingRegistry};
+use dejunker::registry::users::{self, TargetUser};
+use dejunker::registry::{self, OfflineRegistry, RegistryBackend};
+use dejunker::services::{self, ServiceManager};
+use dejunker::subsystems::{RecordingSubsystems, SubsystemChange};
+use dejunker::tasks::{self, TaskScheduler};
+use dejunker::{DejunkerError, Rule, RuleState, Settings, Subsystems};
+use log::{debug, error, info, warn};
+use std::{collections::BTreeMap, collections::HashMap, fs, fs::File, io::Write, path::Path, path::PathBuf, process::ExitCode};
 
 const DEFAULT_DB: &str = "db.yaml";
 
@@ -19,9 +25,20 @@ const DELIM: &str = "\r\n";
 const DELIM: &str = "\n";
 
 /// Main application function
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> ExitCode {
     env_logger::init();
 
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{}", e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+/// Parse the command line, and do what it asks for
+fn run() -> Result<(), DejunkerError> {
     // add some standard options
     let mut cmd = Command::new("Windows dejunker")
         .version("1.0.0")
@@ -59,64 +76,329 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .display_order(2)
                 .help("Database file (definitions of known settings)"),
         )
+        .arg(
+            Arg::new("offline_hive")
+                .long("offline-hive")
+                .value_name("MOUNT_POINT=HIVE_FILE")
+                .action(ArgAction::Append)
+                .required(false)
+                .display_order(3)
+                .help("Read and write an offline hive file instead of the live registry, e.g. HKCU=NTUSER.DAT or HKLM\\SOFTWARE=SOFTWARE (can be repeated)"),
+        )
+        .arg(
+            Arg::new("export_reg")
+                .long("export-reg")
+                .value_name("reg file")
+                .requires("input")
+                .required(false)
+                .display_order(4)
+                .help("Write the input file as a .reg script instead of applying it"),
+        )
+        .arg(
+            Arg::new("export_pol")
+                .long("export-pol")
+                .value_name("GPO directory")
+                .requires("input")
+                .required(false)
+                .display_order(5)
+                .help("Write the input file as User\\Registry.pol and Machine\\Registry.pol files in this directory instead of applying it"),
+        )
+        .arg(
+            Arg::new("import_reg")
+                .long("import-reg")
+                .value_name("reg file")
+                .conflicts_with("input")
+                .required(false)
+                .display_order(6)
+                .help("Write draft rules for the values in a .reg file (to the output file, or stdout)"),
+        )
+        .arg(
+            Arg::new("baseline_reg")
+                .long("baseline-reg")
+                .value_name("reg file")
+                .requires("import_reg")
+                .required(false)
+                .display_order(7)
+                .help("Only draft rules for values that differ from this .reg file (exported with the setting off)"),
+        )
+        .arg(
+            Arg::new("export_admx")
+                .long("export-admx")
+                .value_name("PolicyDefinitions directory")
+                .conflicts_with_all(["input", "output"])
+                .required(false)
+                .display_order(8)
+                .help("Write the rules database as group policy templates (ADMX, en-US ADML) in this directory"),
+        )
+        .arg(
+            Arg::new("dry_run")
+                .long("dry-run")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("output")
+                .display_order(9)
+                .help("Show the registry writes applying the input file or arguments (or undoing a journal) would make, without making them"),
+        )
+        .arg(
+            Arg::new("journal_dir")
+                .long("journal-dir")
+                .value_name("directory")
+                .display_order(10)
+                .help("Directory the previous values of changed registry values are saved to (one journal file per run), %ProgramData%\\dejunker\\journal by default"),
+        )
+        .arg(
+            Arg::new("undo")
+                .long("undo")
+                .value_name("journal file|last")
+                .conflicts_with_all(["input", "output", "opts"])
+                .display_order(11)
+                .help("Restore the registry values changed by an earlier run, from its journal file or the newest one in the journal directory"),
+        )
+        .arg(
+            Arg::new("target_user")
+                .long("target-user")
+                .value_name("name|SID|all|default")
+                .conflicts_with("offline_hive")
+                .display_order(12)
+                .help("Read or apply per user (HKEY_CURRENT_USER) settings for this user instead of the current one: a user name, a SID, all users, or the Default profile (users created later)"),
+        )
+        .arg(
+            Arg::new("hosts_file")
+                .long("hosts-file")
+                .value_name("hosts file")
+                .display_order(13)
+                .help("Hosts file the hostnames of hosts rules are blocked in, instead of that of the machine (also with offline hives)"),
+        )
         .group(ArgGroup::new("opts").required(false).multiple(true));
 
-    let pre_matches = cmd.clone().try_get_matches();
+    // rule arguments are not known yet, only the database file is of interest here
+    let pre_matches = cmd.clone().ignore_errors(true).try_get_matches();
 
-    let db = if pre_matches.is_ok() {
-        pre_matches
-            .unwrap()
-            .get_one::<String>("db")
-            .unwrap()
-            .clone()
-    } else {
-        DEFAULT_DB.to_owned()
-    };
+    let db = pre_matches
+        .ok()
+        .and_then(|pre_matches| pre_matches.get_one::<String>("db").cloned())
+        .unwrap_or_else(|| DEFAULT_DB.to_owned());
 
-    let rules = db::read_database(&db).expect("Unable to parse YAML").rules;
-    let mut commands: Vec<&str> = Vec::new();
-    commands.push("input");
-    commands.push("output");
+    let rules = db::read_database(&db)?.rules;
+    let mut rule_args: Vec<&str> = Vec::new();
 
     // dynamically add all rules as arguments
 
-    let mut display_order: usize = 4;
-
-    for rule in rules.values() {
-        let arg_name: &'static str = Box::leak(rule.name.clone().into_boxed_str());
-        let description: &'static str = Box::leak(rule.description.clone().into_boxed_str());
+    for (display_order, rule) in (20..).zip(rules.values()) {
+        if !rule.value.is_supported() {
+            return Err(DejunkerError::UnsupportedValueType(rule.value.value_type.clone()));
+        }
 
-        let (val, values) = if rule.value.value_type.to_lowercase() == "onoff" {
-            (clap::value_parser!(OnOffType), "on|off")
-        } else {
-            error!("Value type {} is not supported", &rule.value.value_type);
-            process::exit(1);
+        let arg = Arg::new(rule.name.clone())
+            .conflicts_with_all(["input", "output"])
+            .display_order(display_order)
+            .require_equals(true)
+            .long(rule.name.clone())
+            .group("opts")
+            .help(rule.description.clone());
+
+        // choices are listed, numbers and strings are checked against the rule
+        let arg = match rule.value.choices() {
+            Some(choices) => arg
+                .value_name(choices.join("|"))
+                .value_parser(PossibleValuesParser::new(choices)),
+            None => {
+                let value = rule.value.clone();
+                arg.value_name(rule.value.value_type.to_lowercase())
+                    .value_parser(move |s: &str| value.check(s).map(|_| s.to_string()))
+            }
         };
 
         // add to opts
-        cmd = cmd.arg(
-            Arg::new(arg_name)
-                .conflicts_with_all(&["input", "output"])
-                .display_order(display_order)
-                .require_equals(true)
-                .long(arg_name)
-                .value_parser(val)
-                .value_name(values)
-                .group("opts")
-                .help(description),
-        );
+        cmd = cmd.arg(arg);
 
-        commands.push(arg_name);
-        display_order += 1;
+        rule_args.push(&rule.name);
     }
 
     let matches = cmd.get_matches();
-    let args_supplied = env::args().len() > 1;
 
     // if output_file is specified or no parameters are specified, this is view mode
     let output_file = matches.get_one::<String>("output");
     let input_file = matches.get_one::<String>("input");
 
+    // authoring mode, the registry is neither read nor written
+    if let Some(import_file) = matches.get_one::<String>("import_reg") {
+        let drafts = import_reg_file(import_file, matches.get_one::<String>("baseline_reg"))?;
+        match output_file {
+            Some(output_file) => write_string_to_file(&drafts, output_file)?,
+            None => print!("{}", drafts),
+        }
+        return Ok(());
+    }
+
+    if let Some(export_dir) = matches.get_one::<String>("export_admx") {
+        export_admx_files(&rules, export_dir)?;
+        return Ok(());
+    }
+
+    // export mode, the registry is neither read nor written
+    let export_reg = matches.get_one::<String>("export_reg");
+    let export_pol = matches.get_one::<String>("export_pol");
+    if let Some(input_file) = input_file.filter(|_| export_reg.is_some() || export_pol.is_some()) {
+        let changes = record_settings_file(&rules, input_file)?;
+        if let Some(export_file) = export_reg {
+            export_reg_file(&changes, export_file)?;
+        }
+        if let Some(export_dir) = export_pol {
+            export_pol_files(&changes, export_dir)?;
+        }
+        return Ok(());
+    }
+
+    // use the offline hives if any, otherwise the live registry
+    let mut offline = match matches.get_many::<String>("offline_hive") {
+        Some(specs) => {
+            let mut offline = OfflineRegistry::new();
+            for spec in specs {
+                offline.mount_spec(spec)?;
+            }
+            Some(offline)
+        }
+        None => None,
+    };
+    let live = offline.is_none();
+    let mut local: Box<dyn RegistryBackend>;
+    let registry: &mut dyn RegistryBackend = match offline.as_mut() {
+        Some(offline) => offline,
+        None => {
+            local = registry::local_registry()?;
+            local.as_mut()
+        }
+    };
+
+    // services, tasks, packages and firewall rules are those of the machine we are running on,
+    // not of the offline hives
+    let mut local_services: Box<dyn ServiceManager>;
+    let mut local_tasks: Box<dyn TaskScheduler>;
+    let mut local_packages: Box<dyn PackageManager>;
+    let mut local_firewall: Box<dyn Firewall>;
+    let mut hosts_file: FileHosts;
+    let mut subsystems = Subsystems::none();
+    if live {
+        local_services = services::local_services()?;
+        subsystems.services = Some(local_services.as_mut());
+        local_tasks = tasks::local_tasks()?;
+        subsystems.tasks = Some(local_tasks.as_mut());
+        local_packages = packages::local_packages()?;
+        subsystems.packages = Some(local_packages.as_mut());
+        local_firewall = firewall::local_firewall()?;
+        subsystems.firewall = Some(local_firewall.as_mut());
+    }
+    // the hosts file of the machine, unless another one is given (e.g. that of an offline image)
+    let hosts_path = matches.get_one::<String>("hosts_file").map(Into::into);
+    if let Some(path) = hosts_path.or_else(|| live.then(hosts::default_path)) {
+        hosts_file = FileHosts::new(path);
+        subsystems.hosts = Some(&mut hosts_file);
+    }
+
+    let mut outcome = Outcome::default();
+    let result = match matches.get_one::<String>("target_user") {
+        Some(target) => {
+            let target = TargetUser::from_name(target);
+            if is_read_mode(&matches, &rule_args) && target == TargetUser::All {
+                return Err(DejunkerError::UserProfile(
+                    "settings can only be read for a single user".to_string(),
+                ));
+            }
+            let mut profiles = users::local_profiles()?;
+            subsystems.other_user = true;
+            users::for_each_user(registry, profiles.as_mut(), &target, |profile, registry| {
+                info!("Target user: {}", profile);
+                if matches.get_flag("dry_run") {
+                    println!("# {}", profile);
+                }
+                let mut user_outcome = Outcome::default();
+                let result = run_registry_modes(
+                    registry,
+                    &mut subsystems,
+                    &rules,
+                    &matches,
+                    &rule_args,
+                    Some(profile.key()),
+                    &mut user_outcome,
+                );
+                // the machine-wide entries are the same for every user, they are applied once
+                subsystems.user_entries_only = true;
+                // journaled at the hive of the user, HKEY_CURRENT_USER is someone else on undo
+                for change in &user_outcome.changes {
+                    outcome.changes.push(change.mapped(|path| users::user_path(path, profile.key()))?);
+                }
+                outcome.subsystem_changes.extend(user_outcome.subsystem_changes);
+                outcome.drifted.extend(
+                    user_outcome
+                        .drifted
+                        .into_iter()
+                        .map(|rule| format!("{} ({})", rule, profile.name)),
+                );
+                result
+            })
+        }
+        None => run_registry_modes(registry, &mut subsystems, &rules, &matches, &rule_args, None, &mut outcome),
+    };
+
+    // offline hives are only written if everything went well, live changes are made as we go
+    let persisted = match offline.as_mut() {
+        Some(offline) if result.is_ok() => {
+            offline.save()?;
+            true
+        }
+        Some(_) => false,
+        None => true,
+    };
+    if persisted && !(outcome.changes.is_empty() && outcome.subsystem_changes.is_empty()) {
+        let journal = files::journal::Journal::from_changes(&outcome.changes, &outcome.subsystem_changes);
+        let journal_file = files::journal::write_journal(&journal_dir(&matches), &journal)?;
+        info!(
+            "Previous values saved to {}, use --undo to restore them",
+            journal_file.display()
+        );
+    }
+
+    result?;
+    match outcome.drifted.is_empty() {
+        true => Ok(()),
+        false => Err(DejunkerError::Drift(outcome.drifted)),
+    }
+}
+
+/// What applying the settings did
+#[derive(Default)]
+struct Outcome {
+    /// The registry changes made
+    changes: Vec<Change>,
+    /// The changes made through other subsystems
+    subsystem_changes: Vec<SubsystemChange>,
+    /// The rules that do not have the value they were set to after applying them
+    drifted: Vec<String>,
+}
+
+/// Read, plan or apply the settings asked for on the command line, against one registry
+///
+/// * registry: the registry to read, or make the changes to
+/// * subsystems: the backends of the other subsystems
+/// * rules: the list of known rules
+/// * matches: the parsed command line
+/// * rule_args: the names of the rule arguments
+/// * user: the key of HKEY_USERS of the targeted user (see --target-user), None for the
+///   current user
+/// * outcome: the changes made and the rules that drifted are added to this
+///
+fn run_registry_modes(
+    registry: &mut dyn RegistryBackend,
+    subsystems: &mut Subsystems,
+    rules: &HashMap<String, Rule>,
+    matches: &ArgMatches,
+    rule_args: &[&str],
+    user: Option<&str>,
+    outcome: &mut Outcome,
+) -> Result<(), DejunkerError> {
+    let output_file = matches.get_one::<String>("output");
+    let input_file = matches.get_one::<String>("input");
+
     let mut accumulator: String = format!(
         "file: {}{}settings: {}",
         files::settings::FILE_MARKER.to_owned(),
@@ -125,8 +407,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     );
 
     // read  mode
-    if !args_supplied || output_file.is_some() {
-        accumulator += print_values(&rules, output_file)?.as_str();
+    if is_read_mode(matches, rule_args) {
+        accumulator += print_values(registry, subsystems, rules, output_file)?.as_str();
         if !accumulator.is_empty() {
             match output_file {
                 Some(_) => {
@@ -139,30 +421,146 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    // plan mode, show what write mode would do
+    if matches.get_flag("dry_run") {
+        if let Some((journal_file, journal)) = journal_to_undo(matches, subsystems, user)? {
+            println!("undo {}:", journal_file.display());
+            print!("{}", plan::format_undo_plan(&plan::plan_undo(registry, subsystems, &journal)?));
+        }
+        let mut plans = Vec::new();
+        if let Some(input_file) = input_file {
+            let settings = dejunker::read_settings_file(input_file)?;
+            plans.extend(plan::plan_settings(registry, subsystems, rules, &settings, true)?);
+        }
+        for arg in rule_args.iter() {
+            if let Some(value) = matches.get_one::<String>(arg) {
+                plans.push(plan::plan_rule(registry, subsystems, rules, arg, value, false)?);
+            }
+        }
+        print!("{}", plan::format_plan(&plans));
+        return Ok(());
+    }
+
+    // write mode, the previous values are journaled so the changes can be undone
+    let mut recorder = RecordingRegistry::new(registry, true);
+    let mut recording = RecordingSubsystems::new(subsystems, true);
+    let result = apply_requested(&mut recorder, &mut recording.subsystems(), rules, matches, rule_args, user);
+    outcome.changes.extend(recorder.into_changes());
+    for change in recording.into_changes() {
+        if matches!(
+            change,
+            SubsystemChange::Package(_) | SubsystemChange::Hosts(_) | SubsystemChange::Firewall(_)
+        ) {
+            warn!("Changed {}, this cannot be undone from the journal", change);
+        }
+        outcome.subsystem_changes.push(change);
+    }
+
+    // read everything back, policies or other software may have put values back already
+    let drifts = verify::verify_settings(registry, subsystems, rules, &result?)?;
+    eprint!("{}", verify::format_drift(&drifts));
+    outcome.drifted.extend(drifts.into_iter().map(|drift| drift.rule));
+    Ok(())
+}
+
+/// The directory journals are written to and `--undo last` looks in
+fn journal_dir(matches: &ArgMatches) -> PathBuf {
+    matches
+        .get_one::<String>("journal_dir")
+        .map(PathBuf::from)
+        .unwrap_or_else(files::journal::default_dir)
+}
+
+/// The journal file `--undo` asks for ("last" is the newest one in the journal directory),
+/// and what to undo of it. For a targeted user, that is the entries of that user, and the
+/// machine-wide ones unless they were undone for an earlier user already.
+///
+/// * matches: the parsed command line
+/// * subsystems: the backends of the other subsystems
+/// * user: the key of HKEY_USERS of the targeted user, None for the current user
+///
+fn journal_to_undo(
+    matches: &ArgMatches,
+    subsystems: &Subsystems,
+    user: Option<&str>,
+) -> Result<Option<(PathBuf, files::journal::Journal)>, DejunkerError> {
+    let journal_file = match matches.get_one::<String>("undo").map(String::as_str) {
+        Some("last") => files::journal::latest_journal(&journal_dir(matches))?,
+        Some(journal_file) => PathBuf::from(journal_file),
+        None => return Ok(None),
+    };
+    let journal = files::journal::read_journal(&journal_file.to_string_lossy())?;
+    let journal = match user {
+        Some(user) => journal.of_user(user, !subsystems.user_entries_only)?,
+        None => journal,
+    };
+    Ok(Some((journal_file, journal)))
+}
+
+/// Whether the command line asks for the current settings: to write them to an output file,
+/// or nothing else is asked for
+fn is_read_mode(matches: &ArgMatches, rule_args: &[&str]) -> bool {
+    let rule_args_supplied = rule_args.iter().any(|arg| matches.contains_id(arg));
+    matches.contains_id("output")
+        || (!matches.contains_id("input") && !rule_args_supplied && !matches.contains_id("undo"))
+}
+
+/// Make the changes asked for on the command line: undo a journal, apply the input file,
+/// and set the rules given as arguments
+///
+/// * registry: the registry to make the changes to
+/// * subsystems: the backends of the other subsystems
+/// * rules: the list of known rules
+/// * matches: the parsed command line
+/// * rule_args: the names of the rule arguments
+/// * user: the key of HKEY_USERS of the targeted user, None for the current user
+///
+/// Returns the rules that were set, and the value each was set to last
+fn apply_requested(
+    registry: &mut dyn RegistryBackend,
+    subsystems: &mut Subsystems,
+    rules: &HashMap<String, Rule>,
+    matches: &ArgMatches,
+    rule_args: &[&str],
+    user: Option<&str>,
+) -> Result<BTreeMap<String, String>, DejunkerError> {
+    let mut requested = BTreeMap::new();
+
+    if let Some((journal_file, journal)) = journal_to_undo(matches, subsystems, user)? {
+        info!("Undoing {}", journal_file.display());
+        files::journal::undo_journal(registry, subsystems, &journal)?;
+    }
+
     // write mode (file)
-    if input_file.is_some() {
-        apply_settings_file(&rules, input_file.unwrap().as_str(), true)?;
+    if let Some(input_file) = matches.get_one::<String>("input") {
+        let settings = apply_settings_file(registry, subsystems, rules, input_file.as_str(), true)?;
+        requested.extend(settings.settings);
     }
 
     // get all supplied args
-    for arg in commands.iter() {
-        if matches.contains_id(arg) {
-            let value = matches.get_one::<OnOffType>(arg).unwrap();
-            files::settings::execute_rule(&rules, arg, &value.to_string(), false)?;
+    for arg in rule_args.iter() {
+        if let Some(value) = matches.get_one::<String>(arg) {
+            dejunker::execute_rule(registry, subsystems, rules, arg, value, false)?;
+            requested.insert(arg.to_string(), value.clone());
         }
     }
-    Ok(())
+
+    Ok(requested)
 }
 
 // print accumulated string to output file, or stdout
 ///
+/// * registry: the registry to read the current values from
+/// * subsystems: the backends of the other subsystems
 /// * rules: the list of rules to be printed
 /// * output_file: the file to write to (stdout if None)
 ///
 fn print_values(
-    rules: &HashMap<String, db::Rule>,
+    registry: &dyn RegistryBackend,
+    subsystems: &Subsystems,
+    rules: &HashMap<String, Rule>,
     output_file: Option<&String>,
-) -> Result<String, Box<dyn std::error::Error>> {
+) -> Result<String, DejunkerError> {
     debug!(
         "Output file is {}",
         output_file.unwrap_or(&String::from("stdout"))
@@ -174,82 +572,145 @@ fn print_values(
     sorted_rules.sort_by(|a, b| a.name.cmp(&b.name));
 
     for rule in sorted_rules {
-        let arg_name = rule.name.clone();
-        output.push_str(&format!(
-            "    {}: {}\n",
-            arg_name,
-            evaluate_rule(&rule.exec)?
-        ));
+        // e.g. rules for a hive that was not mounted
+        if let Some(reason) = settings::unreachable_entry(registry, subsystems, rule) {
+            debug!("Skipping rule {}, {}", rule.name, reason);
+            continue;
+        }
+
+        let state = dejunker::evaluate_rule(registry, subsystems, rule)?;
+        output.push_str(&format!("    {}: {}\n", rule.name, settings::format_setting(rule, &state)));
+
+        // comments, so the file can still be applied
+        if let RuleState::Mixed(entries) = &state {
+            for entry in entries {
+                output.push_str(&format!("    # {}\n", entry));
+            }
+        }
     }
     Ok(output)
 }
 
-/// Check the value of a rule
+// write accumulated string to file
+fn write_string_to_file(content: &str, file_path: &str) -> Result<(), DejunkerError> {
+    let mut file = File::create(file_path).map_err(DejunkerError::io(file_path))?;
+    file.write_all(content.as_bytes()).map_err(DejunkerError::io(file_path))?;
+    Ok(())
+}
+
+/// Resolve a settings file into the registry changes applying it would make, without
+/// touching the registry
 ///
-/// * exec: The native part of the rule (usually registry access information)
+/// * rules: the list of known rules
+/// * path_or_url: the settings file to resolve
 ///
-fn evaluate_rule(exec: &Vec<Exec>) -> Result<String, Box<dyn std::error::Error>> {
-    let mut results: HashMap<OnOffType, i32> = HashMap::new();
+fn record_settings_file(
+    rules: &HashMap<String, Rule>,
+    path_or_url: &str,
+) -> Result<Vec<Change>, DejunkerError> {
+    let settings = dejunker::read_settings_file(path_or_url)?;
+    plan::export_settings(rules, &settings)
+}
 
-    for op in exec.iter() {
-        match op.subsystem.as_str() {
-            "registry" => {
-                let value = registry::read_value(&op.path, &op.value, &op.value_type)?;
+/// Write registry changes as a .reg script
+///
+/// * changes: the changes to write
+/// * output_file: the .reg file to write
+///
+fn export_reg_file(changes: &[Change], output_file: &str) -> Result<(), DejunkerError> {
+    let script = files::regfile::format_reg_file(changes)?;
+    fs::write(output_file, files::regfile::encode_reg_file(&script)).map_err(DejunkerError::io(output_file))?;
+    Ok(())
+}
 
-                match op.value_type.as_str() {
-                    "i32" => {
-                        let mut value = OnOffType::from_string(value.as_str());
-                        if op.reversed.unwrap_or(false) {
-                            value = value.flipped();
-                        }
+/// Write registry changes as the Registry.pol files of a group policy object. Changes to
+/// HKCU go to User\Registry.pol, changes to HKLM to Machine\Registry.pol, a side without
+/// changes is not written.
+///
+/// * changes: the changes to write
+/// * output_dir: the GPO directory (the one containing the User and Machine directories)
+///
+fn export_pol_files(changes: &[Change], output_dir: &str) -> Result<(), DejunkerError> {
+    for change in changes.iter().filter(|change| files::pol::change_scope(change).is_none()) {
+        warn!("{} cannot be deployed through a policy, skipping it", change.path());
+    }
 
-                        // all values must evaluate to On or Off. If some evaluate to on and some to off, we assume off.
-                        // We use a hashmap as a lazy way of determining this
-                        results.insert(value, 0);
-                    }
-                    _ => {
-                        error!("Value type {} is not supported", op.value_type);
-                        process::exit(1);
-                    }
-                };
-            }
-            _ => {
-                error!("Subsystem '{}' is not supported", op.subsystem);
-                process::exit(1);
-            }
+    for scope in [PolicyScope::User, PolicyScope::Machine] {
+        if !changes.iter().any(|change| files::pol::change_scope(change) == Some(scope)) {
+            continue;
         }
-    }
 
-    if results.keys().len() == 1 {
-        return Ok(results.keys().next().unwrap().to_string());
-    } else {
-        return Ok(OnOffType::On.to_string());
+        let dir = Path::new(output_dir).join(scope.directory());
+        let pol_file = dir.join("Registry.pol");
+        fs::create_dir_all(&dir).map_err(DejunkerError::io(&dir))?;
+        fs::write(&pol_file, files::pol::format_pol_file(changes, scope)?).map_err(DejunkerError::io(&pol_file))?;
     }
+    Ok(())
 }
 
-// write accumulated string to file
-fn write_string_to_file(content: &str, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let mut file = File::create(file_path)?;
-    file.write_all(content.as_bytes())?;
+/// Write the rules database as group policy templates, laid out like a PolicyDefinitions
+/// directory (the ADML file goes in the en-US subdirectory)
+///
+/// * rules: the list of known rules
+/// * output_dir: the directory to write the templates to
+///
+fn export_admx_files(rules: &HashMap<String, Rule>, output_dir: &str) -> Result<(), DejunkerError> {
+    let definitions = files::admx::format_policy_definitions(rules);
+    let output_dir = Path::new(output_dir);
+    let admx_file = output_dir.join(format!("{}.admx", files::admx::POLICY_FILE_NAME));
+    let adml_file = output_dir
+        .join("en-US")
+        .join(format!("{}.adml", files::admx::POLICY_FILE_NAME));
+
+    fs::create_dir_all(output_dir.join("en-US")).map_err(DejunkerError::io(output_dir))?;
+    fs::write(&admx_file, definitions.admx).map_err(DejunkerError::io(&admx_file))?;
+    fs::write(&adml_file, definitions.adml).map_err(DejunkerError::io(&adml_file))?;
     Ok(())
 }
 
+/// Draft rules (in the database format) from a .reg file, or from the difference between two
+/// .reg files
+///
+/// * path: the .reg file to import (exported with the setting on)
+/// * baseline: the .reg file to compare to (exported with the setting off)
+///
+fn import_reg_file(path: &str, baseline: Option<&String>) -> Result<String, DejunkerError> {
+    let read_reg_file = |path: &str| -> Result<_, DejunkerError> {
+        let data = fs::read(path).map_err(DejunkerError::io(path))?;
+        files::regfile::parse_reg_file(path, &files::regfile::decode_reg_file(&data))
+    };
+
+    let after = read_reg_file(path)?;
+    let rules = match baseline {
+        Some(baseline) => {
+            let before = read_reg_file(baseline)?;
+            let rule = files::drafts::draft_rule_from_diff(&before, &after);
+            if rule.is_none() {
+                warn!("No differences between '{}' and '{}'", baseline, path);
+            }
+            rule.into_iter().collect()
+        }
+        None => files::drafts::draft_rules(&after),
+    };
+
+    Ok(files::db::format_database(&rules))
+}
+
 /// Read settings from a settings file, and apply the directives in that file
 ///
+/// * registry: the registry to apply the settings to
 /// * rules: the list of known rules
 /// * path_or_url: the settings files to apply
 ///
+/// Returns the settings that were applied
 fn apply_settings_file(
-    rules: &HashMap<String, db::Rule>,
+    registry: &mut dyn RegistryBackend,
+    subsystems: &mut Subsystems,
+    rules: &HashMap<String, Rule>,
     path_or_url: &str,
-    skip_inaccessible: bool
-) -> Result<(), Box<dyn std::error::Error>> {
-    let file = files::settings::read_settings_file(path_or_url)?;
-
-    // for each setting
-    for (key, value) in file.settings.iter() {
-        files::settings::execute_rule(rules, &key, &value, skip_inaccessible)?;
-    }
-
-    Ok(())
+    skip_inaccessible: bool,
+) -> Result<Settings, DejunkerError> {
+    let settings = dejunker::read_settings_file(path_or_url)?;
+    dejunker::apply_settings(registry, subsystems, rules, &settings, skip_inaccessible)?;
+    Ok(settings)
 }
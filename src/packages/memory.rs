@@ -0,0 +1,55 @@
+use super::{Package, PackageManager, PackageScope};
+use crate::error::DejunkerError;
+use std::collections::BTreeMap;
+use std::result::Result;
+
+/// Appx packages that only exist in memory, indexed by their scope and lowercase full name
+#[derive(Debug, Clone, Default)]
+pub struct MemoryPackages {
+    packages: BTreeMap<(PackageScope, String), Package>,
+}
+
+impl MemoryPackages {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add version 1.0.0.0 of a package, or replace it if it is already there
+    pub fn insert(&mut self, scope: PackageScope, name: &str, publisher: &str, publisher_id: &str) {
+        let package = Package {
+            full_name: format!("{}_1.0.0.0_neutral__{}", name, publisher_id),
+            name: name.to_string(),
+            family_name: format!("{}_{}", name, publisher_id),
+            publisher: publisher.to_string(),
+            publisher_id: publisher_id.to_string(),
+        };
+        self.packages.insert((scope, package.full_name.to_lowercase()), package);
+    }
+}
+
+impl PackageManager for MemoryPackages {
+    fn packages(&self, scope: PackageScope) -> Result<Vec<Package>, DejunkerError> {
+        Ok(self
+            .packages
+            .iter()
+            .filter(|((package_scope, _), _)| *package_scope == scope)
+            .map(|(_, package)| package.clone())
+            .collect())
+    }
+
+    fn remove(&mut self, scope: PackageScope, package: &Package) -> Result<(), DejunkerError> {
+        // deprovisioning removes every version of the package
+        let before = self.packages.len();
+        self.packages.retain(|(package_scope, _), installed| {
+            *package_scope != scope
+                || match scope {
+                    PackageScope::User => !installed.full_name.eq_ignore_ascii_case(&package.full_name),
+                    PackageScope::Provisioned => !installed.family_name.eq_ignore_ascii_case(&package.family_name),
+                }
+        });
+        if self.packages.len() == before {
+            return Err(DejunkerError::system(format!("Package {} is not installed", package.full_name)));
+        }
+        Ok(())
+    }
+}
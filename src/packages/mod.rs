@@ -0,0 +1,279 @@
+use crate::error::DejunkerError;
+use crate::utils::wildcard_pattern;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::result::Result;
+
+mod memory;
+pub mod recorder;
+#[cfg(windows)]
+mod win32;
+
+pub use memory::MemoryPackages;
+#[cfg(windows)]
+pub use win32::Win32Packages;
+
+/// The data of appx entries, as written in the rules database
+pub const PRESENT: &str = "present";
+pub const ABSENT: &str = "absent";
+
+/// An appx package, as identified by Windows
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Package {
+    /// The name, version, architecture and publisher of the package, e.g.
+    /// Clipchamp.Clipchamp_2.2.8.0_neutral__yxz26nhyzhsrt
+    pub full_name: String,
+    /// e.g. Clipchamp.Clipchamp
+    pub name: String,
+    /// The name and publisher of the package, the same for all its versions, e.g.
+    /// Clipchamp.Clipchamp_yxz26nhyzhsrt
+    pub family_name: String,
+    /// The subject of the certificate the package is signed with, e.g. CN=Microsoft
+    /// Corporation, O=Microsoft Corporation, L=Redmond, S=Washington, C=US
+    pub publisher: String,
+    /// A hash of the publisher, e.g. 8wekyb3d8bbwe
+    pub publisher_id: String,
+}
+
+/// Where a package is installed
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum PackageScope {
+    /// Installed for the user running dejunker
+    User,
+    /// Provisioned in the system image, installed for every new user
+    Provisioned,
+}
+
+impl PackageScope {
+    /// Both scopes, for entries that do not name one
+    pub const ALL: [PackageScope; 2] = [PackageScope::User, PackageScope::Provisioned];
+
+    /// Parse a scope as written in the rules database (user or provisioned)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "user" => Some(PackageScope::User),
+            "provisioned" => Some(PackageScope::Provisioned),
+            _ => None,
+        }
+    }
+
+    /// The name of the scope, as written in the rules database
+    pub fn name(&self) -> &'static str {
+        match self {
+            PackageScope::User => "user",
+            PackageScope::Provisioned => "provisioned",
+        }
+    }
+}
+
+impl fmt::Display for PackageScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Access to the appx packages of a machine
+pub trait PackageManager {
+    /// The packages installed for the current user, or provisioned for new users
+    fn packages(&self, scope: PackageScope) -> Result<Vec<Package>, DejunkerError>;
+
+    /// Remove a package for the current user, or deprovision it (it stays installed for the
+    /// users that already have it)
+    fn remove(&mut self, scope: PackageScope, package: &Package) -> Result<(), DejunkerError>;
+}
+
+/// Parse whether a package is present, as written in the rules database (present or absent)
+pub fn parse_presence(name: &str) -> Option<bool> {
+    match name.to_lowercase().as_str() {
+        PRESENT => Some(true),
+        ABSENT => Some(false),
+        _ => None,
+    }
+}
+
+/// The name of a presence, as written in the rules database
+pub fn presence_name(present: bool) -> &'static str {
+    if present { PRESENT } else { ABSENT }
+}
+
+/// Find the packages of a scope matching a name and a publisher. The name is matched against
+/// the name and the family name of packages, and can have wildcards: `*` for any number of
+/// characters and `?` for a single one. The publisher is either a publisher id, or the
+/// subject of the certificate (with wildcards, e.g. `CN=Microsoft Corporation*`).
+///
+/// * manager: the package manager to look in
+/// * scope: where the packages are installed
+/// * name: the name of the packages
+/// * publisher: the publisher of the packages, None for any
+///
+pub fn find_packages(
+    manager: &dyn PackageManager,
+    scope: PackageScope,
+    name: &str,
+    publisher: Option<&str>,
+) -> Result<Vec<Package>, DejunkerError> {
+    let name = wildcard_pattern(name);
+    let publisher = publisher.map(wildcard_pattern);
+    let mut packages: Vec<Package> = manager
+        .packages(scope)?
+        .into_iter()
+        .filter(|package| name.is_match(&package.name) || name.is_match(&package.family_name))
+        .filter(|package| {
+            publisher.as_ref().is_none_or(|publisher| {
+                publisher.is_match(&package.publisher_id) || publisher.is_match(&package.publisher)
+            })
+        })
+        .collect();
+    packages.sort_by_key(|package| package.full_name.to_lowercase());
+    Ok(packages)
+}
+
+/// The package manager of the machine we are running on.
+#[cfg(windows)]
+pub fn local_packages() -> Result<Box<dyn PackageManager>, DejunkerError> {
+    Ok(Box::new(Win32Packages::new()?))
+}
+
+/// The package manager of the machine we are running on. There are no appx packages outside
+/// of Windows.
+#[cfg(not(windows))]
+pub fn local_packages() -> Result<Box<dyn PackageManager>, DejunkerError> {
+    Err(DejunkerError::system("Appx packages are only available on Windows"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::db::{Exec, Rule};
+    use crate::files::settings::unreachable_entry;
+    use crate::test_support::{rule, rules, Machine};
+    use crate::types::state::{EntryState, RuleState};
+    use std::collections::HashMap;
+
+    const MICROSOFT: &str = "CN=Microsoft Corporation, O=Microsoft Corporation, L=Redmond, S=Washington, C=US";
+    const MICROSOFT_ID: &str = "8wekyb3d8bbwe";
+
+    fn package_rule(name: &str, publisher: Option<&str>, scope: Option<PackageScope>) -> HashMap<String, Rule> {
+        let exec = Exec {
+            subsystem: "appx".to_string(),
+            path: name.to_string(),
+            publisher: publisher.map(|publisher| publisher.to_string()),
+            scope,
+            ..Exec::default()
+        };
+        rules([rule("solitaire", "OnOff", vec![exec])])
+    }
+
+    fn installed() -> Machine {
+        let mut machine = Machine::new();
+        for scope in PackageScope::ALL {
+            machine.packages.insert(scope, "Microsoft.MicrosoftSolitaireCollection", MICROSOFT, MICROSOFT_ID);
+            machine.packages.insert(scope, "Clipchamp.Clipchamp", "CN=Clipchamp", "yxz26nhyzhsrt");
+        }
+        machine.packages.insert(PackageScope::User, "Microsoft.BingNews", MICROSOFT, MICROSOFT_ID);
+        machine
+    }
+
+    fn names(packages: &MemoryPackages, scope: PackageScope) -> Vec<String> {
+        packages.packages(scope).unwrap().into_iter().map(|package| package.name).collect()
+    }
+
+    #[test]
+    fn packages_match_on_name_and_publisher() {
+        let packages = installed().packages;
+        let found = |name: &str, publisher: Option<&str>| -> Vec<String> {
+            find_packages(&packages, PackageScope::User, name, publisher)
+                .unwrap()
+                .into_iter()
+                .map(|package| package.name)
+                .collect()
+        };
+
+        assert_eq!(found("Microsoft.*", None), vec!["Microsoft.BingNews", "Microsoft.MicrosoftSolitaireCollection"]);
+        assert_eq!(found("*solitaire*", Some(MICROSOFT_ID)), vec!["Microsoft.MicrosoftSolitaireCollection"]);
+        assert_eq!(found("Clipchamp.Clipchamp_yxz26nhyzhsrt", None), vec!["Clipchamp.Clipchamp"]);
+        assert_eq!(found("*", Some("CN=Clipchamp*")), vec!["Clipchamp.Clipchamp"]);
+        assert!(found("Clipchamp.*", Some(MICROSOFT_ID)).is_empty());
+    }
+
+    #[test]
+    fn off_removes_and_deprovisions() {
+        let rules = package_rule("*Solitaire*", Some(MICROSOFT_ID), None);
+        let mut machine = installed();
+        assert_eq!(machine.state(&rules, "solitaire"), RuleState::Value("on".to_string()));
+
+        machine.set(&rules, "solitaire", "off").unwrap();
+        assert_eq!(names(&machine.packages, PackageScope::User), vec!["Clipchamp.Clipchamp", "Microsoft.BingNews"]);
+        assert_eq!(names(&machine.packages, PackageScope::Provisioned), vec!["Clipchamp.Clipchamp"]);
+        assert_eq!(machine.state(&rules, "solitaire"), RuleState::Value("off".to_string()));
+    }
+
+    #[test]
+    fn a_scope_leaves_the_other_alone() {
+        let rules = package_rule("Clipchamp.Clipchamp", None, Some(PackageScope::Provisioned));
+        let mut machine = installed();
+        machine.set(&rules, "solitaire", "off").unwrap();
+        assert!(names(&machine.packages, PackageScope::User).contains(&"Clipchamp.Clipchamp".to_string()));
+        assert!(!names(&machine.packages, PackageScope::Provisioned).contains(&"Clipchamp.Clipchamp".to_string()));
+    }
+
+    #[test]
+    fn packages_removed_for_the_user_only_are_mixed() {
+        let rules = package_rule("Microsoft.BingNews", None, None);
+        let mut machine = installed();
+
+        match machine.state(&rules, "solitaire") {
+            RuleState::Mixed(entries) => {
+                let states: Vec<(&str, &EntryState)> =
+                    entries.iter().map(|entry| (entry.value.as_str(), &entry.state)).collect();
+                assert_eq!(
+                    states,
+                    vec![
+                        ("installed", &EntryState::Value("on".to_string())),
+                        ("provisioned", &EntryState::Value("off".to_string())),
+                    ]
+                );
+            }
+            other => panic!("unexpected state {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_packages_cannot_be_installed() {
+        let rules = package_rule("Microsoft.ZuneMusic", None, None);
+        let mut machine = installed();
+        assert_eq!(machine.state(&rules, "solitaire"), RuleState::Value("off".to_string()));
+        machine.set(&rules, "solitaire", "on").unwrap();
+        assert_eq!(machine.state(&rules, "solitaire"), RuleState::Value("off".to_string()));
+    }
+
+    #[test]
+    fn only_provisioned_packages_are_reached_for_other_users() {
+        let mut machine = installed();
+        machine.other_user = true;
+        let (registry, subsystems) = machine.split();
+        let unreachable = |scope| {
+            let rules = package_rule("Clipchamp.Clipchamp", None, scope);
+            unreachable_entry(registry, &subsystems, &rules["solitaire"])
+        };
+        assert!(unreachable(None).is_some());
+        assert!(unreachable(Some(PackageScope::User)).is_some());
+        assert_eq!(unreachable(Some(PackageScope::Provisioned)), None);
+    }
+
+    #[test]
+    fn plans_leave_packages_alone() {
+        let rules = package_rule("Clipchamp.Clipchamp", None, None);
+        let mut machine = installed();
+        let plan = machine.plan(&rules, "solitaire", "off");
+        let changes: Vec<String> = plan.subsystem_changes.iter().map(|change| change.to_string()).collect();
+        assert_eq!(
+            changes,
+            vec![
+                "package Clipchamp.Clipchamp_1.0.0.0_neutral__yxz26nhyzhsrt: removed for the current user",
+                "package Clipchamp.Clipchamp_yxz26nhyzhsrt: deprovisioned",
+            ]
+        );
+        assert_eq!(names(&machine.packages, PackageScope::Provisioned).len(), 2);
+    }
+}
@@ -0,0 +1,71 @@
+use super::{Package, PackageManager, PackageScope};
+use crate::error::DejunkerError;
+use std::fmt;
+use std::result::Result;
+
+/// A modification made to the appx packages
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum PackageChange {
+    /// A package removed for the current user
+    Remove { full_name: String },
+    /// A package family no longer provisioned for new users
+    Deprovision { family_name: String },
+}
+
+impl fmt::Display for PackageChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PackageChange::Remove { full_name } => write!(f, "package {}: removed for the current user", full_name),
+            PackageChange::Deprovision { family_name } => write!(f, "package {}: deprovisioned", family_name),
+        }
+    }
+}
+
+/// Wraps another package manager, and keeps a record of every modification made through it.
+/// Queries go to the wrapped package manager. When `apply` is false, modifications are only
+/// recorded and the packages are left alone.
+pub struct RecordingPackages<'a> {
+    inner: &'a mut dyn PackageManager,
+    apply: bool,
+    changes: Vec<PackageChange>,
+}
+
+impl<'a> RecordingPackages<'a> {
+    pub fn new(inner: &'a mut dyn PackageManager, apply: bool) -> Self {
+        RecordingPackages {
+            inner,
+            apply,
+            changes: Vec::new(),
+        }
+    }
+
+    /// All modifications, in the order they were made
+    pub fn changes(&self) -> &[PackageChange] {
+        &self.changes
+    }
+
+    pub fn into_changes(self) -> Vec<PackageChange> {
+        self.changes
+    }
+}
+
+impl PackageManager for RecordingPackages<'_> {
+    fn packages(&self, scope: PackageScope) -> Result<Vec<Package>, DejunkerError> {
+        self.inner.packages(scope)
+    }
+
+    fn remove(&mut self, scope: PackageScope, package: &Package) -> Result<(), DejunkerError> {
+        if self.apply {
+            self.inner.remove(scope, package)?;
+        }
+        self.changes.push(match scope {
+            PackageScope::User => PackageChange::Remove {
+                full_name: package.full_name.clone(),
+            },
+            PackageScope::Provisioned => PackageChange::Deprovision {
+                family_name: package.family_name.clone(),
+            },
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use super::{Package, PackageManager, PackageScope};
+use crate::error::DejunkerError;
+use crate::utils::system_error;
+use log::debug;
+use std::result::Result;
+use windows::core::HSTRING;
+use windows::ApplicationModel::Package as AppxPackage;
+use windows::Management::Deployment::PackageManager as AppxPackageManager;
+
+/// The appx packages of the machine we are running on, accessed through the Windows Runtime
+/// package manager. Listing and removing the packages of the current user works without
+/// elevation, provisioned packages need an elevated process.
+pub struct Win32Packages {
+    manager: AppxPackageManager,
+}
+
+impl Win32Packages {
+    /// Create the package manager
+    pub fn new() -> Result<Self, DejunkerError> {
+        let manager =
+            AppxPackageManager::new().map_err(|e| system_error("Failed to create the package manager".to_string(), &e))?;
+        Ok(Win32Packages { manager })
+    }
+}
+
+impl PackageManager for Win32Packages {
+    fn packages(&self, scope: PackageScope) -> Result<Vec<Package>, DejunkerError> {
+        let failed = |e: windows::core::Error| system_error(format!("Failed to list the {} packages", scope), &e);
+
+        let found: Vec<AppxPackage> = match scope {
+            // an empty security id is the current user
+            PackageScope::User => self
+                .manager
+                .FindPackagesByUserSecurityId(&HSTRING::new())
+                .map_err(failed)?
+                .into_iter()
+                .collect(),
+            PackageScope::Provisioned => self.manager.FindProvisionedPackages().map_err(failed)?.into_iter().collect(),
+        };
+
+        found
+            .iter()
+            .map(|package| {
+                let id = package.Id()?;
+                Ok(Package {
+                    full_name: id.FullName()?.to_string(),
+                    name: id.Name()?.to_string(),
+                    family_name: id.FamilyName()?.to_string(),
+                    publisher: id.Publisher()?.to_string(),
+                    publisher_id: id.PublisherId()?.to_string(),
+                })
+            })
+            .collect::<windows::core::Result<Vec<Package>>>()
+            .map_err(failed)
+    }
+
+    fn remove(&mut self, scope: PackageScope, package: &Package) -> Result<(), DejunkerError> {
+        // the operations are asynchronous, wait for them to complete
+        match scope {
+            PackageScope::User => self
+                .manager
+                .RemovePackageAsync(&HSTRING::from(package.full_name.as_str()))
+                .and_then(|operation| operation.get())
+                .map_err(|e| system_error(format!("Failed to remove package {}", package.full_name), &e))?,
+            PackageScope::Provisioned => self
+                .manager
+                .DeprovisionPackageForAllUsersAsync(&HSTRING::from(package.family_name.as_str()))
+                .and_then(|operation| operation.get())
+                .map_err(|e| system_error(format!("Failed to deprovision package {}", package.family_name), &e))?,
+        };
+        debug!("Removed {} package {}", scope, package.full_name);
+        Ok(())
+    }
+}
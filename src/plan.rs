@@ -0,0 +1,372 @@
+use log::warn;
+use std::collections::HashMap;
+
+use crate::error::DejunkerError;
+use crate::files::db::{Exec, ExecAction, Rule};
+use crate::files::journal::{self, Journal};
+use crate::files::settings::{self, Settings};
+use crate::registry::recorder::{Change, RecordingRegistry};
+use crate::registry::{self, MemoryRegistry, RegistryBackend, RegistryValue};
+use crate::subsystems::{RecordingSubsystems, Subsystems, SubsystemChange};
+use crate::types::state::RuleState;
+use crate::utils;
+
+/// What applying a rule would do, worked out without modifying the registry (or services)
+#[derive(Debug, Clone)]
+pub struct RulePlan {
+    pub rule: String,
+    /// The current state of the rule, None if the rule cannot be reached or needs admin rights
+    /// (and is skipped)
+    pub current: Option<RuleState>,
+    pub desired: String,
+    /// The writes applying the rule would make, with the values they would replace
+    pub changes: Vec<Change>,
+    /// The changes applying the rule would make through other subsystems (e.g. to services)
+    pub subsystem_changes: Vec<SubsystemChange>,
+}
+
+/// What undoing a journal would do, worked out without modifying the registry (or services)
+#[derive(Debug, Clone)]
+pub struct UndoPlan {
+    /// The writes undoing the journal would make, with the values they would replace
+    pub changes: Vec<Change>,
+    /// The changes undoing the journal would make through other subsystems
+    pub subsystem_changes: Vec<SubsystemChange>,
+}
+
+/// Work out what applying a rule would do
+///
+/// * registry: the registry the rule would be applied to (it is only read)
+/// * subsystems: the backends of the other subsystems (they are only read)
+/// * rules: the list of known rules
+/// * rule_name: the name of the rule
+/// * desired_value: the value the rule would be set to
+/// * skip_inaccessible: plan to skip the rule if it cannot be applied, instead of failing
+///
+pub fn plan_rule(
+    registry: &mut dyn RegistryBackend,
+    subsystems: &mut Subsystems,
+    rules: &HashMap<String, Rule>,
+    rule_name: &str,
+    desired_value: &str,
+    skip_inaccessible: bool,
+) -> Result<RulePlan, DejunkerError> {
+    let rule = rules
+        .get(rule_name)
+        .ok_or_else(|| DejunkerError::UnknownRule(rule_name.to_string()))?;
+
+    let mut recorder = RecordingRegistry::new(registry, false);
+    let mut recording = RecordingSubsystems::new(subsystems, false);
+    settings::execute_rule(
+        &mut recorder,
+        &mut recording.subsystems(),
+        rules,
+        rule_name,
+        desired_value,
+        skip_inaccessible,
+    )?;
+    let changes = recorder.into_changes();
+    let subsystem_changes = recording.into_changes();
+
+    let needs_elevation = rule.admin_required && registry.requires_elevation() && !utils::is_elevated();
+    let current = match settings::unreachable_entry(registry, subsystems, rule) {
+        None if !needs_elevation => Some(settings::evaluate_rule(registry, subsystems, rule)?),
+        _ => None,
+    };
+
+    Ok(RulePlan {
+        rule: rule_name.to_string(),
+        current,
+        desired: desired_value.to_string(),
+        changes,
+        subsystem_changes,
+    })
+}
+
+/// Work out what applying a settings file would do, in the order the settings would be applied
+///
+/// * registry: the registry the settings would be applied to (it is only read)
+/// * subsystems: the backends of the other subsystems (they are only read)
+/// * rules: the list of known rules
+/// * settings: the settings to plan for
+/// * skip_inaccessible: plan to skip rules that cannot be applied, instead of failing
+///
+pub fn plan_settings(
+    registry: &mut dyn RegistryBackend,
+    subsystems: &mut Subsystems,
+    rules: &HashMap<String, Rule>,
+    settings: &Settings,
+    skip_inaccessible: bool,
+) -> Result<Vec<RulePlan>, DejunkerError> {
+    let mut sorted_settings = settings.settings.iter().collect::<Vec<_>>();
+    sorted_settings.sort();
+    sorted_settings
+        .into_iter()
+        .map(|(key, value)| plan_rule(registry, subsystems, rules, key, value, skip_inaccessible))
+        .collect()
+}
+
+/// Work out what undoing a journal would do
+///
+/// * registry: the registry the journal would be undone in (it is only read)
+/// * subsystems: the backends of the other subsystems (they are only read)
+/// * journal: the journal to undo
+///
+pub fn plan_undo(
+    registry: &mut dyn RegistryBackend,
+    subsystems: &mut Subsystems,
+    journal: &Journal,
+) -> Result<UndoPlan, DejunkerError> {
+    let mut recorder = RecordingRegistry::new(registry, false);
+    let mut recording = RecordingSubsystems::new(subsystems, false);
+    journal::undo_journal(&mut recorder, &mut recording.subsystems(), journal)?;
+    Ok(UndoPlan {
+        changes: recorder.into_changes(),
+        subsystem_changes: recording.into_changes(),
+    })
+}
+
+/// Work out the registry writes a settings file makes, regardless of any registry (e.g. to
+/// export them as a .reg or .pol file for another machine). Only registry changes can be
+/// exported: rules for other subsystems are skipped, and so are rules adding or removing an
+/// item of a list, as the list they would change is not known. Keys are deleted without the
+/// backup their rule asks for, as the files have no way of taking one.
+///
+/// * rules: the list of known rules
+/// * settings: the settings to export
+///
+pub fn export_settings(rules: &HashMap<String, Rule>, settings: &Settings) -> Result<Vec<Change>, DejunkerError> {
+    let mut exported = settings.clone();
+    exported.settings.retain(|name, value| {
+        let Some(rule) = rules.get(name) else {
+            return true;
+        };
+        if rule.exec.iter().any(|op| op.item.is_some()) {
+            warn!("Rule {} was skipped, items of a list cannot be exported.", name);
+            return false;
+        }
+        let backs_up = |op: &Exec| {
+            matches!(op.values.get(value.as_str()), Some(ExecAction::DeleteKey { backup: true }))
+        };
+        if rule.exec.iter().any(backs_up) {
+            warn!("Rule {} deletes a key without backing it up, exported files cannot take a backup.", name);
+        }
+        true
+    });
+
+    let mut target = MemoryRegistry::new();
+    let mut recorder = RecordingRegistry::new(&mut target, false);
+    settings::apply_settings(&mut recorder, &mut Subsystems::none(), rules, &exported, true)?;
+    // every key is missing from the empty registry, and the files create them anyway
+    let mut changes = recorder.into_changes();
+    changes.retain(|change| !matches!(change, Change::CreateKey { .. }));
+    Ok(changes)
+}
+
+/// Render plans as text, one line per rule followed by one line per registry write and per
+/// change made through another subsystem
+pub fn format_plan(plans: &[RulePlan]) -> String {
+    let mut output = String::new();
+
+    for plan in plans {
+        match &plan.current {
+            None => {
+                output.push_str(&format!("{}: skipped (not accessible, or needs admin rights)\n", plan.rule));
+                continue;
+            }
+            Some(current) if RuleState::is_report_only(&plan.desired) => {
+                output.push_str(&format!("{}: {} (left as is)\n", plan.rule, current));
+            }
+            Some(current) if current.to_string() == plan.desired => {
+                output.push_str(&format!("{}: {} (unchanged)\n", plan.rule, current));
+            }
+            Some(current) => {
+                output.push_str(&format!("{}: {} -> {}\n", plan.rule, current, plan.desired));
+            }
+        }
+
+        output.push_str(&format_changes(&plan.changes, &plan.subsystem_changes));
+    }
+    output
+}
+
+/// Render an undo plan as text, one line per registry write and per change made through
+/// another subsystem
+pub fn format_undo_plan(plan: &UndoPlan) -> String {
+    format_changes(&plan.changes, &plan.subsystem_changes)
+}
+
+/// One indented line per registry write, then per change made through another subsystem
+fn format_changes(changes: &[Change], subsystem_changes: &[SubsystemChange]) -> String {
+    let mut output = String::new();
+    for change in changes {
+        output.push_str(&format!("    {}\n", format_change(change)));
+    }
+    for change in subsystem_changes {
+        output.push_str(&format!("    {}\n", change));
+    }
+    output
+}
+
+/// A single write, with the hive spelled out
+fn format_change(change: &Change) -> String {
+    let key = match registry::get_path_components(change.path()) {
+        Ok((hive, sub_path)) => format!("{}\\{}", hive, sub_path),
+        Err(_) => change.path().to_string(),
+    };
+
+    match change {
+        Change::SetValue {
+            name,
+            previous,
+            value,
+            ..
+        } => {
+            let unchanged = if previous.as_ref() == Some(value) { " (no change)" } else { "" };
+            format!(
+                "{} -> {}: {} -> {}{}",
+                key,
+                name,
+                format_previous(previous),
+                value,
+                unchanged
+            )
+        }
+        Change::DeleteValue { name, previous, .. } => {
+            format!("{} -> {}: {} -> (deleted)", key, name, format_previous(previous))
+        }
+        Change::DeleteKey { .. } => format!("{}: key deleted", key),
+        Change::CreateKey { .. } => format!("{}: key created", key),
+    }
+}
+
+fn format_previous(previous: &Option<RegistryValue>) -> String {
+    match previous {
+        Some(value) => value.to_string(),
+        None => "(not set)".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{registry_exec, rule, rules};
+    use std::collections::BTreeMap;
+
+    const KEY: &str = "HKLM\\SOFTWARE\\Policies\\Microsoft\\Windows\\Explorer";
+
+    fn policy_rule(name: &str, exec: Exec) -> Rule {
+        Rule {
+            admin_required: true,
+            ..rule(name, "OnOff", vec![exec])
+        }
+    }
+
+    #[test]
+    fn exports_leave_out_items_of_lists() {
+        let rules = rules([
+            policy_rule(
+                "no-recent-docs",
+                Exec {
+                    reversed: Some(true),
+                    ..registry_exec(KEY, "NoRecentDocsHistory", "u32")
+                },
+            ),
+            policy_rule(
+                "start-suggestions",
+                Exec {
+                    item: Some("Suggestions".to_string()),
+                    ..registry_exec(KEY, "HiddenItems", "multi_string")
+                },
+            ),
+        ]);
+        let settings = Settings {
+            file: settings::FILE_MARKER.to_string(),
+            settings: HashMap::from([
+                ("no-recent-docs".to_string(), "off".to_string()),
+                ("start-suggestions".to_string(), "off".to_string()),
+            ]),
+        };
+
+        let changes = export_settings(&rules, &settings).unwrap();
+        assert_eq!(
+            changes,
+            [Change::SetValue {
+                path: KEY.to_string(),
+                name: "NoRecentDocsHistory".to_string(),
+                previous: None,
+                value: RegistryValue::Dword(1),
+            }]
+        );
+    }
+
+    #[test]
+    fn exports_delete_keys_without_their_backup() {
+        let rules = rules([policy_rule(
+            "no-recent-docs",
+            Exec {
+                values: BTreeMap::from([
+                    ("on".to_string(), ExecAction::Write("1".to_string())),
+                    ("off".to_string(), ExecAction::DeleteKey { backup: true }),
+                ]),
+                ..registry_exec(KEY, "NoRecentDocsHistory", "u32")
+            },
+        )]);
+        let settings = Settings {
+            file: settings::FILE_MARKER.to_string(),
+            settings: HashMap::from([("no-recent-docs".to_string(), "off".to_string())]),
+        };
+
+        let changes = export_settings(&rules, &settings).unwrap();
+        assert_eq!(
+            changes,
+            [Change::DeleteKey {
+                path: KEY.to_string(),
+                previous: Vec::new(),
+            }]
+        );
+    }
+
+    #[test]
+    fn plans_list_the_writes_and_leave_the_registry_alone() {
+        let rules = rules([rule("no-recent-docs", "OnOff", vec![registry_exec(KEY, "NoRecentDocsHistory", "u32")])]);
+        let mut registry = MemoryRegistry::new();
+        registry.set_u32_value(KEY, "NoRecentDocsHistory", 0).unwrap();
+
+        let plan = plan_rule(&mut registry, &mut Subsystems::none(), &rules, "no-recent-docs", "on", false).unwrap();
+        assert_eq!(plan.current.unwrap().to_string(), "off");
+        assert_eq!(
+            plan.changes,
+            [Change::SetValue {
+                path: KEY.to_string(),
+                name: "NoRecentDocsHistory".to_string(),
+                previous: Some(RegistryValue::Dword(0)),
+                value: RegistryValue::Dword(1),
+            }]
+        );
+        assert_eq!(
+            registry.get_value(KEY, "NoRecentDocsHistory").unwrap(),
+            Some(RegistryValue::Dword(0))
+        );
+    }
+
+    #[test]
+    fn undo_plans_leave_the_registry_alone() {
+        let rules = rules([policy_rule("no-recent-docs", registry_exec(KEY, "NoRecentDocsHistory", "u32"))]);
+        let mut registry = MemoryRegistry::new();
+        registry.set_u32_value(KEY, "NoRecentDocsHistory", 0).unwrap();
+        let mut recorder = RecordingRegistry::new(&mut registry, true);
+        settings::execute_rule(&mut recorder, &mut Subsystems::none(), &rules, "no-recent-docs", "on", false).unwrap();
+        let journal = Journal::from_changes(recorder.changes(), &[]);
+
+        let plan = plan_undo(&mut registry, &mut Subsystems::none(), &journal).unwrap();
+        assert_eq!(
+            format_undo_plan(&plan),
+            format!("    HKEY_LOCAL_MACHINE\\{} -> NoRecentDocsHistory: 1 -> 0\n", &KEY[5..])
+        );
+        assert_eq!(
+            registry.get_value(KEY, "NoRecentDocsHistory").unwrap(),
+            Some(RegistryValue::Dword(1))
+        );
+    }
+}
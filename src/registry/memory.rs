@@ -0,0 +1,164 @@
+use super::{get_path_components, split_key_path, Hive, RegistryBackend, RegistryValue};
+use crate::error::DejunkerError;
+use log::debug;
+use std::collections::BTreeMap;
+use std::result::Result;
+
+/// A registry key held in memory. Like the real registry, names are case insensitive
+/// but case preserving, so children are indexed by their lowercase name.
+#[derive(Debug, Clone, Default)]
+struct MemoryKey {
+    name: String,
+    subkeys: BTreeMap<String, MemoryKey>,
+    values: BTreeMap<String, (String, RegistryValue)>,
+}
+
+impl MemoryKey {
+    fn new(name: &str) -> Self {
+        MemoryKey {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Find a descendant key
+    fn find(&self, sub_path: &str) -> Option<&MemoryKey> {
+        split_key_path(sub_path).try_fold(self, |key, part| key.subkeys.get(&part.to_lowercase()))
+    }
+
+    /// Find a descendant key, creating it (and any missing parent) if it does not exist
+    fn find_or_create(&mut self, sub_path: &str) -> &mut MemoryKey {
+        split_key_path(sub_path).fold(self, |key, part| {
+            key.subkeys
+                .entry(part.to_lowercase())
+                .or_insert_with(|| MemoryKey::new(part))
+        })
+    }
+
+    /// Find a descendant key for modification, without creating it
+    fn find_mut(&mut self, sub_path: &str) -> Option<&mut MemoryKey> {
+        split_key_path(sub_path).try_fold(self, |key, part| key.subkeys.get_mut(&part.to_lowercase()))
+    }
+}
+
+/// A registry that only exists in memory. All hives start out empty.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryRegistry {
+    hives: BTreeMap<Hive, MemoryKey>,
+}
+
+impl MemoryRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Find a key by its full path
+    fn key(&self, path: &str) -> Result<Option<&MemoryKey>, DejunkerError> {
+        let (hive, sub_path) = get_path_components(path)?;
+        Ok(self.hives.get(&hive).and_then(|root| root.find(&sub_path)))
+    }
+}
+
+impl RegistryBackend for MemoryRegistry {
+    fn get_value(&self, path: &str, value_name: &str) -> Result<Option<RegistryValue>, DejunkerError> {
+        let value = self
+            .key(path)?
+            .and_then(|key| key.values.get(&value_name.to_lowercase()))
+            .map(|(_, value)| value.clone());
+
+        debug!("Reading memory registry value: {} -> {}: {:?}", path, value_name, value);
+        Ok(value)
+    }
+
+    fn set_value(&mut self, path: &str, value_name: &str, value: &RegistryValue) -> Result<(), DejunkerError> {
+        debug!("Setting memory registry value: {} -> {}: {}", path, value_name, value);
+        let (hive, sub_path) = get_path_components(path)?;
+
+        let key = self
+            .hives
+            .entry(hive)
+            .or_insert_with(|| MemoryKey::new(hive.name()))
+            .find_or_create(&sub_path);
+        key.values.insert(
+            value_name.to_lowercase(),
+            (value_name.to_string(), value.clone()),
+        );
+        Ok(())
+    }
+
+    fn delete_value(&mut self, path: &str, value_name: &str) -> Result<(), DejunkerError> {
+        debug!("Deleting memory registry value: {} -> {}", path, value_name);
+        let (hive, sub_path) = get_path_components(path)?;
+
+        if let Some(key) = self.hives.get_mut(&hive).and_then(|root| root.find_mut(&sub_path)) {
+            key.values.remove(&value_name.to_lowercase());
+        }
+        Ok(())
+    }
+
+    fn delete_key(&mut self, path: &str) -> Result<(), DejunkerError> {
+        debug!("Deleting memory registry key: {}", path);
+        let (hive, sub_path) = get_path_components(path)?;
+
+        let (parent_path, name) = match sub_path.rsplit_once('\\') {
+            Some((parent, name)) => (parent, name),
+            None => ("", sub_path.as_str()),
+        };
+        if name.is_empty() {
+            return Err(DejunkerError::registry(format!("Refusing to delete the root of {}", hive)));
+        }
+
+        if let Some(parent) = self.hives.get_mut(&hive).and_then(|root| root.find_mut(parent_path)) {
+            parent.subkeys.remove(&name.to_lowercase());
+        }
+        Ok(())
+    }
+
+    fn enum_keys(&self, path: &str) -> Result<Vec<String>, DejunkerError> {
+        Ok(self
+            .key(path)?
+            .map(|key| key.subkeys.values().map(|k| k.name.clone()).collect())
+            .unwrap_or_default())
+    }
+
+    fn enum_values(&self, path: &str) -> Result<Vec<String>, DejunkerError> {
+        Ok(self
+            .key(path)?
+            .map(|key| key.values.values().map(|(name, _)| name.clone()).collect())
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_case_insensitive_but_case_preserving() {
+        let mut registry = MemoryRegistry::new();
+        registry.set_u32_value("HKCU\\Software\\Test", "Value", 1).unwrap();
+        registry.set_u32_value("HKEY_CURRENT_USER\\SOFTWARE\\test", "VALUE", 2).unwrap();
+
+        assert_eq!(registry.get_value("hkcu\\software\\TEST", "value").unwrap(), Some(RegistryValue::Dword(2)));
+        assert_eq!(registry.enum_keys("HKCU\\Software").unwrap(), ["Test"]);
+        assert_eq!(registry.enum_values("HKCU\\Software\\Test").unwrap(), ["VALUE"]);
+        assert_eq!(registry.get_value("HKLM\\Software\\Test", "Value").unwrap(), None);
+    }
+
+    #[test]
+    fn keys_are_deleted_with_their_subkeys() {
+        let mut registry = MemoryRegistry::new();
+        registry.set_u32_value("HKCU\\Software\\Test\\Sub", "Value", 1).unwrap();
+        registry.set_u32_value("HKCU\\Software\\Other", "Value", 1).unwrap();
+
+        registry.delete_key("HKCU\\Software\\TEST").unwrap();
+        assert!(!registry.key_exists("HKCU\\Software\\Test").unwrap());
+        assert_eq!(registry.get_value("HKCU\\Software\\Test\\Sub", "Value").unwrap(), None);
+        assert!(registry.key_exists("HKCU\\Software\\Other").unwrap());
+
+        // deleting what does not exist is not an error, deleting a hive is
+        registry.delete_key("HKCU\\Software\\Test").unwrap();
+        registry.delete_value("HKCU\\Software\\Other", "Missing").unwrap();
+        assert!(registry.delete_key("HKCU").is_err());
+    }
+}
@@ -0,0 +1,664 @@
+use crate::error::DejunkerError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::result::Result;
+
+mod memory;
+mod offline;
+pub mod recorder;
+mod regf;
+pub mod users;
+#[cfg(windows)]
+mod win32;
+
+pub use memory::MemoryRegistry;
+pub use offline::OfflineRegistry;
+#[cfg(windows)]
+pub use win32::Win32Registry;
+
+// registry data types (REG_*), as stored by the registry itself
+pub const REG_SZ: u32 = 1;
+pub const REG_EXPAND_SZ: u32 = 2;
+pub const REG_BINARY: u32 = 3;
+pub const REG_DWORD: u32 = 4;
+pub const REG_MULTI_SZ: u32 = 7;
+pub const REG_QWORD: u32 = 11;
+
+/// Where deleted keys are backed up to, in the hive they were deleted from (as
+/// HIVE\BACKUP_KEY\path of the deleted key)
+pub const BACKUP_KEY: &str = "Software\\dejunker\\Backup";
+
+/// The key 64-bit Windows keeps the 32-bit view of a redirected key in, below that key
+pub const WOW64_32_KEY: &str = "WOW6432Node";
+
+/// The keys 64-bit Windows keeps a separate copy of for 32-bit programs (the most specific
+/// first). Everything else is shared by both views.
+const WOW64_REDIRECTED_KEYS: [(Hive, &str); 4] = [
+    (Hive::LocalMachine, "Software\\Classes"),
+    (Hive::LocalMachine, "Software"),
+    (Hive::CurrentUser, "Software\\Classes"),
+    (Hive::ClassesRoot, ""),
+];
+
+/// The views of the registry on 64-bit Windows: 64-bit programs (like us) see the 64-bit
+/// view, 32-bit programs see the 32-bit view, where some keys are redirected below
+/// WOW6432Node
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum RegistryView {
+    Bits32,
+    Bits64,
+    Both,
+}
+
+impl RegistryView {
+    /// Parse a view name (32, 64 or both)
+    pub fn from_name(name: &str) -> Option<RegistryView> {
+        match name.to_lowercase().as_str() {
+            "32" => Some(RegistryView::Bits32),
+            "64" => Some(RegistryView::Bits64),
+            "both" => Some(RegistryView::Both),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RegistryView::Bits32 => "32",
+            RegistryView::Bits64 => "64",
+            RegistryView::Both => "both",
+        }
+    }
+}
+
+impl fmt::Display for RegistryView {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The root keys of the registry
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum Hive {
+    LocalMachine,
+    CurrentUser,
+    ClassesRoot,
+    Users,
+    CurrentConfig,
+}
+
+impl Hive {
+    /// Parse a hive name, either in long (HKEY_LOCAL_MACHINE) or short (HKLM) form
+    pub fn from_name(name: &str) -> Option<Hive> {
+        match name.to_uppercase().as_str() {
+            "HKLM" | "HKEY_LOCAL_MACHINE" => Some(Hive::LocalMachine),
+            "HKCU" | "HKEY_CURRENT_USER" => Some(Hive::CurrentUser),
+            "HKCR" | "HKEY_CLASSES_ROOT" => Some(Hive::ClassesRoot),
+            "HKU" | "HKEY_USERS" => Some(Hive::Users),
+            "HKCC" | "HKEY_CURRENT_CONFIG" => Some(Hive::CurrentConfig),
+            _ => None,
+        }
+    }
+
+    /// The long form name of the hive
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hive::LocalMachine => "HKEY_LOCAL_MACHINE",
+            Hive::CurrentUser => "HKEY_CURRENT_USER",
+            Hive::ClassesRoot => "HKEY_CLASSES_ROOT",
+            Hive::Users => "HKEY_USERS",
+            Hive::CurrentConfig => "HKEY_CURRENT_CONFIG",
+        }
+    }
+}
+
+impl fmt::Display for Hive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A typed registry value. Data types we don't interpret are kept as raw bytes,
+/// so they survive a read/write cycle unchanged.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RegistryValue {
+    Dword(u32),
+    Qword(u64),
+    String(String),
+    /// A string with %VARIABLE% references, expanded by whoever reads it (REG_EXPAND_SZ)
+    ExpandString(String),
+    /// A list of strings (REG_MULTI_SZ)
+    MultiString(Vec<String>),
+    Binary(Vec<u8>),
+    Other { kind: u32, data: Vec<u8> },
+}
+
+impl RegistryValue {
+    /// Decode a value from its registry type (REG_*) and raw little endian data
+    pub fn from_raw(kind: u32, data: &[u8]) -> RegistryValue {
+        match kind {
+            REG_DWORD if data.len() == 4 => {
+                RegistryValue::Dword(u32::from_le_bytes([data[0], data[1], data[2], data[3]]))
+            }
+            REG_QWORD if data.len() == 8 => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(data);
+                RegistryValue::Qword(u64::from_le_bytes(bytes))
+            }
+            REG_SZ => RegistryValue::String(decode_utf16(data)),
+            REG_EXPAND_SZ => RegistryValue::ExpandString(decode_utf16(data)),
+            REG_MULTI_SZ => RegistryValue::MultiString(decode_multi_utf16(data)),
+            REG_BINARY => RegistryValue::Binary(data.to_vec()),
+            _ => RegistryValue::Other {
+                kind,
+                data: data.to_vec(),
+            },
+        }
+    }
+
+    /// Registry type (REG_*) of the value
+    pub fn kind(&self) -> u32 {
+        match self {
+            RegistryValue::Dword(_) => REG_DWORD,
+            RegistryValue::Qword(_) => REG_QWORD,
+            RegistryValue::String(_) => REG_SZ,
+            RegistryValue::ExpandString(_) => REG_EXPAND_SZ,
+            RegistryValue::MultiString(_) => REG_MULTI_SZ,
+            RegistryValue::Binary(_) => REG_BINARY,
+            RegistryValue::Other { kind, .. } => *kind,
+        }
+    }
+
+    /// Raw data of the value, as the registry stores it
+    pub fn to_raw(&self) -> Vec<u8> {
+        match self {
+            RegistryValue::Dword(value) => value.to_le_bytes().to_vec(),
+            RegistryValue::Qword(value) => value.to_le_bytes().to_vec(),
+            RegistryValue::String(value) | RegistryValue::ExpandString(value) => encode_utf16(value),
+            RegistryValue::MultiString(items) => encode_multi_utf16(items),
+            RegistryValue::Binary(data) => data.clone(),
+            RegistryValue::Other { data, .. } => data.clone(),
+        }
+    }
+}
+
+impl fmt::Display for RegistryValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegistryValue::Dword(value) => write!(f, "{}", value),
+            RegistryValue::Qword(value) => write!(f, "{}", value),
+            RegistryValue::String(value) | RegistryValue::ExpandString(value) => write!(f, "{}", value),
+            RegistryValue::MultiString(items) => write!(f, "{:?}", items),
+            RegistryValue::Binary(data) => {
+                let bytes: Vec<String> = data.iter().map(|byte| format!("{:02x}", byte)).collect();
+                write!(f, "hex:{}", bytes.join(","))
+            }
+            RegistryValue::Other { kind, data } => {
+                write!(f, "<type {}, {} bytes>", kind, data.len())
+            }
+        }
+    }
+}
+
+/// Access to a registry. The live Windows registry is one implementation, but rules
+/// can be evaluated and applied against anything implementing this trait.
+///
+/// All paths include the hive name, e.g. HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft
+pub trait RegistryBackend {
+    /// Read a value. Returns None if the key or the value does not exist.
+    fn get_value(&self, path: &str, value_name: &str) -> Result<Option<RegistryValue>, DejunkerError>;
+
+    /// Write a value. The key (and any missing parent) will be created if it doesn't exist.
+    fn set_value(&mut self, path: &str, value_name: &str, value: &RegistryValue) -> Result<(), DejunkerError>;
+
+    /// Delete a value. Deleting a value that does not exist is not an error.
+    fn delete_value(&mut self, path: &str, value_name: &str) -> Result<(), DejunkerError>;
+
+    /// Delete a key, including all its subkeys and values. Deleting a key that does not
+    /// exist is not an error.
+    fn delete_key(&mut self, path: &str) -> Result<(), DejunkerError>;
+
+    /// Names of the direct subkeys of a key (empty if the key does not exist)
+    fn enum_keys(&self, path: &str) -> Result<Vec<String>, DejunkerError>;
+
+    /// Names of the values of a key (empty if the key does not exist)
+    fn enum_values(&self, path: &str) -> Result<Vec<String>, DejunkerError>;
+
+    /// Whether a key exists. The root key of a hive always exists.
+    fn key_exists(&self, path: &str) -> Result<bool, DejunkerError> {
+        match path.trim_end_matches('\\').rsplit_once('\\') {
+            Some((parent, name)) => Ok(self
+                .enum_keys(parent)?
+                .iter()
+                .any(|key| key.eq_ignore_ascii_case(name))),
+            None => Ok(true),
+        }
+    }
+
+    /// Copy a key to its backup key (see backup_path) before it is deleted. Wrappers that
+    /// record modifications do not record the copy, so undoing them leaves the backup alone.
+    fn back_up_key(&mut self, path: &str) -> Result<(), DejunkerError> {
+        copy_key(self, path, &backup_path(path)?)
+    }
+
+    /// Whether a path can be reached through this backend at all
+    fn is_accessible(&self, _path: &str) -> bool {
+        true
+    }
+
+    /// Whether writes are subject to the access rights of the current process (i.e. rules
+    /// flagged admin_required need an elevated process)
+    fn requires_elevation(&self) -> bool {
+        false
+    }
+
+    /// Read a value from registry, formatted as a string. Returns None if the value does not exist.
+    ///
+    /// * path: the registry path (includes HIVE name, eg. HKEY_LOCAL_MACHINE\....)
+    /// * value: the registry value name
+    /// * datatype: the data type of the value (u32, i32, u64, i64, string, expand_string,
+    ///   multi_string, binary)
+    fn read_value(&self, path: &str, value: &str, datatype: &str) -> Result<Option<String>, DejunkerError> {
+        let expected = get_datatype(datatype)?;
+
+        match self.get_value(path, value)? {
+            Some(data) if data.kind() == expected => Ok(Some(data.to_string())),
+            Some(data) => Err(DejunkerError::registry(format!(
+                "Registry value {}\\{} has type {}, expected {}",
+                path,
+                value,
+                data.kind(),
+                expected
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Set a u32 value to the registry (as a DWORD). The key will be created if it doesn't exist.
+    ///
+    /// * path: The registry path (includes HIVE name, eg. HKEY_LOCAL_MACHINE\....)
+    /// * value_name: The registry value name
+    fn set_u32_value(&mut self, path: &str, value_name: &str, value: u32) -> Result<(), DejunkerError> {
+        self.set_value(path, value_name, &RegistryValue::Dword(value))
+    }
+
+    /// Set a u64 value to the registry (as a QWORD). The key will be created if it doesn't exist.
+    ///
+    /// * path: The registry path (includes HIVE name, eg. HKEY_LOCAL_MACHINE\....)
+    /// * value_name: The registry value name
+    fn set_u64_value(&mut self, path: &str, value_name: &str, value: u64) -> Result<(), DejunkerError> {
+        self.set_value(path, value_name, &RegistryValue::Qword(value))
+    }
+
+    /// Set a string value to the registry (as a REG_SZ). The key will be created if it doesn't exist.
+    ///
+    /// * path: The registry path (includes HIVE name, eg. HKEY_LOCAL_MACHINE\....)
+    /// * value_name: The registry value name
+    fn set_string_value(&mut self, path: &str, value_name: &str, value: &str) -> Result<(), DejunkerError> {
+        self.set_value(path, value_name, &RegistryValue::String(value.to_string()))
+    }
+
+    /// Set an expandable string value to the registry (as a REG_EXPAND_SZ, %VARIABLES% are
+    /// kept as they are). The key will be created if it doesn't exist.
+    ///
+    /// * path: The registry path (includes HIVE name, eg. HKEY_LOCAL_MACHINE\....)
+    /// * value_name: The registry value name
+    fn set_expand_string_value(&mut self, path: &str, value_name: &str, value: &str) -> Result<(), DejunkerError> {
+        self.set_value(path, value_name, &RegistryValue::ExpandString(value.to_string()))
+    }
+
+    /// Set a list of strings to the registry (as a REG_MULTI_SZ). The key will be created if it doesn't exist.
+    ///
+    /// * path: The registry path (includes HIVE name, eg. HKEY_LOCAL_MACHINE\....)
+    /// * value_name: The registry value name
+    fn set_multi_string_value(&mut self, path: &str, value_name: &str, items: &[String]) -> Result<(), DejunkerError> {
+        self.set_value(path, value_name, &RegistryValue::MultiString(items.to_vec()))
+    }
+
+    /// Set binary data to the registry (as a REG_BINARY). The key will be created if it doesn't exist.
+    ///
+    /// * path: The registry path (includes HIVE name, eg. HKEY_LOCAL_MACHINE\....)
+    /// * value_name: The registry value name
+    fn set_binary_value(&mut self, path: &str, value_name: &str, data: &[u8]) -> Result<(), DejunkerError> {
+        self.set_value(path, value_name, &RegistryValue::Binary(data.to_vec()))
+    }
+}
+
+/// Backend for the registry of the machine we are running on.
+#[cfg(windows)]
+pub fn local_registry() -> Result<Box<dyn RegistryBackend>, DejunkerError> {
+    Ok(Box::new(Win32Registry))
+}
+
+/// Backend for the registry of the machine we are running on. There is no live registry
+/// outside of Windows.
+#[cfg(not(windows))]
+pub fn local_registry() -> Result<Box<dyn RegistryBackend>, DejunkerError> {
+    Err(DejunkerError::registry("The live registry is only available on Windows"))
+}
+
+/// Copy the values of a key and all its subkeys to another key (created as needed). Keys
+/// without values are not copied.
+///
+/// * registry: the registry to copy in
+/// * from: the key to copy
+/// * to: the key to copy to
+///
+pub fn copy_key<R: RegistryBackend + ?Sized>(registry: &mut R, from: &str, to: &str) -> Result<(), DejunkerError> {
+    for name in registry.enum_values(from)? {
+        if let Some(value) = registry.get_value(from, &name)? {
+            registry.set_value(to, &name, &value)?;
+        }
+    }
+    for key in registry.enum_keys(from)? {
+        copy_key(registry, &format!("{}\\{}", from, key), &format!("{}\\{}", to, key))?;
+    }
+    Ok(())
+}
+
+/// A value of a key or of one of its subkeys
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct KeyValue {
+    pub path: String,
+    pub name: String,
+    pub value: RegistryValue,
+}
+
+/// The values of a key and all its subkeys, parents first. Keys without values are left out,
+/// like copy_key does.
+///
+/// * registry: the registry to read from
+/// * path: the key
+///
+pub fn key_values(registry: &dyn RegistryBackend, path: &str) -> Result<Vec<KeyValue>, DejunkerError> {
+    let mut values = Vec::new();
+    for name in registry.enum_values(path)? {
+        if let Some(value) = registry.get_value(path, &name)? {
+            values.push(KeyValue {
+                path: path.to_string(),
+                name,
+                value,
+            });
+        }
+    }
+    for key in registry.enum_keys(path)? {
+        values.extend(key_values(registry, &format!("{}\\{}", path, key))?);
+    }
+    Ok(values)
+}
+
+/// The key a key is backed up to before it is deleted
+pub fn backup_path(path: &str) -> Result<String, DejunkerError> {
+    let (hive, sub_path) = get_path_components(path)?;
+    Ok(format!("{}\\{}\\{}", hive.name(), BACKUP_KEY, sub_path))
+}
+
+/// The paths a key is seen at in a view of the registry. The 32-bit view of a redirected key
+/// is the WOW6432Node key below it (e.g. HKLM\Software\Vendor is HKLM\Software\WOW6432Node\Vendor),
+/// keys both views share are the same in both. The Win32 backend opens WOW6432Node paths
+/// through the 32-bit view (KEY_WOW64_32KEY), and all other paths through the 64-bit view.
+///
+/// * path: the path of the key, as seen by 64-bit programs
+/// * view: the view(s), None for the view of the running program
+///
+pub fn view_paths(path: &str, view: Option<RegistryView>) -> Result<Vec<String>, DejunkerError> {
+    let (hive, sub_path) = get_path_components(path)?;
+    let path_32 = || -> String {
+        let redirected = split_key_path(&sub_path).any(|key| key.eq_ignore_ascii_case(WOW64_32_KEY));
+        let parent = WOW64_REDIRECTED_KEYS
+            .iter()
+            .filter(|(redirected_hive, _)| *redirected_hive == hive)
+            .map(|(_, key)| *key)
+            .find(|key| is_below(&sub_path, key));
+
+        match parent {
+            Some(parent) if !redirected => {
+                let (parent, rest) = sub_path.split_at(parent.len());
+                let keys = [parent, WOW64_32_KEY, rest.trim_start_matches('\\')];
+                let keys: Vec<&str> = keys.into_iter().filter(|key| !key.is_empty()).collect();
+                format!("{}\\{}", hive.name(), keys.join("\\"))
+            }
+            _ => path.to_string(),
+        }
+    };
+
+    Ok(match view {
+        None | Some(RegistryView::Bits64) => vec![path.to_string()],
+        Some(RegistryView::Bits32) => vec![path_32()],
+        Some(RegistryView::Both) => {
+            let path_32 = path_32();
+            match path_32 == path {
+                true => vec![path_32],
+                false => vec![path.to_string(), path_32],
+            }
+        }
+    })
+}
+
+/// Whether a subpath is a key or below it (case insensitive)
+fn is_below(sub_path: &str, key: &str) -> bool {
+    let (sub_path, key) = (sub_path.to_lowercase(), key.to_lowercase());
+    key.is_empty() || sub_path == key || sub_path.starts_with(&format!("{}\\", key))
+}
+
+/// Split a path into hive and subpath. E.g.
+///
+/// path: The path "HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft" will return (Hive::LocalMachine, "SOFTWARE\Microsoft")
+///
+/// A path that is just a hive name refers to the root key of that hive.
+pub fn get_path_components(path: &str) -> Result<(Hive, String), DejunkerError> {
+    let mut parts = path.splitn(2, '\\');
+    let hive_str = parts.next().ok_or_else(|| DejunkerError::registry(format!("Invalid registry path '{}'", path)))?;
+    let reg_path = parts.next().unwrap_or("");
+
+    let hive = Hive::from_name(hive_str).ok_or_else(|| DejunkerError::registry(format!("Invalid registry hive in '{}'", path)))?;
+
+    Ok((hive, reg_path.trim_matches('\\').to_string()))
+}
+
+/// Split a subpath (no hive) into key names, ignoring empty components
+fn split_key_path(sub_path: &str) -> impl Iterator<Item = &str> {
+    sub_path.split('\\').filter(|part| !part.is_empty())
+}
+
+/// Map datatype from our data string to registry type.
+fn get_datatype(datatype: &str) -> Result<u32, DejunkerError> {
+    match datatype.to_lowercase().as_str() {
+        "u32" => Ok(REG_DWORD),
+        "i32" => Ok(REG_DWORD),
+        "u64" => Ok(REG_QWORD),
+        "i64" => Ok(REG_QWORD),
+        "string" => Ok(REG_SZ),
+        "expand_string" => Ok(REG_EXPAND_SZ),
+        "multi_string" => Ok(REG_MULTI_SZ),
+        "binary" => Ok(REG_BINARY),
+        _ => Err(DejunkerError::UnsupportedValueType(datatype.to_string())),
+    }
+}
+
+/// Read data of unknown length through an API that reports the length it needs (like
+/// RegGetValueW): first without a buffer, then with a buffer of the reported length, grown for
+/// as long as the API reports it is too small (the data can grow between two calls).
+///
+/// * more_data: what the API returns when the buffer is too small
+/// * success: what the API returns when the data was read
+/// * read: calls the API with the buffer (None to only ask for the length) and its length,
+///   which the API sets to the length of the data, or the length it needs
+///
+/// Returns the result of the last call, and the data if it succeeded
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) fn read_sized<R: PartialEq>(
+    more_data: R,
+    success: R,
+    mut read: impl FnMut(Option<&mut [u8]>, &mut u32) -> R,
+) -> (R, Vec<u8>) {
+    let mut buffer: Vec<u8> = Vec::new();
+    loop {
+        let mut length = buffer.len() as u32;
+        let data = match buffer.is_empty() {
+            true => None,
+            false => Some(buffer.as_mut_slice()),
+        };
+        let result = read(data, &mut length);
+
+        let length_only = result == success && buffer.is_empty() && length > 0;
+        if result == more_data || length_only {
+            // always grow, in case the API does not report the length it needs
+            let length = (length as usize).max(buffer.len() * 2).max(64);
+            buffer.resize(length, 0);
+            continue;
+        }
+        if result == success {
+            buffer.truncate(length as usize);
+        } else {
+            buffer.clear();
+        }
+        return (result, buffer);
+    }
+}
+
+/// Decode a (possibly null terminated) little endian UTF-16 buffer
+pub fn decode_utf16(data: &[u8]) -> String {
+    let wide: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    String::from_utf16_lossy(&wide)
+        .trim_end_matches('\u{0}')
+        .to_string()
+}
+
+/// Encode a string as null terminated little endian UTF-16
+pub fn encode_utf16(value: &str) -> Vec<u8> {
+    value
+        .encode_utf16()
+        .chain(Some(0))
+        .flat_map(|c| c.to_le_bytes())
+        .collect()
+}
+
+/// Decode a REG_MULTI_SZ buffer: null terminated UTF-16 strings, followed by an empty one
+pub fn decode_multi_utf16(data: &[u8]) -> Vec<String> {
+    decode_utf16(data)
+        .split('\u{0}')
+        .take_while(|item| !item.is_empty())
+        .map(|item| item.to_string())
+        .collect()
+}
+
+/// Encode a list of strings as a REG_MULTI_SZ buffer
+pub fn encode_multi_utf16(items: &[String]) -> Vec<u8> {
+    let mut data: Vec<u8> = items.iter().flat_map(|item| encode_utf16(item)).collect();
+    data.extend_from_slice(&[0, 0]);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MORE_DATA: u32 = 234;
+    const SUCCESS: u32 = 0;
+
+    /// A reader behaving like RegGetValueW for data that grows by `growth` bytes on every call
+    fn growing_reader(
+        mut data: Vec<u8>,
+        growth: usize,
+    ) -> impl FnMut(Option<&mut [u8]>, &mut u32) -> u32 {
+        move |buffer, length| {
+            let result = match buffer {
+                None => SUCCESS,
+                Some(buffer) if buffer.len() < data.len() => MORE_DATA,
+                Some(buffer) => {
+                    buffer[..data.len()].copy_from_slice(&data);
+                    SUCCESS
+                }
+            };
+            *length = data.len() as u32;
+            data.extend(std::iter::repeat_n(0xab, growth));
+            result
+        }
+    }
+
+    #[test]
+    fn read_sized_reads_data_longer_than_a_fixed_buffer() {
+        let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        let (result, read) = read_sized(MORE_DATA, SUCCESS, growing_reader(data.clone(), 0));
+        assert_eq!(result, SUCCESS);
+        assert_eq!(read, data);
+    }
+
+    #[test]
+    fn read_sized_retries_when_the_data_grows() {
+        let (result, read) = read_sized(MORE_DATA, SUCCESS, growing_reader(vec![1; 10], 3));
+        assert_eq!(result, SUCCESS);
+        assert!(read.len() > 10);
+        assert_eq!(&read[..10], &[1; 10]);
+    }
+
+    #[test]
+    fn read_sized_reads_empty_data() {
+        let (result, read) = read_sized(MORE_DATA, SUCCESS, growing_reader(vec![], 0));
+        assert_eq!(result, SUCCESS);
+        assert!(read.is_empty());
+    }
+
+    #[test]
+    fn read_sized_returns_failures() {
+        let (result, read) = read_sized(MORE_DATA, SUCCESS, |_, length| {
+            *length = 0;
+            2
+        });
+        assert_eq!(result, 2);
+        assert!(read.is_empty());
+    }
+
+    #[test]
+    fn long_values_are_read_whole() {
+        let long_string = "x".repeat(40_000);
+        let values = [
+            RegistryValue::String(long_string.clone()),
+            RegistryValue::ExpandString(format!("%SystemRoot%\\{}", long_string)),
+            RegistryValue::MultiString(vec![long_string.clone(); 5]),
+            RegistryValue::Binary((0..70_000).map(|i| (i % 251) as u8).collect()),
+        ];
+
+        // read the way Win32Registry reads values, from an API reporting the length it needs
+        for value in values {
+            let (result, data) = read_sized(MORE_DATA, SUCCESS, growing_reader(value.to_raw(), 0));
+            assert_eq!(result, SUCCESS);
+            assert_eq!(RegistryValue::from_raw(value.kind(), &data), value);
+        }
+    }
+
+    #[test]
+    fn view_paths_redirect_the_32_bit_view() {
+        let paths = |path, view| view_paths(path, view).unwrap();
+
+        assert_eq!(
+            paths("HKLM\\Software\\Vendor", Some(RegistryView::Both)),
+            ["HKLM\\Software\\Vendor", "HKEY_LOCAL_MACHINE\\Software\\WOW6432Node\\Vendor"]
+        );
+        assert_eq!(
+            paths("HKLM\\SOFTWARE\\Classes\\CLSID", Some(RegistryView::Bits32)),
+            ["HKEY_LOCAL_MACHINE\\SOFTWARE\\Classes\\WOW6432Node\\CLSID"]
+        );
+        assert_eq!(
+            paths("HKCR\\CLSID", Some(RegistryView::Bits32)),
+            ["HKEY_CLASSES_ROOT\\WOW6432Node\\CLSID"]
+        );
+        assert_eq!(
+            paths("HKLM\\Software", Some(RegistryView::Bits32)),
+            ["HKEY_LOCAL_MACHINE\\Software\\WOW6432Node"]
+        );
+        assert_eq!(paths("HKLM\\Software\\Vendor", Some(RegistryView::Bits64)), ["HKLM\\Software\\Vendor"]);
+        assert_eq!(paths("HKLM\\Software\\Vendor", None), ["HKLM\\Software\\Vendor"]);
+
+        // shared keys, and keys that are already in the 32-bit view
+        assert_eq!(paths("HKCU\\Software\\Vendor", Some(RegistryView::Both)), ["HKCU\\Software\\Vendor"]);
+        assert_eq!(paths("HKLM\\SoftwareX", Some(RegistryView::Bits32)), ["HKLM\\SoftwareX"]);
+        assert_eq!(
+            paths("HKLM\\Software\\WOW6432Node\\Vendor", Some(RegistryView::Bits32)),
+            ["HKLM\\Software\\WOW6432Node\\Vendor"]
+        );
+    }
+}
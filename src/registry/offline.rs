@@ -0,0 +1,139 @@
+use super::regf::HiveFile;
+use super::{get_path_components, split_key_path, Hive, RegistryBackend, RegistryValue};
+use crate::error::DejunkerError;
+use log::{debug, info};
+use std::path::{Path, PathBuf};
+use std::result::Result;
+
+/// A hive file, and where it is mounted in the registry namespace
+struct Mount {
+    hive: Hive,
+    prefix: String,
+    file: PathBuf,
+    contents: HiveFile,
+}
+
+/// A registry made of offline hive files (e.g. the NTUSER.DAT of the Default profile, or the
+/// SOFTWARE hive of a Windows image), each mounted at a registry path. Paths outside of all
+/// mounted hives are not accessible.
+#[derive(Default)]
+pub struct OfflineRegistry {
+    mounts: Vec<Mount>,
+}
+
+impl OfflineRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mount a hive file at a registry path
+    ///
+    /// * mount_point: the registry path the root of the hive maps to, e.g. HKCU or HKLM\SOFTWARE
+    /// * file: the hive file
+    ///
+    pub fn mount(&mut self, mount_point: &str, file: &Path) -> Result<(), DejunkerError> {
+        let (hive, prefix) = get_path_components(mount_point)?;
+        let contents = HiveFile::open(file)?;
+
+        info!("Mounted {} at {}", file.display(), mount_point);
+        self.mounts.push(Mount {
+            hive,
+            prefix,
+            file: file.to_path_buf(),
+            contents,
+        });
+        Ok(())
+    }
+
+    /// Mount a hive from a MOUNT_POINT=FILE specification, e.g. HKCU=C:\Users\Default\NTUSER.DAT
+    pub fn mount_spec(&mut self, spec: &str) -> Result<(), DejunkerError> {
+        let (mount_point, file) = spec
+            .split_once('=')
+            .ok_or_else(|| DejunkerError::parse(spec, "invalid offline hive, expected MOUNT_POINT=FILE"))?;
+        self.mount(mount_point, Path::new(file))
+    }
+
+    /// Write all modified hives back to their files
+    pub fn save(&mut self) -> Result<(), DejunkerError> {
+        for mount in self.mounts.iter_mut().filter(|mount| mount.contents.is_modified()) {
+            info!("Saving {}", mount.file.display());
+            mount.contents.save(&mount.file)?;
+        }
+        Ok(())
+    }
+
+    /// Find the hive containing a path, and the path relative to the root of that hive.
+    /// When mount points are nested, the most specific one wins.
+    fn resolve(&self, path: &str) -> Result<(usize, String), DejunkerError> {
+        let (hive, sub_path) = get_path_components(path)?;
+        let parts: Vec<&str> = split_key_path(&sub_path).collect();
+
+        let mut best: Option<(usize, String)> = None;
+        for (index, mount) in self.mounts.iter().enumerate() {
+            let prefix: Vec<&str> = split_key_path(&mount.prefix).collect();
+            let matches = mount.hive == hive
+                && prefix.len() <= parts.len()
+                && prefix
+                    .iter()
+                    .zip(parts.iter())
+                    .all(|(a, b)| a.to_lowercase() == b.to_lowercase());
+            if !matches {
+                continue;
+            }
+            let relative = parts[prefix.len()..].join("\\");
+
+            let better = match &best {
+                Some((current, _)) => self.mounts[*current].prefix.len() < mount.prefix.len(),
+                None => true,
+            };
+            if better {
+                best = Some((index, relative));
+            }
+        }
+
+        let resolved = best.ok_or_else(|| {
+            DejunkerError::AccessDenied(format!("{} is not inside any mounted offline hive", path))
+        })?;
+        debug!("{} resolves to {} in {}", path, resolved.1, self.mounts[resolved.0].file.display());
+        Ok(resolved)
+    }
+}
+
+impl RegistryBackend for OfflineRegistry {
+    fn get_value(&self, path: &str, value_name: &str) -> Result<Option<RegistryValue>, DejunkerError> {
+        let (mount, sub_path) = self.resolve(path)?;
+        self.mounts[mount].contents.get_value(&sub_path, value_name)
+    }
+
+    fn set_value(&mut self, path: &str, value_name: &str, value: &RegistryValue) -> Result<(), DejunkerError> {
+        debug!("Setting offline registry value: {} -> {}: {}", path, value_name, value);
+        let (mount, sub_path) = self.resolve(path)?;
+        self.mounts[mount].contents.set_value(&sub_path, value_name, value)
+    }
+
+    fn delete_value(&mut self, path: &str, value_name: &str) -> Result<(), DejunkerError> {
+        debug!("Deleting offline registry value: {} -> {}", path, value_name);
+        let (mount, sub_path) = self.resolve(path)?;
+        self.mounts[mount].contents.delete_value(&sub_path, value_name)
+    }
+
+    fn delete_key(&mut self, path: &str) -> Result<(), DejunkerError> {
+        debug!("Deleting offline registry key: {}", path);
+        let (mount, sub_path) = self.resolve(path)?;
+        self.mounts[mount].contents.delete_key(&sub_path)
+    }
+
+    fn enum_keys(&self, path: &str) -> Result<Vec<String>, DejunkerError> {
+        let (mount, sub_path) = self.resolve(path)?;
+        self.mounts[mount].contents.enum_keys(&sub_path)
+    }
+
+    fn enum_values(&self, path: &str) -> Result<Vec<String>, DejunkerError> {
+        let (mount, sub_path) = self.resolve(path)?;
+        self.mounts[mount].contents.enum_values(&sub_path)
+    }
+
+    fn is_accessible(&self, path: &str) -> bool {
+        self.resolve(path).is_ok()
+    }
+}
@@ -0,0 +1,192 @@
+use super::{key_values, KeyValue, RegistryBackend, RegistryValue};
+use crate::error::DejunkerError;
+use std::result::Result;
+
+/// A modification made to a registry
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Change {
+    SetValue {
+        path: String,
+        name: String,
+        previous: Option<RegistryValue>,
+        value: RegistryValue,
+    },
+    DeleteValue {
+        path: String,
+        name: String,
+        previous: Option<RegistryValue>,
+    },
+    DeleteKey {
+        path: String,
+        /// The values of the key and its subkeys before it was deleted, empty if unknown
+        previous: Vec<KeyValue>,
+    },
+    /// A key that did not exist, created (with any missing subkey) by writing a value below it
+    CreateKey { path: String },
+}
+
+impl Change {
+    /// Path of the key the change applies to
+    pub fn path(&self) -> &str {
+        match self {
+            Change::SetValue { path, .. } => path,
+            Change::DeleteValue { path, .. } => path,
+            Change::DeleteKey { path, .. } => path,
+            Change::CreateKey { path } => path,
+        }
+    }
+
+    /// The same change, with its paths mapped to other paths (e.g. by users::user_path)
+    pub fn mapped(&self, map: impl Fn(&str) -> Result<String, DejunkerError>) -> Result<Change, DejunkerError> {
+        let mut change = self.clone();
+        match &mut change {
+            Change::SetValue { path, .. } | Change::DeleteValue { path, .. } | Change::CreateKey { path } => {
+                *path = map(path)?
+            }
+            Change::DeleteKey { path, previous } => {
+                *path = map(path)?;
+                for value in previous {
+                    value.path = map(&value.path)?;
+                }
+            }
+        }
+        Ok(change)
+    }
+}
+
+/// Wraps another registry, and keeps a record of every modification made through it, along
+/// with the value that was there before. Reads go to the wrapped registry. When `apply` is
+/// false, modifications are only recorded and the wrapped registry is left alone.
+pub struct RecordingRegistry<'a> {
+    inner: &'a mut dyn RegistryBackend,
+    apply: bool,
+    changes: Vec<Change>,
+}
+
+impl<'a> RecordingRegistry<'a> {
+    pub fn new(inner: &'a mut dyn RegistryBackend, apply: bool) -> Self {
+        RecordingRegistry {
+            inner,
+            apply,
+            changes: Vec::new(),
+        }
+    }
+
+    /// All modifications, in the order they were made
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    pub fn into_changes(self) -> Vec<Change> {
+        self.changes
+    }
+
+    /// The key writing a value to a key creates: the key itself or its first missing parent,
+    /// None if the key exists (or was already recorded as created)
+    fn created_key(&self, path: &str) -> Result<Option<String>, DejunkerError> {
+        let path = path.trim_end_matches('\\');
+        // when not applying, keys created earlier still do not exist in the wrapped registry
+        let recorded = self.changes.iter().any(|change| match change {
+            Change::CreateKey { path: created } => is_same_or_subkey(path, created),
+            _ => false,
+        });
+        if recorded {
+            return Ok(None);
+        }
+        if self.inner.key_exists(path)? {
+            return Ok(None);
+        }
+
+        // the hive itself always exists
+        let mut key = String::new();
+        for (index, component) in path.split('\\').enumerate() {
+            if index > 0 {
+                key.push('\\');
+            }
+            key.push_str(component);
+            if index > 0 && !self.inner.key_exists(&key)? {
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Whether a key is another key, or below it (case insensitively)
+fn is_same_or_subkey(path: &str, key: &str) -> bool {
+    let (path, key) = (path.to_lowercase(), key.to_lowercase());
+    path == key || path.starts_with(&format!("{}\\", key))
+}
+
+impl RegistryBackend for RecordingRegistry<'_> {
+    fn get_value(&self, path: &str, value_name: &str) -> Result<Option<RegistryValue>, DejunkerError> {
+        self.inner.get_value(path, value_name)
+    }
+
+    fn set_value(&mut self, path: &str, value_name: &str, value: &RegistryValue) -> Result<(), DejunkerError> {
+        let previous = self.inner.get_value(path, value_name)?;
+        let created = self.created_key(path)?;
+        if self.apply {
+            self.inner.set_value(path, value_name, value)?;
+        }
+        if let Some(created) = created {
+            self.changes.push(Change::CreateKey { path: created });
+        }
+        self.changes.push(Change::SetValue {
+            path: path.to_string(),
+            name: value_name.to_string(),
+            previous,
+            value: value.clone(),
+        });
+        Ok(())
+    }
+
+    fn delete_value(&mut self, path: &str, value_name: &str) -> Result<(), DejunkerError> {
+        let previous = self.inner.get_value(path, value_name)?;
+        if self.apply {
+            self.inner.delete_value(path, value_name)?;
+        }
+        self.changes.push(Change::DeleteValue {
+            path: path.to_string(),
+            name: value_name.to_string(),
+            previous,
+        });
+        Ok(())
+    }
+
+    fn delete_key(&mut self, path: &str) -> Result<(), DejunkerError> {
+        let previous = key_values(&*self.inner, path)?;
+        if self.apply {
+            self.inner.delete_key(path)?;
+        }
+        self.changes.push(Change::DeleteKey {
+            path: path.to_string(),
+            previous,
+        });
+        Ok(())
+    }
+
+    fn back_up_key(&mut self, path: &str) -> Result<(), DejunkerError> {
+        match self.apply {
+            true => self.inner.back_up_key(path),
+            false => Ok(()),
+        }
+    }
+
+    fn enum_keys(&self, path: &str) -> Result<Vec<String>, DejunkerError> {
+        self.inner.enum_keys(path)
+    }
+
+    fn enum_values(&self, path: &str) -> Result<Vec<String>, DejunkerError> {
+        self.inner.enum_values(path)
+    }
+
+    fn is_accessible(&self, path: &str) -> bool {
+        self.inner.is_accessible(path)
+    }
+
+    // also when only recording, so plans skip the same rules applying would
+    fn requires_elevation(&self) -> bool {
+        self.inner.requires_elevation()
+    }
+}
@@ -0,0 +1,1027 @@
+//! Reading and in-place editing of registry hive files (the "regf" format used for
+//! NTUSER.DAT, SOFTWARE, SYSTEM and friends).
+//!
+//! The whole file is kept in memory. Modifications allocate new cells (reusing free
+//! cells where possible, appending hive bins otherwise) and release the cells they
+//! replace, so everything we don't touch is written back byte for byte.
+
+use super::{split_key_path, RegistryValue};
+use crate::error::DejunkerError;
+use log::debug;
+use std::fs;
+use std::path::Path;
+use std::result::Result;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const BASE_BLOCK_SIZE: usize = 4096;
+const HBIN_HEADER_SIZE: usize = 32;
+const HBIN_ALIGNMENT: usize = 4096;
+const BIG_DATA_SEGMENT_SIZE: usize = 16344;
+const MAX_LEAF_LIST_SIZE: usize = 1012;
+const RI_CHUNK_SIZE: usize = 512;
+const NO_CELL: u32 = 0xFFFF_FFFF;
+
+const KEY_COMP_NAME: u16 = 0x0020;
+const VALUE_COMP_NAME: u16 = 0x0001;
+const DATA_INLINE: u32 = 0x8000_0000;
+
+// base block fields
+const BB_PRIMARY_SEQUENCE: usize = 4;
+const BB_SECONDARY_SEQUENCE: usize = 8;
+const BB_LAST_WRITTEN: usize = 12;
+const BB_MAJOR_VERSION: usize = 20;
+const BB_MINOR_VERSION: usize = 24;
+const BB_FILE_TYPE: usize = 28;
+const BB_ROOT_CELL: usize = 36;
+const BB_HBINS_SIZE: usize = 40;
+const BB_CHECKSUM: usize = 508;
+
+// key node (nk) fields, relative to the start of the cell data
+const NK_FLAGS: usize = 2;
+const NK_LAST_WRITTEN: usize = 4;
+const NK_PARENT: usize = 16;
+const NK_SUBKEY_COUNT: usize = 20;
+const NK_SUBKEY_LIST: usize = 28;
+const NK_VOLATILE_SUBKEY_LIST: usize = 32;
+const NK_VALUE_COUNT: usize = 36;
+const NK_VALUE_LIST: usize = 40;
+const NK_SECURITY: usize = 44;
+const NK_CLASS: usize = 48;
+const NK_MAX_SUBKEY_NAME: usize = 52;
+const NK_MAX_VALUE_NAME: usize = 60;
+const NK_MAX_VALUE_DATA: usize = 64;
+const NK_NAME_LENGTH: usize = 72;
+const NK_NAME: usize = 76;
+
+// key value (vk) fields
+const VK_NAME_LENGTH: usize = 2;
+const VK_DATA_SIZE: usize = 4;
+const VK_DATA: usize = 8;
+const VK_TYPE: usize = 12;
+const VK_FLAGS: usize = 16;
+const VK_NAME: usize = 20;
+
+// security (sk) fields
+const SK_FLINK: usize = 4;
+const SK_BLINK: usize = 8;
+const SK_REFERENCES: usize = 12;
+
+/// A registry hive file loaded in memory
+pub struct HiveFile {
+    data: Vec<u8>,
+    minor_version: u32,
+    root: u32,
+    free_cells: Vec<(u32, usize)>,
+    modified: bool,
+}
+
+impl HiveFile {
+    /// Load a hive file. Hives with pending transaction log entries are rejected, as we
+    /// would otherwise be editing a stale copy of the data.
+    pub fn open(path: &Path) -> Result<HiveFile, DejunkerError> {
+        debug!("Loading hive file {}", path.display());
+        let data = fs::read(path).map_err(DejunkerError::io(path))?;
+        HiveFile::from_bytes(data).map_err(|e| DejunkerError::parse(&path.display().to_string(), e.to_string()))
+    }
+
+    /// Parse a hive from its file contents
+    pub fn from_bytes(mut data: Vec<u8>) -> Result<HiveFile, DejunkerError> {
+        if data.len() < BASE_BLOCK_SIZE || &data[0..4] != b"regf" {
+            return Err(DejunkerError::registry("not a registry hive file"));
+        }
+        if read_u32(&data, BB_CHECKSUM)? != checksum(&data) {
+            return Err(DejunkerError::registry("base block checksum mismatch"));
+        }
+        if read_u32(&data, BB_PRIMARY_SEQUENCE)? != read_u32(&data, BB_SECONDARY_SEQUENCE)? {
+            return Err(DejunkerError::registry("hive has pending transaction log entries, load and unload it once to replay them"));
+        }
+        let major = read_u32(&data, BB_MAJOR_VERSION)?;
+        let minor_version = read_u32(&data, BB_MINOR_VERSION)?;
+        if major != 1 || minor_version < 3 {
+            return Err(DejunkerError::registry(format!("unsupported hive format version {}.{}", major, minor_version)));
+        }
+        if read_u32(&data, BB_FILE_TYPE)? != 0 {
+            return Err(DejunkerError::registry("not a primary hive file"));
+        }
+
+        // anything past the hive bins is not part of the hive
+        let hbins_size = read_u32(&data, BB_HBINS_SIZE)? as usize;
+        if BASE_BLOCK_SIZE + hbins_size > data.len() {
+            return Err(DejunkerError::registry("hive file is truncated"));
+        }
+        data.truncate(BASE_BLOCK_SIZE + hbins_size);
+
+        let mut hive = HiveFile {
+            root: read_u32(&data, BB_ROOT_CELL)?,
+            data,
+            minor_version,
+            free_cells: Vec::new(),
+            modified: false,
+        };
+        hive.scan_free_cells()?;
+
+        let root = hive.cell(hive.root)?;
+        if root.get(0..2) != Some(b"nk") {
+            return Err(DejunkerError::registry("root cell is not a key"));
+        }
+
+        Ok(hive)
+    }
+
+    /// Whether the hive was changed since it was loaded
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    /// Write the hive back to disk. The file is replaced atomically.
+    pub fn save(&mut self, path: &Path) -> Result<(), DejunkerError> {
+        let sequence = read_u32(&self.data, BB_PRIMARY_SEQUENCE)?.wrapping_add(1);
+        let hbins_size = (self.data.len() - BASE_BLOCK_SIZE) as u32;
+        write_u32(&mut self.data, BB_PRIMARY_SEQUENCE, sequence);
+        write_u32(&mut self.data, BB_SECONDARY_SEQUENCE, sequence);
+        write_u64(&mut self.data, BB_LAST_WRITTEN, now_filetime());
+        write_u32(&mut self.data, BB_HBINS_SIZE, hbins_size);
+        let sum = checksum(&self.data);
+        write_u32(&mut self.data, BB_CHECKSUM, sum);
+
+        debug!("Writing hive file {}", path.display());
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        fs::write(&temp_path, &self.data).map_err(DejunkerError::io(&temp_path))?;
+        fs::rename(&temp_path, path).map_err(DejunkerError::io(path))?;
+
+        self.modified = false;
+        Ok(())
+    }
+
+    /// Read a value. Returns None if the key or the value does not exist.
+    ///
+    /// * sub_path: path of the key, relative to the root of the hive
+    /// * value_name: the registry value name
+    pub fn get_value(&self, sub_path: &str, value_name: &str) -> Result<Option<RegistryValue>, DejunkerError> {
+        let Some(key) = self.find_key(sub_path)? else {
+            return Ok(None);
+        };
+        let Some((_, value)) = self.find_value(key, value_name)? else {
+            return Ok(None);
+        };
+
+        let (kind, data) = self.value_data(value)?;
+        Ok(Some(RegistryValue::from_raw(kind, &data)))
+    }
+
+    /// Write a value, creating the key (and any missing parent) if it doesn't exist
+    pub fn set_value(&mut self, sub_path: &str, value_name: &str, value: &RegistryValue) -> Result<(), DejunkerError> {
+        let key = self.create_key(sub_path)?;
+        let data = value.to_raw();
+        let (data_size, data_cell) = self.write_value_data(&data)?;
+
+        match self.find_value(key, value_name)? {
+            Some((_, vk)) => {
+                self.free_value_data(vk)?;
+                self.put_u32(vk, VK_DATA_SIZE, data_size)?;
+                self.put_u32(vk, VK_DATA, data_cell)?;
+                self.put_u32(vk, VK_TYPE, value.kind())?;
+            }
+            None => {
+                let (name, compressed) = encode_name(value_name);
+                let vk = self.alloc(VK_NAME + name.len())?;
+                let cell = self.cell_mut(vk)?;
+                cell[0..2].copy_from_slice(b"vk");
+                cell[VK_NAME_LENGTH..VK_NAME_LENGTH + 2].copy_from_slice(&(name.len() as u16).to_le_bytes());
+                cell[VK_DATA_SIZE..VK_DATA_SIZE + 4].copy_from_slice(&data_size.to_le_bytes());
+                cell[VK_DATA..VK_DATA + 4].copy_from_slice(&data_cell.to_le_bytes());
+                cell[VK_TYPE..VK_TYPE + 4].copy_from_slice(&value.kind().to_le_bytes());
+                let flags = if compressed { VALUE_COMP_NAME } else { 0 };
+                cell[VK_FLAGS..VK_FLAGS + 2].copy_from_slice(&flags.to_le_bytes());
+                cell[VK_NAME..VK_NAME + name.len()].copy_from_slice(&name);
+
+                let mut values = self.value_offsets(key)?;
+                values.push(vk);
+                self.write_value_list(key, &values)?;
+            }
+        }
+
+        let name_size = (value_name.encode_utf16().count() * 2) as u32;
+        let nk = self.cell(key)?;
+        let max_name = read_u32(nk, NK_MAX_VALUE_NAME)?.max(name_size);
+        let max_data = read_u32(nk, NK_MAX_VALUE_DATA)?.max(data.len() as u32);
+        self.put_u32(key, NK_MAX_VALUE_NAME, max_name)?;
+        self.put_u32(key, NK_MAX_VALUE_DATA, max_data)?;
+        self.touch(key)
+    }
+
+    /// Delete a value. Deleting a value that does not exist is not an error.
+    pub fn delete_value(&mut self, sub_path: &str, value_name: &str) -> Result<(), DejunkerError> {
+        let Some(key) = self.find_key(sub_path)? else {
+            return Ok(());
+        };
+        let Some((index, vk)) = self.find_value(key, value_name)? else {
+            return Ok(());
+        };
+
+        self.free_value_data(vk)?;
+        self.free(vk)?;
+
+        let mut values = self.value_offsets(key)?;
+        values.remove(index);
+        self.write_value_list(key, &values)?;
+        self.touch(key)
+    }
+
+    /// Delete a key with all its subkeys and values. Deleting a key that does not exist
+    /// is not an error.
+    pub fn delete_key(&mut self, sub_path: &str) -> Result<(), DejunkerError> {
+        let sub_path = sub_path.trim_matches('\\');
+        let (parent_path, name) = match sub_path.rsplit_once('\\') {
+            Some((parent, name)) => (parent, name),
+            None => ("", sub_path),
+        };
+        if name.is_empty() {
+            return Err(DejunkerError::registry("Refusing to delete the root key of a hive"));
+        }
+
+        let Some(parent) = self.find_key(parent_path)? else {
+            return Ok(());
+        };
+        let Some(key) = self.find_subkey(parent, name)? else {
+            return Ok(());
+        };
+
+        self.free_key(key)?;
+        let subkeys: Vec<u32> = self
+            .subkey_offsets(parent)?
+            .into_iter()
+            .filter(|&offset| offset != key)
+            .collect();
+        self.write_subkey_list(parent, &subkeys)?;
+        self.touch(parent)
+    }
+
+    /// Names of the subkeys of a key (empty if the key does not exist)
+    pub fn enum_keys(&self, sub_path: &str) -> Result<Vec<String>, DejunkerError> {
+        match self.find_key(sub_path)? {
+            Some(key) => self
+                .subkey_offsets(key)?
+                .into_iter()
+                .map(|subkey| self.key_name(subkey))
+                .collect(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Names of the values of a key (empty if the key does not exist)
+    pub fn enum_values(&self, sub_path: &str) -> Result<Vec<String>, DejunkerError> {
+        match self.find_key(sub_path)? {
+            Some(key) => self
+                .value_offsets(key)?
+                .into_iter()
+                .map(|value| self.value_name(value))
+                .collect(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // --- navigation ---
+
+    fn find_key(&self, sub_path: &str) -> Result<Option<u32>, DejunkerError> {
+        let mut key = self.root;
+        for part in split_key_path(sub_path) {
+            match self.find_subkey(key, part)? {
+                Some(subkey) => key = subkey,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(key))
+    }
+
+    fn create_key(&mut self, sub_path: &str) -> Result<u32, DejunkerError> {
+        let mut key = self.root;
+        for part in split_key_path(sub_path) {
+            key = match self.find_subkey(key, part)? {
+                Some(subkey) => subkey,
+                None => self.create_subkey(key, part)?,
+            };
+        }
+        Ok(key)
+    }
+
+    fn find_subkey(&self, key: u32, name: &str) -> Result<Option<u32>, DejunkerError> {
+        let wanted = upcase(name);
+        for subkey in self.subkey_offsets(key)? {
+            if upcase(&self.key_name(subkey)?) == wanted {
+                return Ok(Some(subkey));
+            }
+        }
+        Ok(None)
+    }
+
+    fn find_value(&self, key: u32, name: &str) -> Result<Option<(usize, u32)>, DejunkerError> {
+        let wanted = upcase(name);
+        for (index, value) in self.value_offsets(key)?.into_iter().enumerate() {
+            if upcase(&self.value_name(value)?) == wanted {
+                return Ok(Some((index, value)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn key_name(&self, key: u32) -> Result<String, DejunkerError> {
+        let nk = self.cell(key)?;
+        if nk.get(0..2) != Some(b"nk") {
+            return Err(DejunkerError::registry(format!("cell {:#x} is not a key", key)));
+        }
+        let length = read_u16(nk, NK_NAME_LENGTH)? as usize;
+        let name = nk.get(NK_NAME..NK_NAME + length).ok_or_else(|| DejunkerError::registry("key name out of bounds"))?;
+        Ok(decode_name(name, read_u16(nk, NK_FLAGS)? & KEY_COMP_NAME != 0))
+    }
+
+    fn value_name(&self, value: u32) -> Result<String, DejunkerError> {
+        let vk = self.cell(value)?;
+        if vk.get(0..2) != Some(b"vk") {
+            return Err(DejunkerError::registry(format!("cell {:#x} is not a value", value)));
+        }
+        let length = read_u16(vk, VK_NAME_LENGTH)? as usize;
+        let name = vk.get(VK_NAME..VK_NAME + length).ok_or_else(|| DejunkerError::registry("value name out of bounds"))?;
+        Ok(decode_name(name, read_u16(vk, VK_FLAGS)? & VALUE_COMP_NAME != 0))
+    }
+
+    fn subkey_offsets(&self, key: u32) -> Result<Vec<u32>, DejunkerError> {
+        let nk = self.cell(key)?;
+        let mut offsets = Vec::new();
+        if read_u32(nk, NK_SUBKEY_COUNT)? > 0 {
+            self.read_subkey_list(read_u32(nk, NK_SUBKEY_LIST)?, &mut offsets, true)?;
+        }
+        Ok(offsets)
+    }
+
+    fn read_subkey_list(&self, list: u32, offsets: &mut Vec<u32>, allow_index: bool) -> Result<(), DejunkerError> {
+        let cell = self.cell(list)?;
+        let count = read_u16(cell, 2)? as usize;
+        match cell.get(0..2) {
+            Some(b"li") => {
+                for i in 0..count {
+                    offsets.push(read_u32(cell, 4 + i * 4)?);
+                }
+            }
+            Some(b"lf") | Some(b"lh") => {
+                for i in 0..count {
+                    offsets.push(read_u32(cell, 4 + i * 8)?);
+                }
+            }
+            Some(b"ri") if allow_index => {
+                for i in 0..count {
+                    self.read_subkey_list(read_u32(cell, 4 + i * 4)?, offsets, false)?;
+                }
+            }
+            _ => return Err(DejunkerError::registry(format!("cell {:#x} is not a subkey list", list))),
+        }
+        Ok(())
+    }
+
+    fn value_offsets(&self, key: u32) -> Result<Vec<u32>, DejunkerError> {
+        let nk = self.cell(key)?;
+        let count = read_u32(nk, NK_VALUE_COUNT)? as usize;
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let list = self.cell(read_u32(nk, NK_VALUE_LIST)?)?;
+        (0..count).map(|i| read_u32(list, i * 4)).collect()
+    }
+
+    fn value_data(&self, value: u32) -> Result<(u32, Vec<u8>), DejunkerError> {
+        let vk = self.cell(value)?;
+        let size = read_u32(vk, VK_DATA_SIZE)?;
+        let kind = read_u32(vk, VK_TYPE)?;
+        let offset = read_u32(vk, VK_DATA)?;
+
+        // small values live in the offset field itself
+        if size & DATA_INLINE != 0 {
+            let size = ((size & !DATA_INLINE) as usize).min(4);
+            return Ok((kind, offset.to_le_bytes()[..size].to_vec()));
+        }
+
+        let size = size as usize;
+        if size == 0 {
+            return Ok((kind, Vec::new()));
+        }
+        let cell = self.cell(offset)?;
+        if size > BIG_DATA_SEGMENT_SIZE && self.minor_version >= 4 && cell.get(0..2) == Some(b"db") {
+            let count = read_u16(cell, 2)? as usize;
+            let segments = self.cell(read_u32(cell, 4)?)?;
+            let mut data = Vec::with_capacity(size);
+            for i in 0..count {
+                let segment = self.cell(read_u32(segments, i * 4)?)?;
+                let length = (size - data.len()).min(BIG_DATA_SEGMENT_SIZE).min(segment.len());
+                data.extend_from_slice(&segment[..length]);
+            }
+            if data.len() != size {
+                return Err(DejunkerError::registry(format!("big data value {:#x} is truncated", value)));
+            }
+            return Ok((kind, data));
+        }
+
+        let data = cell.get(..size).ok_or_else(|| DejunkerError::registry("value data out of bounds"))?;
+        Ok((kind, data.to_vec()))
+    }
+
+    // --- modification ---
+
+    fn create_subkey(&mut self, parent: u32, name: &str) -> Result<u32, DejunkerError> {
+        if name.encode_utf16().count() > 255 {
+            return Err(DejunkerError::registry(format!("Key name '{}' is too long", name)));
+        }
+        debug!("Creating hive key {}", name);
+
+        let security = read_u32(self.cell(parent)?, NK_SECURITY)?;
+        let (encoded, compressed) = encode_name(name);
+        let key = self.alloc(NK_NAME + encoded.len())?;
+
+        let cell = self.cell_mut(key)?;
+        cell[0..2].copy_from_slice(b"nk");
+        let flags = if compressed { KEY_COMP_NAME } else { 0 };
+        cell[NK_FLAGS..NK_FLAGS + 2].copy_from_slice(&flags.to_le_bytes());
+        cell[NK_LAST_WRITTEN..NK_LAST_WRITTEN + 8].copy_from_slice(&now_filetime().to_le_bytes());
+        cell[NK_PARENT..NK_PARENT + 4].copy_from_slice(&parent.to_le_bytes());
+        for field in [NK_SUBKEY_LIST, NK_VOLATILE_SUBKEY_LIST, NK_VALUE_LIST, NK_CLASS] {
+            cell[field..field + 4].copy_from_slice(&NO_CELL.to_le_bytes());
+        }
+        cell[NK_SECURITY..NK_SECURITY + 4].copy_from_slice(&security.to_le_bytes());
+        cell[NK_NAME_LENGTH..NK_NAME_LENGTH + 2].copy_from_slice(&(encoded.len() as u16).to_le_bytes());
+        cell[NK_NAME..NK_NAME + encoded.len()].copy_from_slice(&encoded);
+
+        // the new key shares the security descriptor of its parent
+        let references = read_u32(self.cell(security)?, SK_REFERENCES)?;
+        self.put_u32(security, SK_REFERENCES, references + 1)?;
+
+        let mut subkeys = self.subkey_offsets(parent)?;
+        subkeys.push(key);
+        self.write_subkey_list(parent, &subkeys)?;
+
+        // the name length field also carries flags in its upper bits
+        let name_size = (name.encode_utf16().count() * 2) as u32;
+        let max_name = read_u32(self.cell(parent)?, NK_MAX_SUBKEY_NAME)?;
+        if max_name & 0xFFFF < name_size {
+            self.put_u32(parent, NK_MAX_SUBKEY_NAME, (max_name & !0xFFFF) | name_size)?;
+        }
+        self.touch(parent)?;
+        Ok(key)
+    }
+
+    /// Release all cells of a key and its descendants
+    fn free_key(&mut self, key: u32) -> Result<(), DejunkerError> {
+        for subkey in self.subkey_offsets(key)? {
+            self.free_key(subkey)?;
+        }
+        for value in self.value_offsets(key)? {
+            self.free_value_data(value)?;
+            self.free(value)?;
+        }
+
+        let nk = self.cell(key)?;
+        let subkey_list = read_u32(nk, NK_SUBKEY_LIST)?;
+        let value_list = read_u32(nk, NK_VALUE_LIST)?;
+        let class = read_u32(nk, NK_CLASS)?;
+        let security = read_u32(nk, NK_SECURITY)?;
+        let has_subkeys = read_u32(nk, NK_SUBKEY_COUNT)? > 0;
+        let has_values = read_u32(nk, NK_VALUE_COUNT)? > 0;
+        if has_subkeys {
+            self.free_subkey_list(subkey_list)?;
+        }
+        if has_values {
+            self.free(value_list)?;
+        }
+        if class != NO_CELL {
+            self.free(class)?;
+        }
+        self.release_security(security)?;
+        self.free(key)
+    }
+
+    /// Drop a reference to a security descriptor, removing it once it is no longer used
+    fn release_security(&mut self, security: u32) -> Result<(), DejunkerError> {
+        let sk = self.cell(security)?;
+        let references = read_u32(sk, SK_REFERENCES)?;
+        if references > 1 {
+            return self.put_u32(security, SK_REFERENCES, references - 1);
+        }
+
+        let next = read_u32(sk, SK_FLINK)?;
+        let previous = read_u32(sk, SK_BLINK)?;
+        if next == security {
+            // never remove the last descriptor of the hive
+            return self.put_u32(security, SK_REFERENCES, 0);
+        }
+        self.put_u32(previous, SK_FLINK, next)?;
+        self.put_u32(next, SK_BLINK, previous)?;
+        self.free(security)
+    }
+
+    fn free_subkey_list(&mut self, list: u32) -> Result<(), DejunkerError> {
+        let cell = self.cell(list)?;
+        if cell.get(0..2) == Some(b"ri") {
+            let sublists: Vec<u32> = (0..read_u16(cell, 2)? as usize)
+                .map(|i| read_u32(cell, 4 + i * 4))
+                .collect::<Result<_, _>>()?;
+            for sublist in sublists {
+                self.free(sublist)?;
+            }
+        }
+        self.free(list)
+    }
+
+    /// Replace the subkey list of a key. Entries are sorted the way Windows expects.
+    fn write_subkey_list(&mut self, key: u32, subkeys: &[u32]) -> Result<(), DejunkerError> {
+        let nk = self.cell(key)?;
+        if read_u32(nk, NK_SUBKEY_COUNT)? > 0 {
+            let old_list = read_u32(nk, NK_SUBKEY_LIST)?;
+            self.free_subkey_list(old_list)?;
+        }
+
+        let mut entries = subkeys
+            .iter()
+            .map(|&subkey| Ok((upcase(&self.key_name(subkey)?), subkey)))
+            .collect::<Result<Vec<_>, DejunkerError>>()?;
+        entries.sort();
+
+        let list = if entries.is_empty() {
+            NO_CELL
+        } else if entries.len() <= MAX_LEAF_LIST_SIZE {
+            self.write_leaf_list(&entries)?
+        } else {
+            let leaves = entries
+                .chunks(RI_CHUNK_SIZE)
+                .map(|chunk| self.write_leaf_list(chunk))
+                .collect::<Result<Vec<_>, _>>()?;
+            let index = self.alloc(4 + leaves.len() * 4)?;
+            let cell = self.cell_mut(index)?;
+            cell[0..2].copy_from_slice(b"ri");
+            cell[2..4].copy_from_slice(&(leaves.len() as u16).to_le_bytes());
+            for (i, leaf) in leaves.iter().enumerate() {
+                cell[4 + i * 4..8 + i * 4].copy_from_slice(&leaf.to_le_bytes());
+            }
+            index
+        };
+
+        self.put_u32(key, NK_SUBKEY_COUNT, entries.len() as u32)?;
+        self.put_u32(key, NK_SUBKEY_LIST, list)
+    }
+
+    fn write_leaf_list(&mut self, entries: &[(Vec<u16>, u32)]) -> Result<u32, DejunkerError> {
+        // hash leaves (lh) appeared in format 1.5, older hives use fast leaves (lf)
+        let hashed = self.minor_version >= 5;
+        let list = self.alloc(4 + entries.len() * 8)?;
+        let cell = self.cell_mut(list)?;
+        cell[0..2].copy_from_slice(if hashed { b"lh" } else { b"lf" });
+        cell[2..4].copy_from_slice(&(entries.len() as u16).to_le_bytes());
+        for (i, (name, offset)) in entries.iter().enumerate() {
+            let hint = if hashed {
+                name.iter().fold(0u32, |hash, &c| hash.wrapping_mul(37).wrapping_add(c as u32))
+            } else {
+                let mut hint = [0u8; 4];
+                for (slot, &c) in hint.iter_mut().zip(name.iter()) {
+                    *slot = c as u8;
+                }
+                u32::from_le_bytes(hint)
+            };
+            cell[4 + i * 8..8 + i * 8].copy_from_slice(&offset.to_le_bytes());
+            cell[8 + i * 8..12 + i * 8].copy_from_slice(&hint.to_le_bytes());
+        }
+        Ok(list)
+    }
+
+    fn write_value_list(&mut self, key: u32, values: &[u32]) -> Result<(), DejunkerError> {
+        let nk = self.cell(key)?;
+        if read_u32(nk, NK_VALUE_COUNT)? > 0 {
+            let old_list = read_u32(nk, NK_VALUE_LIST)?;
+            self.free(old_list)?;
+        }
+
+        let list = if values.is_empty() {
+            NO_CELL
+        } else {
+            let list = self.alloc(values.len() * 4)?;
+            let cell = self.cell_mut(list)?;
+            for (i, value) in values.iter().enumerate() {
+                cell[i * 4..i * 4 + 4].copy_from_slice(&value.to_le_bytes());
+            }
+            list
+        };
+
+        self.put_u32(key, NK_VALUE_COUNT, values.len() as u32)?;
+        self.put_u32(key, NK_VALUE_LIST, list)
+    }
+
+    /// Store value data, returning the (size, offset) pair for the value cell
+    fn write_value_data(&mut self, data: &[u8]) -> Result<(u32, u32), DejunkerError> {
+        if data.len() <= 4 {
+            let mut inline = [0u8; 4];
+            inline[..data.len()].copy_from_slice(data);
+            return Ok((data.len() as u32 | DATA_INLINE, u32::from_le_bytes(inline)));
+        }
+
+        if data.len() > BIG_DATA_SEGMENT_SIZE && self.minor_version >= 4 {
+            let segments = data
+                .chunks(BIG_DATA_SEGMENT_SIZE)
+                .map(|chunk| {
+                    let segment = self.alloc(chunk.len())?;
+                    self.cell_mut(segment)?[..chunk.len()].copy_from_slice(chunk);
+                    Ok(segment)
+                })
+                .collect::<Result<Vec<u32>, DejunkerError>>()?;
+
+            let list = self.alloc(segments.len() * 4)?;
+            let cell = self.cell_mut(list)?;
+            for (i, segment) in segments.iter().enumerate() {
+                cell[i * 4..i * 4 + 4].copy_from_slice(&segment.to_le_bytes());
+            }
+
+            let db = self.alloc(8)?;
+            let cell = self.cell_mut(db)?;
+            cell[0..2].copy_from_slice(b"db");
+            cell[2..4].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+            cell[4..8].copy_from_slice(&list.to_le_bytes());
+            return Ok((data.len() as u32, db));
+        }
+
+        let cell = self.alloc(data.len())?;
+        self.cell_mut(cell)?[..data.len()].copy_from_slice(data);
+        Ok((data.len() as u32, cell))
+    }
+
+    /// Release the data cells of a value (but not the value cell itself)
+    fn free_value_data(&mut self, value: u32) -> Result<(), DejunkerError> {
+        let vk = self.cell(value)?;
+        let size = read_u32(vk, VK_DATA_SIZE)?;
+        let offset = read_u32(vk, VK_DATA)?;
+        if size & DATA_INLINE != 0 || size == 0 {
+            return Ok(());
+        }
+
+        let cell = self.cell(offset)?;
+        if size as usize > BIG_DATA_SEGMENT_SIZE && self.minor_version >= 4 && cell.get(0..2) == Some(b"db") {
+            let list = read_u32(cell, 4)?;
+            let segments = self.cell(list)?;
+            let segments: Vec<u32> = (0..read_u16(cell, 2)? as usize)
+                .map(|i| read_u32(segments, i * 4))
+                .collect::<Result<_, _>>()?;
+            for segment in segments {
+                self.free(segment)?;
+            }
+            self.free(list)?;
+        }
+        self.free(offset)
+    }
+
+    /// Update the last written timestamp of a key, and mark the hive as modified
+    fn touch(&mut self, key: u32) -> Result<(), DejunkerError> {
+        self.modified = true;
+        let now = now_filetime().to_le_bytes();
+        self.cell_mut(key)?[NK_LAST_WRITTEN..NK_LAST_WRITTEN + 8].copy_from_slice(&now);
+        Ok(())
+    }
+
+    // --- cells ---
+
+    /// Data of an allocated cell (without the size field)
+    fn cell(&self, offset: u32) -> Result<&[u8], DejunkerError> {
+        let (start, end) = self.cell_bounds(offset)?;
+        Ok(&self.data[start..end])
+    }
+
+    fn cell_mut(&mut self, offset: u32) -> Result<&mut [u8], DejunkerError> {
+        let (start, end) = self.cell_bounds(offset)?;
+        Ok(&mut self.data[start..end])
+    }
+
+    fn cell_bounds(&self, offset: u32) -> Result<(usize, usize), DejunkerError> {
+        let position = BASE_BLOCK_SIZE + offset as usize;
+        let size = read_u32(&self.data, position)? as i32;
+        if size >= 0 {
+            return Err(DejunkerError::registry(format!("cell {:#x} is not allocated", offset)));
+        }
+        let end = position + size.unsigned_abs() as usize;
+        if end > self.data.len() || end < position + 4 {
+            return Err(DejunkerError::registry(format!("cell {:#x} is out of bounds", offset)));
+        }
+        Ok((position + 4, end))
+    }
+
+    fn put_u32(&mut self, cell: u32, field: usize, value: u32) -> Result<(), DejunkerError> {
+        let cell = self.cell_mut(cell)?;
+        let slot = cell.get_mut(field..field + 4).ok_or_else(|| DejunkerError::registry("cell field out of bounds"))?;
+        slot.copy_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+
+    /// Allocate a zero filled cell with room for `length` bytes of data
+    fn alloc(&mut self, length: usize) -> Result<u32, DejunkerError> {
+        let size = (length + 4 + 7) & !7;
+
+        let Some(index) = self.free_cells.iter().position(|&(_, free)| free >= size) else {
+            self.append_hbin(size)?;
+            return self.alloc(length);
+        };
+
+        let (offset, free) = self.free_cells.swap_remove(index);
+        let position = BASE_BLOCK_SIZE + offset as usize;
+        let size = if free - size >= 8 {
+            // split, the remainder stays free
+            let remainder = offset + size as u32;
+            write_u32(&mut self.data, position + size, (free - size) as u32);
+            self.free_cells.push((remainder, free - size));
+            size
+        } else {
+            free
+        };
+
+        write_u32(&mut self.data, position, (size as i32).wrapping_neg() as u32);
+        self.data[position + 4..position + size].fill(0);
+        Ok(offset)
+    }
+
+    fn free(&mut self, offset: u32) -> Result<(), DejunkerError> {
+        let (start, end) = self.cell_bounds(offset)?;
+        let size = end - start + 4;
+        write_u32(&mut self.data, start - 4, size as u32);
+        self.free_cells.push((offset, size));
+        Ok(())
+    }
+
+    /// Add a hive bin large enough to hold a cell of the given size
+    fn append_hbin(&mut self, cell_size: usize) -> Result<(), DejunkerError> {
+        let size = (cell_size + HBIN_HEADER_SIZE).div_ceil(HBIN_ALIGNMENT) * HBIN_ALIGNMENT;
+        let offset = self.data.len() - BASE_BLOCK_SIZE;
+        debug!("Adding hive bin at {:#x} ({} bytes)", offset, size);
+
+        let mut hbin = vec![0u8; size];
+        hbin[0..4].copy_from_slice(b"hbin");
+        write_u32(&mut hbin, 4, offset as u32);
+        write_u32(&mut hbin, 8, size as u32);
+        write_u64(&mut hbin, 20, now_filetime());
+        write_u32(&mut hbin, HBIN_HEADER_SIZE, (size - HBIN_HEADER_SIZE) as u32);
+        self.data.extend_from_slice(&hbin);
+
+        self.free_cells
+            .push(((offset + HBIN_HEADER_SIZE) as u32, size - HBIN_HEADER_SIZE));
+        Ok(())
+    }
+
+    /// Walk all hive bins, validating them and collecting their free cells
+    fn scan_free_cells(&mut self) -> Result<(), DejunkerError> {
+        let mut position = BASE_BLOCK_SIZE;
+        while position < self.data.len() {
+            if self.data.get(position..position + 4) != Some(b"hbin") {
+                return Err(DejunkerError::registry(format!("invalid hive bin at {:#x}", position - BASE_BLOCK_SIZE)));
+            }
+            let bin_size = read_u32(&self.data, position + 8)? as usize;
+            let bin_end = position + bin_size;
+            if bin_size < HBIN_HEADER_SIZE || bin_end > self.data.len() {
+                return Err(DejunkerError::registry(format!("invalid hive bin size at {:#x}", position - BASE_BLOCK_SIZE)));
+            }
+
+            let mut cell = position + HBIN_HEADER_SIZE;
+            while cell < bin_end {
+                let size = read_u32(&self.data, cell)? as i32;
+                let length = size.unsigned_abs() as usize;
+                if length < 8 || cell + length > bin_end {
+                    return Err(DejunkerError::registry(format!("invalid cell at {:#x}", cell - BASE_BLOCK_SIZE)));
+                }
+                if size > 0 {
+                    self.free_cells.push(((cell - BASE_BLOCK_SIZE) as u32, length));
+                }
+                cell += length;
+            }
+            position = bin_end;
+        }
+        Ok(())
+    }
+}
+
+/// XOR of the first 127 dwords of the base block
+fn checksum(data: &[u8]) -> u32 {
+    let sum = data[..BB_CHECKSUM]
+        .chunks_exact(4)
+        .fold(0u32, |sum, c| sum ^ u32::from_le_bytes([c[0], c[1], c[2], c[3]]));
+    match sum {
+        0xFFFF_FFFF => 0xFFFF_FFFE,
+        0 => 1,
+        sum => sum,
+    }
+}
+
+/// Names are stored as Latin-1 ("compressed") when possible, UTF-16 otherwise
+fn encode_name(name: &str) -> (Vec<u8>, bool) {
+    if name.chars().all(|c| (c as u32) < 0x100) {
+        (name.chars().map(|c| c as u8).collect(), true)
+    } else {
+        (
+            name.encode_utf16().flat_map(|c| c.to_le_bytes()).collect(),
+            false,
+        )
+    }
+}
+
+fn decode_name(data: &[u8], compressed: bool) -> String {
+    if compressed {
+        data.iter().map(|&c| c as char).collect()
+    } else {
+        super::decode_utf16(data)
+    }
+}
+
+/// Uppercased UTF-16 form of a name, which is what the registry compares and sorts on.
+/// Like Windows, only characters of the basic multilingual plane are case mapped.
+fn upcase(name: &str) -> Vec<u16> {
+    name.chars()
+        .map(|c| {
+            let mut upper = c.to_uppercase();
+            match (upper.next(), upper.next()) {
+                (Some(u), None) if (c as u32) < 0x10000 && (u as u32) < 0x10000 => u,
+                _ => c,
+            }
+        })
+        .collect::<String>()
+        .encode_utf16()
+        .collect()
+}
+
+/// Current time as a Windows FILETIME (100ns intervals since 1601-01-01)
+fn now_filetime() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (since_epoch.as_secs() + 11_644_473_600) * 10_000_000 + since_epoch.subsec_nanos() as u64 / 100
+}
+
+fn read_u16(data: &[u8], position: usize) -> Result<u16, DejunkerError> {
+    let bytes = data.get(position..position + 2).ok_or_else(|| DejunkerError::registry("read past the end of the hive"))?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], position: usize) -> Result<u32, DejunkerError> {
+    let bytes = data.get(position..position + 4).ok_or_else(|| DejunkerError::registry("read past the end of the hive"))?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn write_u32(data: &mut [u8], position: usize, value: u32) {
+    data[position..position + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u64(data: &mut [u8], position: usize, value: u64) {
+    data[position..position + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT_CELL: usize = 0x20;
+    const ROOT_NAME: &[u8] = b"ROOT";
+
+    /// A hive with a single, empty hive bin holding the root key and its security descriptor
+    fn empty_hive(minor_version: u32) -> HiveFile {
+        let mut data = vec![0u8; BASE_BLOCK_SIZE + HBIN_ALIGNMENT];
+        data[0..4].copy_from_slice(b"regf");
+        write_u32(&mut data, BB_PRIMARY_SEQUENCE, 1);
+        write_u32(&mut data, BB_SECONDARY_SEQUENCE, 1);
+        write_u32(&mut data, BB_MAJOR_VERSION, 1);
+        write_u32(&mut data, BB_MINOR_VERSION, minor_version);
+        write_u32(&mut data, BB_ROOT_CELL, ROOT_CELL as u32);
+        write_u32(&mut data, BB_HBINS_SIZE, HBIN_ALIGNMENT as u32);
+
+        let hbin = BASE_BLOCK_SIZE;
+        data[hbin..hbin + 4].copy_from_slice(b"hbin");
+        write_u32(&mut data, hbin + 8, HBIN_ALIGNMENT as u32);
+
+        let root_size = (NK_NAME + ROOT_NAME.len() + 4 + 7) & !7;
+        let security = ROOT_CELL + root_size;
+        let root = hbin + ROOT_CELL;
+        write_u32(&mut data, root, (root_size as i32).wrapping_neg() as u32);
+        let nk = root + 4;
+        data[nk..nk + 2].copy_from_slice(b"nk");
+        data[nk + NK_FLAGS..nk + NK_FLAGS + 2].copy_from_slice(&KEY_COMP_NAME.to_le_bytes());
+        for field in [NK_SUBKEY_LIST, NK_VOLATILE_SUBKEY_LIST, NK_VALUE_LIST, NK_CLASS] {
+            write_u32(&mut data, nk + field, NO_CELL);
+        }
+        write_u32(&mut data, nk + NK_SECURITY, security as u32);
+        data[nk + NK_NAME_LENGTH..nk + NK_NAME_LENGTH + 2].copy_from_slice(&(ROOT_NAME.len() as u16).to_le_bytes());
+        data[nk + NK_NAME..nk + NK_NAME + ROOT_NAME.len()].copy_from_slice(ROOT_NAME);
+
+        // a security descriptor linked to itself, with an empty descriptor
+        let sk_size = 24;
+        write_u32(&mut data, hbin + security, (sk_size as i32).wrapping_neg() as u32);
+        let sk = hbin + security + 4;
+        data[sk..sk + 2].copy_from_slice(b"sk");
+        write_u32(&mut data, sk + SK_FLINK, security as u32);
+        write_u32(&mut data, sk + SK_BLINK, security as u32);
+        write_u32(&mut data, sk + SK_REFERENCES, 1);
+
+        // the rest of the bin is a single free cell
+        let free = security + sk_size;
+        write_u32(&mut data, hbin + free, (HBIN_ALIGNMENT - free) as u32);
+
+        let sum = checksum(&data);
+        write_u32(&mut data, BB_CHECKSUM, sum);
+        HiveFile::from_bytes(data).unwrap()
+    }
+
+    fn temp_file(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("dejunker-{}-{}.dat", name, std::process::id()))
+    }
+
+    /// The signature of the subkey list of a key, and the names in the order of the list
+    fn subkey_list(hive: &HiveFile, sub_path: &str) -> (Vec<u8>, Vec<String>) {
+        let key = hive.find_key(sub_path).unwrap().unwrap();
+        let list = read_u32(hive.cell(key).unwrap(), NK_SUBKEY_LIST).unwrap();
+        (hive.cell(list).unwrap()[0..2].to_vec(), hive.enum_keys(sub_path).unwrap())
+    }
+
+    #[test]
+    fn hives_round_trip_through_a_file() {
+        let mut hive = empty_hive(5);
+        let big: Vec<u8> = (0..BIG_DATA_SEGMENT_SIZE * 2 + 100).map(|i| i as u8).collect();
+        hive.set_value("Software\\Test", "Dword", &RegistryValue::Dword(42)).unwrap();
+        hive.set_value("Software\\Test", "String", &RegistryValue::String("hello".to_string())).unwrap();
+        hive.set_value("Software\\Test", "Big", &RegistryValue::Binary(big.clone())).unwrap();
+        hive.set_value("Software\\Test", "Gone", &RegistryValue::Dword(1)).unwrap();
+        for name in ["zeta", "Alpha", "beta", "Gamma"] {
+            hive.set_value(&format!("Software\\{}", name), "", &RegistryValue::Dword(0)).unwrap();
+        }
+        hive.delete_value("Software\\Test", "Gone").unwrap();
+        hive.delete_key("Software\\Gamma").unwrap();
+        assert!(hive.is_modified());
+
+        // values over a segment are stored as big data
+        let test = hive.find_key("Software\\Test").unwrap().unwrap();
+        let (_, vk) = hive.find_value(test, "Big").unwrap().unwrap();
+        let data = read_u32(hive.cell(vk).unwrap(), VK_DATA).unwrap();
+        assert_eq!(&hive.cell(data).unwrap()[0..2], b"db");
+
+        let path = temp_file("regf-round-trip");
+        hive.save(&path).unwrap();
+        assert!(!hive.is_modified());
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        assert!(!Path::new(&temp_path).exists());
+
+        let saved = fs::read(&path).unwrap();
+        assert_eq!(read_u32(&saved, BB_CHECKSUM).unwrap(), checksum(&saved));
+        assert_eq!(read_u32(&saved, BB_PRIMARY_SEQUENCE).unwrap(), 2);
+        assert_eq!(read_u32(&saved, BB_SECONDARY_SEQUENCE).unwrap(), 2);
+        assert_eq!(read_u32(&saved, BB_HBINS_SIZE).unwrap() as usize, saved.len() - BASE_BLOCK_SIZE);
+
+        let reopened = HiveFile::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(reopened.get_value("SOFTWARE\\test", "dword").unwrap(), Some(RegistryValue::Dword(42)));
+        assert_eq!(
+            reopened.get_value("Software\\Test", "String").unwrap(),
+            Some(RegistryValue::String("hello".to_string()))
+        );
+        assert_eq!(reopened.get_value("Software\\Test", "Big").unwrap(), Some(RegistryValue::Binary(big)));
+        assert_eq!(reopened.get_value("Software\\Test", "Gone").unwrap(), None);
+        assert_eq!(reopened.enum_values("Software\\Test").unwrap(), ["Dword", "String", "Big"]);
+
+        // subkeys are sorted on their uppercase names, in a hash leaf
+        let (signature, names) = subkey_list(&reopened, "Software");
+        assert_eq!(signature, b"lh");
+        assert_eq!(names, ["Alpha", "beta", "Test", "zeta"]);
+    }
+
+    #[test]
+    fn older_hives_use_fast_leaves_and_no_big_data() {
+        let mut hive = empty_hive(3);
+        let big = vec![7u8; BIG_DATA_SEGMENT_SIZE + 1];
+        hive.set_value("b", "Big", &RegistryValue::Binary(big.clone())).unwrap();
+        hive.set_value("A", "", &RegistryValue::Dword(0)).unwrap();
+
+        let key = hive.find_key("b").unwrap().unwrap();
+        let (_, vk) = hive.find_value(key, "Big").unwrap().unwrap();
+        let data = read_u32(hive.cell(vk).unwrap(), VK_DATA).unwrap();
+        assert_ne!(&hive.cell(data).unwrap()[0..2], b"db");
+        assert_eq!(hive.get_value("b", "Big").unwrap(), Some(RegistryValue::Binary(big)));
+
+        let (signature, names) = subkey_list(&hive, "");
+        assert_eq!(signature, b"lf");
+        assert_eq!(names, ["A", "b"]);
+    }
+
+    #[test]
+    fn freed_cells_are_reused() {
+        let mut hive = empty_hive(5);
+        let data = RegistryValue::Binary(vec![1u8; 3000]);
+
+        // the first bin has room for a single value this size
+        hive.set_value("Test", "First", &data).unwrap();
+        let size = hive.data.len();
+        assert_eq!(size, BASE_BLOCK_SIZE + HBIN_ALIGNMENT);
+        hive.delete_value("Test", "First").unwrap();
+        hive.set_value("Test", "Second", &data).unwrap();
+        assert_eq!(hive.data.len(), size);
+
+        hive.delete_key("Test").unwrap();
+        hive.set_value("Other", "Third", &data).unwrap();
+        assert_eq!(hive.data.len(), size);
+        assert_eq!(hive.enum_keys("").unwrap(), ["Other"]);
+    }
+}
@@ -0,0 +1,506 @@
+use super::{get_path_components, Hive, RegistryBackend, RegistryValue};
+use crate::error::DejunkerError;
+use log::{debug, info, warn};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::result::Result;
+
+/// The key of HKEY_USERS the Default profile is loaded at (the profiles of users are loaded
+/// at their SID, like Windows does when they log in)
+pub const DEFAULT_PROFILE_KEY: &str = "dejunker_Default";
+
+/// Name of the registry hive file in a profile directory
+pub const USER_HIVE_FILE: &str = "NTUSER.DAT";
+
+// built in service accounts (SYSTEM, LOCAL SERVICE and NETWORK SERVICE), they have profiles
+// but are not users
+const SERVICE_SIDS: [&str; 3] = ["S-1-5-18", "S-1-5-19", "S-1-5-20"];
+
+/// A user profile: a directory holding the registry hive (NTUSER.DAT) of a user
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UserProfile {
+    /// The name of the profile directory, which is the user name the profile was created for
+    pub name: String,
+    /// The SID of the user, None for the Default profile
+    pub sid: Option<String>,
+    pub directory: PathBuf,
+}
+
+impl UserProfile {
+    /// A user profile, named after its directory
+    pub fn new(sid: Option<&str>, directory: &Path) -> Self {
+        let name = directory
+            .to_string_lossy()
+            .rsplit(['\\', '/'])
+            .find(|part| !part.is_empty())
+            .unwrap_or_default()
+            .to_string();
+        UserProfile {
+            name,
+            sid: sid.map(|sid| sid.to_string()),
+            directory: directory.to_path_buf(),
+        }
+    }
+
+    /// The key of HKEY_USERS the hive of the profile is (or gets) loaded at
+    pub fn key(&self) -> &str {
+        self.sid.as_deref().unwrap_or(DEFAULT_PROFILE_KEY)
+    }
+
+    /// The registry hive file of the profile
+    pub fn hive_file(&self) -> PathBuf {
+        self.directory.join(USER_HIVE_FILE)
+    }
+}
+
+impl fmt::Display for UserProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.sid {
+            Some(sid) => write!(f, "{} ({})", self.name, sid),
+            None => write!(f, "{} (Default profile)", self.name),
+        }
+    }
+}
+
+/// The user profiles of a machine, and the loading of their registry hives. The live
+/// implementation reads the profile list of Windows, anything else (e.g. a fake for tests)
+/// can implement this trait.
+pub trait UserProfiles {
+    /// The profiles of the users of the machine, including service accounts (but not the
+    /// Default profile)
+    fn profiles(&self) -> Result<Vec<UserProfile>, DejunkerError>;
+
+    /// The Default profile, new users start out with a copy of it
+    fn default_profile(&self) -> Result<UserProfile, DejunkerError>;
+
+    /// Whether a hive is loaded at a key of HKEY_USERS (e.g. the user is logged in)
+    fn is_loaded(&self, key: &str) -> Result<bool, DejunkerError>;
+
+    /// Load a hive file at a key of HKEY_USERS
+    fn load_hive(&mut self, key: &str, file: &Path) -> Result<(), DejunkerError>;
+
+    /// Unload a hive loaded with load_hive
+    fn unload_hive(&mut self, key: &str) -> Result<(), DejunkerError>;
+}
+
+/// The user(s) per user (HKEY_CURRENT_USER) rules are applied to, instead of the user we are
+/// running as
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TargetUser {
+    /// Every user with a profile (not the service accounts)
+    All,
+    /// The Default profile, i.e. users created from now on
+    Default,
+    Sid(String),
+    /// A user, by the name of their profile directory
+    Name(String),
+}
+
+impl TargetUser {
+    /// Parse a target user: all, default, a SID (S-1-...) or a user name. DOMAIN\name is
+    /// matched on the name alone.
+    pub fn from_name(name: &str) -> TargetUser {
+        match name.to_lowercase().as_str() {
+            "all" => TargetUser::All,
+            "default" => TargetUser::Default,
+            lower if lower.starts_with("s-1-") => TargetUser::Sid(name.to_uppercase()),
+            _ => TargetUser::Name(name.rsplit('\\').next().unwrap_or(name).to_string()),
+        }
+    }
+
+    /// Find the profiles of the targeted user(s). Fails if a user has no profile.
+    pub fn resolve(&self, profiles: &dyn UserProfiles) -> Result<Vec<UserProfile>, DejunkerError> {
+        let users = || -> Result<Vec<UserProfile>, DejunkerError> {
+            Ok(profiles
+                .profiles()?
+                .into_iter()
+                .filter(|profile| !profile.sid.as_deref().is_some_and(|sid| SERVICE_SIDS.contains(&sid)))
+                .collect())
+        };
+
+        let found: Vec<UserProfile> = match self {
+            TargetUser::All => users()?,
+            TargetUser::Default => vec![profiles.default_profile()?],
+            TargetUser::Sid(sid) => users()?
+                .into_iter()
+                .filter(|profile| profile.sid.as_deref().is_some_and(|s| s.eq_ignore_ascii_case(sid)))
+                .collect(),
+            TargetUser::Name(name) => users()?
+                .into_iter()
+                .filter(|profile| profile.name.eq_ignore_ascii_case(name))
+                .collect(),
+        };
+
+        match self {
+            TargetUser::Sid(user) | TargetUser::Name(user) if found.is_empty() => {
+                Err(DejunkerError::UserProfile(format!("no profile found for user {}", user)))
+            }
+            _ => Ok(found),
+        }
+    }
+}
+
+/// Run something against the registry of each targeted user, with HKEY_CURRENT_USER standing
+/// for the hive of that user. The hives of users that are not logged in are loaded for the
+/// duration, and unloaded afterwards (also when it fails).
+///
+/// * registry: the registry the hives are loaded in
+/// * profiles: the user profiles of the machine
+/// * target: the user(s) to run for
+/// * run: what to run, with the profile and the registry of the user
+///
+pub fn for_each_user(
+    registry: &mut dyn RegistryBackend,
+    profiles: &mut dyn UserProfiles,
+    target: &TargetUser,
+    mut run: impl FnMut(&UserProfile, &mut dyn RegistryBackend) -> Result<(), DejunkerError>,
+) -> Result<(), DejunkerError> {
+    let targets = target.resolve(profiles)?;
+    if targets.is_empty() {
+        warn!("There are no user profiles to apply to");
+    }
+
+    for profile in targets {
+        let key = profile.key().to_string();
+        let load = !profiles.is_loaded(&key)?;
+        if load {
+            info!("Loading the registry hive of {}", profile);
+            profiles.load_hive(&key, &profile.hive_file())?;
+        }
+
+        let result = run(&profile, &mut UserRegistry::new(registry, &key));
+
+        if load {
+            debug!("Unloading the registry hive of {}", profile);
+            let unloaded = profiles.unload_hive(&key);
+            result?;
+            unloaded?;
+        } else {
+            result?;
+        }
+    }
+    Ok(())
+}
+
+/// Wraps another registry, with HKEY_CURRENT_USER mapped to a key of HKEY_USERS (the hive of
+/// another user). Other paths are left alone.
+pub struct UserRegistry<'a> {
+    inner: &'a mut dyn RegistryBackend,
+    key: String,
+}
+
+impl<'a> UserRegistry<'a> {
+    /// * inner: the registry to wrap
+    /// * key: the key of HKEY_USERS standing for HKEY_CURRENT_USER (usually a SID)
+    pub fn new(inner: &'a mut dyn RegistryBackend, key: &str) -> Self {
+        UserRegistry {
+            inner,
+            key: key.to_string(),
+        }
+    }
+
+    /// The path a path maps to
+    fn map(&self, path: &str) -> Result<String, DejunkerError> {
+        user_path(path, &self.key)
+    }
+}
+
+/// The path a registry path maps to when HKEY_CURRENT_USER stands for the hive loaded at a key
+/// of HKEY_USERS (see UserRegistry). Other paths are left alone.
+///
+/// * path: the registry path
+/// * key: the key of HKEY_USERS standing for HKEY_CURRENT_USER (usually a SID)
+///
+pub fn user_path(path: &str, key: &str) -> Result<String, DejunkerError> {
+    match get_path_components(path)? {
+        (Hive::CurrentUser, sub_path) if sub_path.is_empty() => Ok(format!("{}\\{}", Hive::Users, key)),
+        (Hive::CurrentUser, sub_path) => Ok(format!("{}\\{}\\{}", Hive::Users, key, sub_path)),
+        _ => Ok(path.to_string()),
+    }
+}
+
+impl RegistryBackend for UserRegistry<'_> {
+    fn get_value(&self, path: &str, value_name: &str) -> Result<Option<RegistryValue>, DejunkerError> {
+        self.inner.get_value(&self.map(path)?, value_name)
+    }
+
+    fn set_value(&mut self, path: &str, value_name: &str, value: &RegistryValue) -> Result<(), DejunkerError> {
+        let path = self.map(path)?;
+        self.inner.set_value(&path, value_name, value)
+    }
+
+    fn delete_value(&mut self, path: &str, value_name: &str) -> Result<(), DejunkerError> {
+        let path = self.map(path)?;
+        self.inner.delete_value(&path, value_name)
+    }
+
+    fn delete_key(&mut self, path: &str) -> Result<(), DejunkerError> {
+        let path = self.map(path)?;
+        self.inner.delete_key(&path)
+    }
+
+    fn enum_keys(&self, path: &str) -> Result<Vec<String>, DejunkerError> {
+        self.inner.enum_keys(&self.map(path)?)
+    }
+
+    fn enum_values(&self, path: &str) -> Result<Vec<String>, DejunkerError> {
+        self.inner.enum_values(&self.map(path)?)
+    }
+
+    fn is_accessible(&self, path: &str) -> bool {
+        self.map(path).is_ok_and(|path| self.inner.is_accessible(&path))
+    }
+
+    fn requires_elevation(&self) -> bool {
+        self.inner.requires_elevation()
+    }
+}
+
+/// The user profiles of the machine we are running on, from the profile list of Windows
+#[cfg(windows)]
+pub struct LocalProfiles {
+    registry: super::Win32Registry,
+}
+
+#[cfg(windows)]
+const PROFILE_LIST_KEY: &str = "HKEY_LOCAL_MACHINE\\SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\ProfileList";
+
+#[cfg(windows)]
+impl LocalProfiles {
+    /// A directory of the profile list (these are REG_EXPAND_SZ, e.g. %SystemDrive%\Users)
+    fn directory(&self, path: &str, value_name: &str) -> Result<Option<PathBuf>, DejunkerError> {
+        Ok(match self.registry.get_value(path, value_name)? {
+            Some(RegistryValue::String(directory)) | Some(RegistryValue::ExpandString(directory)) => {
+                Some(PathBuf::from(expand_variables(&directory)))
+            }
+            _ => None,
+        })
+    }
+}
+
+#[cfg(windows)]
+impl UserProfiles for LocalProfiles {
+    fn profiles(&self) -> Result<Vec<UserProfile>, DejunkerError> {
+        let mut profiles = Vec::new();
+        for sid in self.registry.enum_keys(PROFILE_LIST_KEY)? {
+            let path = format!("{}\\{}", PROFILE_LIST_KEY, sid);
+            match self.directory(&path, "ProfileImagePath")? {
+                Some(directory) => profiles.push(UserProfile::new(Some(&sid), &directory)),
+                None => debug!("Profile {} has no directory, skipping it", sid),
+            }
+        }
+        Ok(profiles)
+    }
+
+    fn default_profile(&self) -> Result<UserProfile, DejunkerError> {
+        let directory = self
+            .directory(PROFILE_LIST_KEY, "Default")?
+            .ok_or_else(|| DejunkerError::UserProfile("the Default profile was not found".to_string()))?;
+        Ok(UserProfile::new(None, &directory))
+    }
+
+    fn is_loaded(&self, key: &str) -> Result<bool, DejunkerError> {
+        self.registry.key_exists(&format!("{}\\{}", Hive::Users, key))
+    }
+
+    fn load_hive(&mut self, key: &str, file: &Path) -> Result<(), DejunkerError> {
+        self.registry.load_hive(key, file)
+    }
+
+    fn unload_hive(&mut self, key: &str) -> Result<(), DejunkerError> {
+        self.registry.unload_hive(key)
+    }
+}
+
+/// Replace the %VARIABLES% of a string with their value (unknown variables are left alone)
+#[cfg(windows)]
+fn expand_variables(value: &str) -> String {
+    let mut output = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('%') {
+        let Some(length) = rest[start + 1..].find('%') else {
+            break;
+        };
+        output.push_str(&rest[..start]);
+        match std::env::var(&rest[start + 1..start + 1 + length]) {
+            Ok(expanded) => output.push_str(&expanded),
+            Err(_) => output.push_str(&rest[start..start + length + 2]),
+        }
+        rest = &rest[start + length + 2..];
+    }
+    output.push_str(rest);
+    output
+}
+
+/// The user profiles of the machine we are running on
+#[cfg(windows)]
+pub fn local_profiles() -> Result<Box<dyn UserProfiles>, DejunkerError> {
+    Ok(Box::new(LocalProfiles {
+        registry: super::Win32Registry,
+    }))
+}
+
+/// The user profiles of the machine we are running on. There are no user profiles to find
+/// outside of Windows.
+#[cfg(not(windows))]
+pub fn local_profiles() -> Result<Box<dyn UserProfiles>, DejunkerError> {
+    Err(DejunkerError::UserProfile(
+        "user profiles can only be found on Windows".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::journal::{undo_journal, Journal};
+    use crate::registry::recorder::RecordingRegistry;
+    use crate::registry::MemoryRegistry;
+    use crate::subsystems::Subsystems;
+
+    const ALICE: &str = "S-1-5-21-1000-1001";
+    const BOB: &str = "S-1-5-21-1000-1002";
+
+    /// Alice is logged in, Bob is not
+    struct FakeProfiles {
+        loaded: Vec<String>,
+        calls: Vec<String>,
+    }
+
+    impl FakeProfiles {
+        fn new() -> Self {
+            FakeProfiles {
+                loaded: vec![ALICE.to_string()],
+                calls: Vec::new(),
+            }
+        }
+    }
+
+    impl UserProfiles for FakeProfiles {
+        fn profiles(&self) -> Result<Vec<UserProfile>, DejunkerError> {
+            Ok(vec![
+                UserProfile::new(Some("S-1-5-18"), Path::new("C:\\Windows\\system32\\config\\systemprofile")),
+                UserProfile::new(Some(ALICE), Path::new("C:\\Users\\alice")),
+                UserProfile::new(Some(BOB), Path::new("C:\\Users\\Bob")),
+            ])
+        }
+
+        fn default_profile(&self) -> Result<UserProfile, DejunkerError> {
+            Ok(UserProfile::new(None, Path::new("C:\\Users\\Default")))
+        }
+
+        fn is_loaded(&self, key: &str) -> Result<bool, DejunkerError> {
+            Ok(self.loaded.iter().any(|loaded| loaded == key))
+        }
+
+        fn load_hive(&mut self, key: &str, file: &Path) -> Result<(), DejunkerError> {
+            self.calls.push(format!("load {} {}", key, file.display()));
+            self.loaded.push(key.to_string());
+            Ok(())
+        }
+
+        fn unload_hive(&mut self, key: &str) -> Result<(), DejunkerError> {
+            self.calls.push(format!("unload {}", key));
+            self.loaded.retain(|loaded| loaded != key);
+            Ok(())
+        }
+    }
+
+    fn resolve(target: &str) -> Result<Vec<String>, DejunkerError> {
+        let profiles = TargetUser::from_name(target).resolve(&FakeProfiles::new())?;
+        Ok(profiles.iter().map(|profile| profile.to_string()).collect())
+    }
+
+    #[test]
+    fn target_users_resolve_to_profiles() {
+        assert_eq!(
+            resolve("all").unwrap(),
+            [format!("alice ({})", ALICE), format!("Bob ({})", BOB)]
+        );
+        assert_eq!(resolve("BOB").unwrap(), [format!("Bob ({})", BOB)]);
+        assert_eq!(resolve("CONTOSO\\alice").unwrap(), [format!("alice ({})", ALICE)]);
+        assert_eq!(resolve(&BOB.to_lowercase()).unwrap(), [format!("Bob ({})", BOB)]);
+        assert_eq!(resolve("default").unwrap(), ["Default (Default profile)"]);
+
+        assert!(matches!(resolve("carol"), Err(DejunkerError::UserProfile(_))));
+        assert!(matches!(resolve("S-1-5-18"), Err(DejunkerError::UserProfile(_))));
+    }
+
+    #[test]
+    fn current_user_is_mapped_to_the_target_user() {
+        let mut registry = MemoryRegistry::new();
+        let mut profiles = FakeProfiles::new();
+
+        for_each_user(&mut registry, &mut profiles, &TargetUser::All, |_, registry| {
+            registry.set_u32_value("HKCU\\Software\\Test", "Value", 1)?;
+            registry.set_u32_value("HKLM\\Software\\Test", "Value", 2)
+        })
+        .unwrap();
+
+        for sid in [ALICE, BOB] {
+            let path = format!("HKEY_USERS\\{}\\Software\\Test", sid);
+            assert_eq!(registry.get_value(&path, "Value").unwrap(), Some(RegistryValue::Dword(1)));
+        }
+        assert_eq!(registry.get_value("HKCU\\Software\\Test", "Value").unwrap(), None);
+        assert_eq!(
+            registry.get_value("HKLM\\Software\\Test", "Value").unwrap(),
+            Some(RegistryValue::Dword(2))
+        );
+
+        // only the hive of the user that is not logged in is loaded, and unloaded afterwards
+        assert_eq!(
+            profiles.calls,
+            [format!("load {} C:\\Users\\Bob{}NTUSER.DAT", BOB, std::path::MAIN_SEPARATOR), format!("unload {}", BOB)]
+        );
+        assert_eq!(profiles.loaded, [ALICE]);
+    }
+
+    #[test]
+    fn changes_are_journaled_at_the_hive_of_the_user() {
+        let mut registry = MemoryRegistry::new();
+        let mut profiles = FakeProfiles::new();
+        let mut changes = Vec::new();
+
+        for_each_user(&mut registry, &mut profiles, &TargetUser::All, |profile, registry| {
+            let mut recorder = RecordingRegistry::new(registry, true);
+            recorder.set_u32_value("HKCU\\Software\\Test", "Value", 1)?;
+            for change in recorder.changes() {
+                changes.push(change.mapped(|path| user_path(path, profile.key()))?);
+            }
+            Ok(())
+        })
+        .unwrap();
+        let paths: Vec<&str> = changes.iter().map(|change| change.path()).collect();
+        assert_eq!(
+            paths,
+            [
+                format!("HKEY_USERS\\{}\\Software", ALICE),
+                format!("HKEY_USERS\\{}\\Software\\Test", ALICE),
+                format!("HKEY_USERS\\{}\\Software", BOB),
+                format!("HKEY_USERS\\{}\\Software\\Test", BOB)
+            ]
+        );
+
+        // Bob is not logged in, the values of Bob are left for the next time Bob is targeted
+        let journal = Journal::from_changes(&changes, &[]);
+        registry.delete_key(&format!("HKEY_USERS\\{}", BOB)).unwrap();
+        undo_journal(&mut registry, &mut Subsystems::none(), &journal).unwrap();
+        let alice = format!("HKEY_USERS\\{}\\Software\\Test", ALICE);
+        assert_eq!(registry.get_value(&alice, "Value").unwrap(), None);
+        assert!(!registry.key_exists(&format!("HKEY_USERS\\{}\\Software", ALICE)).unwrap());
+        assert!(!registry.key_exists(&format!("HKEY_USERS\\{}", BOB)).unwrap());
+    }
+
+    #[test]
+    fn hives_are_unloaded_on_failure() {
+        let mut registry = MemoryRegistry::new();
+        let mut profiles = FakeProfiles::new();
+
+        let result = for_each_user(&mut registry, &mut profiles, &TargetUser::Default, |_, _| {
+            Err(DejunkerError::registry("failed"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(profiles.calls.last().unwrap(), &format!("unload {}", DEFAULT_PROFILE_KEY));
+        assert_eq!(profiles.loaded, [ALICE]);
+    }
+}
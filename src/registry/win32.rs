@@ -0,0 +1,364 @@
+use super::{get_path_components, read_sized, Hive, RegistryBackend, RegistryValue, WOW64_32_KEY};
+use crate::error::DejunkerError;
+use crate::utils::{self, to_wide};
+use log::debug;
+use std::path::Path;
+use std::result::Result;
+use windows::core::{PCWSTR, PWSTR};
+use windows::Win32::Security::{SE_BACKUP_NAME, SE_RESTORE_NAME};
+use windows::Win32::Foundation::{
+    ERROR_ACCESS_DENIED, ERROR_FILE_NOT_FOUND, ERROR_MORE_DATA, ERROR_NO_MORE_ITEMS, ERROR_SUCCESS, WIN32_ERROR,
+};
+use windows::Win32::System::Registry::{
+    RegCloseKey, RegCreateKeyExW, RegDeleteKeyExW, RegDeleteKeyValueW, RegDeleteTreeW, RegEnumKeyExW,
+    RegEnumValueW, RegGetValueW, RegLoadKeyW, RegOpenKeyExW, RegSetKeyValueW, RegUnLoadKeyW, HKEY,
+    HKEY_CLASSES_ROOT, HKEY_CURRENT_CONFIG, HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, HKEY_USERS, KEY_ALL_ACCESS,
+    KEY_READ, KEY_SET_VALUE, KEY_WOW64_32KEY, KEY_WOW64_64KEY, KEY_WRITE, REG_OPTION_NON_VOLATILE, REG_SAM_FLAGS,
+    REG_VALUE_TYPE, RRF_NOEXPAND, RRF_RT_ANY, RRF_SUBKEY_WOW6432KEY, RRF_SUBKEY_WOW6464KEY,
+};
+
+/// The registry of the machine we are running on, accessed through the Win32 API
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Win32Registry;
+
+impl Win32Registry {
+    /// Load a hive file (e.g. the NTUSER.DAT of a user that is not logged in) at a key of
+    /// HKEY_USERS. This needs an elevated process.
+    ///
+    /// * key: the key of HKEY_USERS to load the hive at
+    /// * file: the hive file
+    ///
+    pub fn load_hive(&self, key: &str, file: &Path) -> Result<(), DejunkerError> {
+        // held by administrators, but not enabled by default
+        for privilege in [SE_BACKUP_NAME, SE_RESTORE_NAME] {
+            if !utils::enable_privilege(privilege) {
+                debug!("Could not enable privilege {}", unsafe { privilege.display() });
+            }
+        }
+
+        let key_wide = to_wide(key);
+        let file_wide = to_wide(&file.to_string_lossy());
+        let result = unsafe { RegLoadKeyW(HKEY_USERS, PCWSTR(key_wide.as_ptr()), PCWSTR(file_wide.as_ptr())) };
+        if result != ERROR_SUCCESS {
+            return Err(win32_error(
+                format!("Failed to load {} at {}\\{}", file.display(), Hive::Users, key),
+                result,
+            ));
+        }
+        debug!("Loaded {} at {}\\{}", file.display(), Hive::Users, key);
+        Ok(())
+    }
+
+    /// Unload a hive loaded with load_hive
+    pub fn unload_hive(&self, key: &str) -> Result<(), DejunkerError> {
+        let key_wide = to_wide(key);
+        let result = unsafe { RegUnLoadKeyW(HKEY_USERS, PCWSTR(key_wide.as_ptr())) };
+        if result != ERROR_SUCCESS {
+            return Err(win32_error(format!("Failed to unload {}\\{}", Hive::Users, key), result));
+        }
+        debug!("Unloaded {}\\{}", Hive::Users, key);
+        Ok(())
+    }
+}
+
+impl RegistryBackend for Win32Registry {
+    fn get_value(&self, path: &str, value_name: &str) -> Result<Option<RegistryValue>, DejunkerError> {
+        let log_message: String = format!("Reading registry value: {} -> {}: ", path, value_name);
+        let (hive, sub_path) = get_path_components(path)?;
+
+        let (sub_path, view) = split_view(&sub_path);
+        let view_flags = match view == KEY_WOW64_32KEY {
+            true => RRF_SUBKEY_WOW6432KEY,
+            false => RRF_SUBKEY_WOW6464KEY,
+        };
+        let value_wide = to_wide(value_name);
+        let sub_path_wide = to_wide(&sub_path);
+
+        let mut kind = REG_VALUE_TYPE::default();
+        let (result, buffer) = read_sized(ERROR_MORE_DATA, ERROR_SUCCESS, |data, length| unsafe {
+            RegGetValueW(
+                get_hkey(hive),
+                PCWSTR(sub_path_wide.as_ptr()),
+                PCWSTR(value_wide.as_ptr()),
+                RRF_RT_ANY | RRF_NOEXPAND | view_flags,
+                Some(&mut kind),
+                data.map(|data| data.as_mut_ptr() as *mut _),
+                Some(length),
+            )
+        });
+
+        if result == ERROR_FILE_NOT_FOUND {
+            debug!("{}[NOT FOUND]", log_message);
+            return Ok(None);
+        }
+        if result != ERROR_SUCCESS {
+            debug!("{}[FAILED] (ERR = {})", log_message, result.0);
+            return Err(win32_error(format!("Failed to read registry value {} -> {}", path, value_name), result));
+        }
+
+        let value = RegistryValue::from_raw(kind.0, &buffer);
+        debug!("{}[SUCCESS] (Value = {})", log_message, value);
+        Ok(Some(value))
+    }
+
+    fn set_value(&mut self, path: &str, value_name: &str, value: &RegistryValue) -> Result<(), DejunkerError> {
+        let log_message: String = format!(
+            "Setting registry value: {} -> {}: {}",
+            path, value_name, value
+        );
+        let (hive, sub_path) = get_path_components(path)?;
+        let (sub_path, view) = split_view(&sub_path);
+
+        let value_wide = to_wide(value_name);
+        let path_wide = to_wide(&sub_path);
+
+        // Create the key if it doesn't exist
+        let mut key_handle: HKEY = HKEY::default();
+        let result = unsafe {
+            RegCreateKeyExW(
+                get_hkey(hive),
+                PCWSTR(path_wide.as_ptr()),
+                0,
+                None,
+                REG_OPTION_NON_VOLATILE,
+                KEY_WRITE | view,
+                None,
+                &mut key_handle,
+                None,
+            )
+        };
+
+        if result != ERROR_SUCCESS {
+            debug!("{}[FAILED] (ERR = {})", log_message, result.0);
+            return Err(win32_error(format!("Failed to create registry key {}", path), result));
+        }
+
+        let data = value.to_raw();
+        let result = unsafe {
+            RegSetKeyValueW(
+                key_handle,
+                PCWSTR::null(),
+                PCWSTR(value_wide.as_ptr()),
+                value.kind(),
+                Some(data.as_ptr() as *const _),
+                data.len() as u32,
+            )
+        };
+        unsafe {
+            _ = RegCloseKey(key_handle);
+        }
+
+        if result != ERROR_SUCCESS {
+            debug!("{}[FAILED] (ERR = {})", log_message, result.0);
+            return Err(win32_error(format!("Failed to set registry value {} -> {}", path, value_name), result));
+        }
+
+        debug!("{}[SUCCESS]", log_message);
+        Ok(())
+    }
+
+    fn delete_value(&mut self, path: &str, value_name: &str) -> Result<(), DejunkerError> {
+        let log_message: String = format!("Deleting registry value: {} -> {}", path, value_name);
+        let Some(key) = open_key(path, KEY_SET_VALUE)? else {
+            debug!("{}[SUCCESS] (the key does not exist)", log_message);
+            return Ok(());
+        };
+
+        let value_wide = to_wide(value_name);
+        let result = unsafe { RegDeleteKeyValueW(key, PCWSTR::null(), PCWSTR(value_wide.as_ptr())) };
+        unsafe {
+            _ = RegCloseKey(key);
+        }
+
+        if result != ERROR_SUCCESS && result != ERROR_FILE_NOT_FOUND {
+            debug!("{}[FAILED] (ERR = {})", log_message, result.0);
+            return Err(win32_error(format!("Failed to delete registry value {} -> {}", path, value_name), result));
+        }
+
+        debug!("{}[SUCCESS]", log_message);
+        Ok(())
+    }
+
+    fn delete_key(&mut self, path: &str) -> Result<(), DejunkerError> {
+        let log_message: String = format!("Deleting registry key: {}", path);
+        let (hive, sub_path) = get_path_components(path)?;
+
+        // an empty subpath would delete everything below the hive root
+        if sub_path.is_empty() {
+            return Err(DejunkerError::registry(format!("Refusing to delete the root of {}", hive)));
+        }
+        let Some(key) = open_key(path, KEY_ALL_ACCESS)? else {
+            debug!("{}[SUCCESS] (the key does not exist)", log_message);
+            return Ok(());
+        };
+
+        // RegDeleteTreeW cannot be told the view, so it empties the open key, and the key
+        // itself is deleted through the view it is in
+        let mut result = unsafe { RegDeleteTreeW(key, PCWSTR::null()) };
+        unsafe {
+            _ = RegCloseKey(key);
+        }
+        if result == ERROR_SUCCESS {
+            let (sub_path, view) = split_view(&sub_path);
+            let path_wide = to_wide(&sub_path);
+            result = unsafe { RegDeleteKeyExW(get_hkey(hive), PCWSTR(path_wide.as_ptr()), view.0, 0) };
+        }
+
+        if result != ERROR_SUCCESS && result != ERROR_FILE_NOT_FOUND {
+            debug!("{}[FAILED] (ERR = {})", log_message, result.0);
+            return Err(win32_error(format!("Failed to delete registry key {}", path), result));
+        }
+
+        debug!("{}[SUCCESS]", log_message);
+        Ok(())
+    }
+
+    fn enum_keys(&self, path: &str) -> Result<Vec<String>, DejunkerError> {
+        let Some(key) = open_key(path, KEY_READ)? else {
+            return Ok(Vec::new());
+        };
+
+        let mut names = Vec::new();
+        // key names are limited to 255 characters
+        let mut buffer: [u16; 256] = [0; 256];
+        for index in 0.. {
+            let mut length = buffer.len() as u32;
+            let result = unsafe {
+                RegEnumKeyExW(
+                    key,
+                    index,
+                    PWSTR(buffer.as_mut_ptr()),
+                    &mut length,
+                    None,
+                    PWSTR::null(),
+                    None,
+                    None,
+                )
+            };
+            if result == ERROR_NO_MORE_ITEMS {
+                break;
+            }
+            if result != ERROR_SUCCESS {
+                unsafe {
+                    _ = RegCloseKey(key);
+                }
+                return Err(win32_error(format!("Failed to enumerate registry key {}", path), result));
+            }
+            names.push(String::from_utf16_lossy(&buffer[..length as usize]));
+        }
+
+        unsafe {
+            _ = RegCloseKey(key);
+        }
+        Ok(names)
+    }
+
+    fn enum_values(&self, path: &str) -> Result<Vec<String>, DejunkerError> {
+        let Some(key) = open_key(path, KEY_READ)? else {
+            return Ok(Vec::new());
+        };
+
+        let mut names = Vec::new();
+        // value names are limited to 16383 characters
+        let mut buffer: Vec<u16> = vec![0; 16384];
+        for index in 0.. {
+            let mut length = buffer.len() as u32;
+            let result = unsafe {
+                RegEnumValueW(
+                    key,
+                    index,
+                    PWSTR(buffer.as_mut_ptr()),
+                    &mut length,
+                    None,
+                    None,
+                    None,
+                    None,
+                )
+            };
+            if result == ERROR_NO_MORE_ITEMS {
+                break;
+            }
+            if result != ERROR_SUCCESS {
+                unsafe {
+                    _ = RegCloseKey(key);
+                }
+                return Err(win32_error(format!("Failed to enumerate registry values of {}", path), result));
+            }
+            names.push(String::from_utf16_lossy(&buffer[..length as usize]));
+        }
+
+        unsafe {
+            _ = RegCloseKey(key);
+        }
+        Ok(names)
+    }
+
+    fn requires_elevation(&self) -> bool {
+        true
+    }
+}
+
+/// Open a key, through the view it is in. Returns None if the key does not exist. The caller
+/// must close the key.
+fn open_key(path: &str, access: REG_SAM_FLAGS) -> Result<Option<HKEY>, DejunkerError> {
+    let (hive, sub_path) = get_path_components(path)?;
+    let (sub_path, view) = split_view(&sub_path);
+    let path_wide = to_wide(&sub_path);
+
+    let mut key_handle: HKEY = HKEY::default();
+    let result = unsafe {
+        RegOpenKeyExW(
+            get_hkey(hive),
+            PCWSTR(path_wide.as_ptr()),
+            0,
+            access | view,
+            &mut key_handle,
+        )
+    };
+
+    if result == ERROR_FILE_NOT_FOUND {
+        return Ok(None);
+    }
+    if result != ERROR_SUCCESS {
+        debug!("Opening registry key {} [FAILED] (ERR = {})", path, result.0);
+        return Err(win32_error(format!("Failed to open registry key {}", path), result));
+    }
+    Ok(Some(key_handle))
+}
+
+/// The registry view a subpath is in (see registry::view_paths), and the subpath in that view:
+/// WOW6432Node paths are in the 32-bit view, without the WOW6432Node key, everything else is
+/// in the 64-bit view
+fn split_view(sub_path: &str) -> (String, REG_SAM_FLAGS) {
+    let keys: Vec<&str> = sub_path.split('\\').collect();
+    match keys.iter().position(|key| key.eq_ignore_ascii_case(WOW64_32_KEY)) {
+        Some(index) => {
+            let keys: Vec<&str> = [&keys[..index], &keys[index + 1..]].concat();
+            (keys.join("\\"), KEY_WOW64_32KEY)
+        }
+        None => (sub_path.to_string(), KEY_WOW64_64KEY),
+    }
+}
+
+/// Error for a failed registry API call
+fn win32_error(message: String, result: WIN32_ERROR) -> DejunkerError {
+    if result == ERROR_ACCESS_DENIED {
+        DejunkerError::AccessDenied(message)
+    } else {
+        DejunkerError::Registry {
+            message,
+            code: Some(result.0),
+        }
+    }
+}
+
+/// Map a hive to the predefined Win32 key handle
+fn get_hkey(hive: Hive) -> HKEY {
+    match hive {
+        Hive::LocalMachine => HKEY_LOCAL_MACHINE,
+        Hive::CurrentUser => HKEY_CURRENT_USER,
+        Hive::ClassesRoot => HKEY_CLASSES_ROOT,
+        Hive::Users => HKEY_USERS,
+        Hive::CurrentConfig => HKEY_CURRENT_CONFIG,
+    }
+}
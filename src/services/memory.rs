@@ -0,0 +1,44 @@
+use super::{ServiceManager, ServiceStatus, StartType};
+use crate::error::DejunkerError;
+use std::collections::BTreeMap;
+use std::result::Result;
+
+/// Services that only exist in memory, indexed by their lowercase name
+#[derive(Debug, Clone, Default)]
+pub struct MemoryServices {
+    services: BTreeMap<String, ServiceStatus>,
+}
+
+impl MemoryServices {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a service, or replace it if there is one with the same name
+    pub fn insert(&mut self, name: &str, start_type: StartType, running: bool) {
+        self.services
+            .insert(name.to_lowercase(), ServiceStatus { start_type, running });
+    }
+
+    fn service_mut(&mut self, name: &str) -> Result<&mut ServiceStatus, DejunkerError> {
+        self.services
+            .get_mut(&name.to_lowercase())
+            .ok_or_else(|| DejunkerError::system(format!("Service {} does not exist", name)))
+    }
+}
+
+impl ServiceManager for MemoryServices {
+    fn query(&self, name: &str) -> Result<Option<ServiceStatus>, DejunkerError> {
+        Ok(self.services.get(&name.to_lowercase()).copied())
+    }
+
+    fn set_start_type(&mut self, name: &str, start_type: StartType) -> Result<(), DejunkerError> {
+        self.service_mut(name)?.start_type = start_type;
+        Ok(())
+    }
+
+    fn stop(&mut self, name: &str) -> Result<(), DejunkerError> {
+        self.service_mut(name)?.running = false;
+        Ok(())
+    }
+}
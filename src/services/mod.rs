@@ -0,0 +1,224 @@
+use crate::error::DejunkerError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::result::Result;
+
+mod memory;
+pub mod recorder;
+#[cfg(windows)]
+mod win32;
+
+pub use memory::MemoryServices;
+#[cfg(windows)]
+pub use win32::Win32Services;
+
+/// When a service is started
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum StartType {
+    /// A driver loaded by the boot loader
+    Boot,
+    /// A driver loaded while the kernel starts
+    System,
+    /// Started with Windows
+    Automatic,
+    /// Started shortly after Windows (automatic, delayed start)
+    Delayed,
+    /// Started when a program or another service asks for it
+    Manual,
+    /// Cannot be started
+    Disabled,
+}
+
+impl StartType {
+    /// Parse a start type a service can be set to, as written in the rules database
+    /// (case insensitive). Boot and system are for drivers, they cannot be set.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "automatic" => Some(StartType::Automatic),
+            "delayed" => Some(StartType::Delayed),
+            "manual" => Some(StartType::Manual),
+            "disabled" => Some(StartType::Disabled),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StartType::Boot => "boot",
+            StartType::System => "system",
+            StartType::Automatic => "automatic",
+            StartType::Delayed => "delayed",
+            StartType::Manual => "manual",
+            StartType::Disabled => "disabled",
+        }
+    }
+
+    /// Whether services of this start type are started without anything asking for them
+    pub fn starts_with_windows(&self) -> bool {
+        !matches!(self, StartType::Manual | StartType::Disabled)
+    }
+}
+
+impl fmt::Display for StartType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// What the service control manager reports about a service
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ServiceStatus {
+    pub start_type: StartType,
+    /// Whether the service is running (or starting, or stopping)
+    pub running: bool,
+}
+
+/// Access to the services of a machine. Service names are the short names (e.g. DiagTrack,
+/// not "Connected User Experiences and Telemetry"), and are case insensitive.
+pub trait ServiceManager {
+    /// Query a service. Returns None if there is no such service.
+    fn query(&self, name: &str) -> Result<Option<ServiceStatus>, DejunkerError>;
+
+    /// Change the start type of a service. A running service keeps running.
+    fn set_start_type(&mut self, name: &str, start_type: StartType) -> Result<(), DejunkerError>;
+
+    /// Ask a running service to stop, without waiting for it to have stopped
+    fn stop(&mut self, name: &str) -> Result<(), DejunkerError>;
+}
+
+/// The services of the machine we are running on.
+#[cfg(windows)]
+pub fn local_services() -> Result<Box<dyn ServiceManager>, DejunkerError> {
+    Ok(Box::new(Win32Services::new()?))
+}
+
+/// The services of the machine we are running on. There are no Windows services outside of
+/// Windows.
+#[cfg(not(windows))]
+pub fn local_services() -> Result<Box<dyn ServiceManager>, DejunkerError> {
+    Err(DejunkerError::system("Services are only available on Windows"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::db::{Exec, ExecAction, Rule};
+    use crate::files::settings::execute_rule;
+    use crate::registry::MemoryRegistry;
+    use crate::subsystems::{Subsystems, SubsystemChange};
+    use crate::test_support::{rule, rules, Machine};
+    use crate::types::state::{EntryState, RuleState};
+    use std::collections::{BTreeMap, HashMap};
+
+    fn service_rule(name: &str, values: &[(&str, &str)], stop: bool) -> HashMap<String, Rule> {
+        let exec = Exec {
+            subsystem: "service".to_string(),
+            path: "DiagTrack".to_string(),
+            values: values
+                .iter()
+                .map(|(key, start_type)| (key.to_string(), ExecAction::Write(start_type.to_string())))
+                .collect::<BTreeMap<_, _>>(),
+            stop: Some(stop),
+            ..Exec::default()
+        };
+        rules([rule(name, "OnOff", vec![exec])])
+    }
+
+    #[test]
+    fn off_disables_and_stops_the_service() {
+        let rules = service_rule("telemetry", &[], true);
+        let mut machine = Machine::new();
+        machine.services.insert("DiagTrack", StartType::Automatic, true);
+        assert_eq!(machine.state(&rules, "telemetry"), RuleState::Value("on".to_string()));
+
+        machine.set(&rules, "telemetry", "off").unwrap();
+        let status = machine.services.query("diagtrack").unwrap().unwrap();
+        assert_eq!(status.start_type, StartType::Disabled);
+        assert!(!status.running);
+        assert_eq!(machine.state(&rules, "telemetry"), RuleState::Value("off".to_string()));
+
+        // turning it back on does not start it
+        machine.set(&rules, "telemetry", "on").unwrap();
+        let status = machine.services.query("DiagTrack").unwrap().unwrap();
+        assert_eq!(status.start_type, StartType::Automatic);
+        assert!(!status.running);
+    }
+
+    #[test]
+    fn running_services_keep_running_without_stop() {
+        let rules = service_rule("telemetry", &[("on", "delayed"), ("off", "manual")], false);
+        let mut machine = Machine::new();
+        machine.services.insert("DiagTrack", StartType::Delayed, true);
+        assert_eq!(machine.state(&rules, "telemetry"), RuleState::Value("on".to_string()));
+
+        machine.set(&rules, "telemetry", "off").unwrap();
+        let status = machine.services.query("DiagTrack").unwrap().unwrap();
+        assert_eq!(status.start_type, StartType::Manual);
+        assert!(status.running);
+        assert_eq!(machine.state(&rules, "telemetry"), RuleState::Value("off".to_string()));
+    }
+
+    #[test]
+    fn unmapped_start_types_are_unknown() {
+        let rules = service_rule("telemetry", &[("on", "automatic"), ("off", "disabled")], false);
+        let mut machine = Machine::new();
+        machine.services.insert("DiagTrack", StartType::Manual, false);
+        match machine.state(&rules, "telemetry") {
+            RuleState::Mixed(entries) => assert_eq!(entries[0].state, EntryState::Unknown("manual".to_string())),
+            other => panic!("unexpected state {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_services_are_off() {
+        let rules = service_rule("telemetry", &[], true);
+        let mut machine = Machine::new();
+        assert_eq!(machine.state(&rules, "telemetry"), RuleState::Value("off".to_string()));
+
+        // nothing to set, and no failure
+        machine.set(&rules, "telemetry", "on").unwrap();
+        machine.set(&rules, "telemetry", "off").unwrap();
+        assert_eq!(machine.services.query("DiagTrack").unwrap(), None);
+    }
+
+    #[test]
+    fn plans_leave_services_alone() {
+        let rules = service_rule("telemetry", &[], true);
+        let mut machine = Machine::new();
+        machine.services.insert("DiagTrack", StartType::Automatic, true);
+
+        let plan = machine.plan(&rules, "telemetry", "off");
+        assert_eq!(plan.current, Some(RuleState::Value("on".to_string())));
+        assert_eq!(
+            plan.subsystem_changes,
+            vec![
+                SubsystemChange::Service(recorder::ServiceChange::SetStartType {
+                    name: "DiagTrack".to_string(),
+                    previous: StartType::Automatic,
+                    start_type: StartType::Disabled,
+                }),
+                SubsystemChange::Service(recorder::ServiceChange::Stop {
+                    name: "DiagTrack".to_string()
+                }),
+            ]
+        );
+        assert_eq!(
+            machine.services.query("DiagTrack").unwrap(),
+            Some(ServiceStatus {
+                start_type: StartType::Automatic,
+                running: true
+            })
+        );
+    }
+
+    #[test]
+    fn rules_are_skipped_without_a_service_manager() {
+        let rules = service_rule("telemetry", &[], true);
+        let mut registry = MemoryRegistry::new();
+        execute_rule(&mut registry, &mut Subsystems::none(), &rules, "telemetry", "off", true).unwrap();
+        assert!(matches!(
+            execute_rule(&mut registry, &mut Subsystems::none(), &rules, "telemetry", "off", false),
+            Err(DejunkerError::AccessDenied(_))
+        ));
+    }
+}
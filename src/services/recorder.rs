@@ -0,0 +1,89 @@
+use super::{ServiceManager, ServiceStatus, StartType};
+use crate::error::DejunkerError;
+use std::fmt;
+use std::result::Result;
+
+/// A modification made to a service
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ServiceChange {
+    SetStartType {
+        name: String,
+        previous: StartType,
+        start_type: StartType,
+    },
+    Stop {
+        name: String,
+    },
+}
+
+impl fmt::Display for ServiceChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServiceChange::SetStartType {
+                name,
+                previous,
+                start_type,
+            } => write!(f, "service {}: {} -> {}", name, previous, start_type),
+            ServiceChange::Stop { name } => write!(f, "service {}: stopped", name),
+        }
+    }
+}
+
+/// Wraps another service manager, and keeps a record of every modification made through it.
+/// Queries go to the wrapped service manager. When `apply` is false, modifications are only
+/// recorded and the services are left alone.
+pub struct RecordingServices<'a> {
+    inner: &'a mut dyn ServiceManager,
+    apply: bool,
+    changes: Vec<ServiceChange>,
+}
+
+impl<'a> RecordingServices<'a> {
+    pub fn new(inner: &'a mut dyn ServiceManager, apply: bool) -> Self {
+        RecordingServices {
+            inner,
+            apply,
+            changes: Vec::new(),
+        }
+    }
+
+    /// All modifications, in the order they were made
+    pub fn changes(&self) -> &[ServiceChange] {
+        &self.changes
+    }
+
+    pub fn into_changes(self) -> Vec<ServiceChange> {
+        self.changes
+    }
+}
+
+impl ServiceManager for RecordingServices<'_> {
+    fn query(&self, name: &str) -> Result<Option<ServiceStatus>, DejunkerError> {
+        self.inner.query(name)
+    }
+
+    fn set_start_type(&mut self, name: &str, start_type: StartType) -> Result<(), DejunkerError> {
+        let previous = self
+            .inner
+            .query(name)?
+            .ok_or_else(|| DejunkerError::system(format!("Service {} does not exist", name)))?
+            .start_type;
+        if self.apply {
+            self.inner.set_start_type(name, start_type)?;
+        }
+        self.changes.push(ServiceChange::SetStartType {
+            name: name.to_string(),
+            previous,
+            start_type,
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self, name: &str) -> Result<(), DejunkerError> {
+        if self.apply {
+            self.inner.stop(name)?;
+        }
+        self.changes.push(ServiceChange::Stop { name: name.to_string() });
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+use super::{ServiceManager, ServiceStatus, StartType};
+use crate::error::DejunkerError;
+use crate::registry::read_sized;
+use crate::utils::{error_code, system_error, to_wide, win32_error};
+use log::debug;
+use std::result::Result;
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{
+    ERROR_INSUFFICIENT_BUFFER, ERROR_SERVICE_DOES_NOT_EXIST, ERROR_SERVICE_NOT_ACTIVE, ERROR_SUCCESS,
+};
+use windows::Win32::System::Services::{
+    ChangeServiceConfig2W, ChangeServiceConfigW, CloseServiceHandle, ControlService, OpenSCManagerW, OpenServiceW,
+    QueryServiceConfig2W, QueryServiceConfigW, QueryServiceStatus, ENUM_SERVICE_TYPE, QUERY_SERVICE_CONFIGW,
+    SC_HANDLE, SC_MANAGER_CONNECT, SERVICE_AUTO_START, SERVICE_BOOT_START, SERVICE_CHANGE_CONFIG,
+    SERVICE_CONFIG_DELAYED_AUTO_START_INFO, SERVICE_CONTROL_STOP, SERVICE_DELAYED_AUTO_START_INFO,
+    SERVICE_DEMAND_START, SERVICE_DISABLED, SERVICE_ERROR, SERVICE_NO_CHANGE, SERVICE_QUERY_CONFIG,
+    SERVICE_QUERY_STATUS, SERVICE_STATUS, SERVICE_STOP, SERVICE_STOPPED, SERVICE_SYSTEM_START,
+};
+
+/// The services of the machine we are running on, accessed through the service control
+/// manager. Changing services needs an elevated process.
+pub struct Win32Services {
+    manager: ServiceHandle,
+}
+
+/// A service control manager or service handle, closed when dropped
+struct ServiceHandle(SC_HANDLE);
+
+impl Drop for ServiceHandle {
+    fn drop(&mut self) {
+        unsafe {
+            _ = CloseServiceHandle(self.0);
+        }
+    }
+}
+
+impl Win32Services {
+    /// Connect to the service control manager
+    pub fn new() -> Result<Self, DejunkerError> {
+        let manager = unsafe { OpenSCManagerW(PCWSTR::null(), PCWSTR::null(), SC_MANAGER_CONNECT) }
+            .map_err(|e| system_error("Failed to connect to the service control manager".to_string(), &e))?;
+        Ok(Win32Services {
+            manager: ServiceHandle(manager),
+        })
+    }
+
+    /// Open a service. Returns None if there is no such service.
+    fn open(&self, name: &str, access: u32) -> Result<Option<ServiceHandle>, DejunkerError> {
+        let name_wide = to_wide(name);
+        match unsafe { OpenServiceW(self.manager.0, PCWSTR(name_wide.as_ptr()), access) } {
+            Ok(handle) => Ok(Some(ServiceHandle(handle))),
+            Err(e) if error_code(&e) == ERROR_SERVICE_DOES_NOT_EXIST => Ok(None),
+            Err(e) => Err(system_error(format!("Failed to open service {}", name), &e)),
+        }
+    }
+
+    /// Open a service that must exist
+    fn open_existing(&self, name: &str, access: u32) -> Result<ServiceHandle, DejunkerError> {
+        self.open(name, access)?
+            .ok_or_else(|| DejunkerError::system(format!("Service {} does not exist", name)))
+    }
+}
+
+impl ServiceManager for Win32Services {
+    fn query(&self, name: &str) -> Result<Option<ServiceStatus>, DejunkerError> {
+        let Some(service) = self.open(name, SERVICE_QUERY_CONFIG | SERVICE_QUERY_STATUS)? else {
+            return Ok(None);
+        };
+
+        // the configuration is followed by the strings it points to
+        let (result, buffer) = read_sized(ERROR_INSUFFICIENT_BUFFER, ERROR_SUCCESS, |data, length| {
+            let config = data.map(|data| data.as_mut_ptr() as *mut QUERY_SERVICE_CONFIGW);
+            let result = unsafe { QueryServiceConfigW(service.0, config, *length, length) };
+            result.map_or_else(|e| error_code(&e), |_| ERROR_SUCCESS)
+        });
+        if result != ERROR_SUCCESS || buffer.len() < std::mem::size_of::<QUERY_SERVICE_CONFIGW>() {
+            return Err(win32_error(format!("Failed to query service {}", name), result));
+        }
+        let config = unsafe { std::ptr::read_unaligned(buffer.as_ptr() as *const QUERY_SERVICE_CONFIGW) };
+
+        let start_type = match config.dwStartType {
+            SERVICE_BOOT_START => StartType::Boot,
+            SERVICE_SYSTEM_START => StartType::System,
+            SERVICE_AUTO_START if is_delayed(&service) => StartType::Delayed,
+            SERVICE_AUTO_START => StartType::Automatic,
+            SERVICE_DEMAND_START => StartType::Manual,
+            SERVICE_DISABLED => StartType::Disabled,
+            other => {
+                return Err(DejunkerError::system(format!(
+                    "Service {} has an unknown start type {}",
+                    name, other.0
+                )));
+            }
+        };
+
+        let mut status = SERVICE_STATUS::default();
+        unsafe { QueryServiceStatus(service.0, &mut status) }
+            .map_err(|e| system_error(format!("Failed to query the status of service {}", name), &e))?;
+
+        Ok(Some(ServiceStatus {
+            start_type,
+            running: status.dwCurrentState != SERVICE_STOPPED,
+        }))
+    }
+
+    fn set_start_type(&mut self, name: &str, start_type: StartType) -> Result<(), DejunkerError> {
+        let service = self.open_existing(name, SERVICE_CHANGE_CONFIG)?;
+        let native = match start_type {
+            StartType::Boot => SERVICE_BOOT_START,
+            StartType::System => SERVICE_SYSTEM_START,
+            StartType::Automatic | StartType::Delayed => SERVICE_AUTO_START,
+            StartType::Manual => SERVICE_DEMAND_START,
+            StartType::Disabled => SERVICE_DISABLED,
+        };
+
+        unsafe {
+            ChangeServiceConfigW(
+                service.0,
+                ENUM_SERVICE_TYPE(SERVICE_NO_CHANGE),
+                native,
+                SERVICE_ERROR(SERVICE_NO_CHANGE),
+                PCWSTR::null(),
+                PCWSTR::null(),
+                None,
+                PCWSTR::null(),
+                PCWSTR::null(),
+                PCWSTR::null(),
+                PCWSTR::null(),
+            )
+        }
+        .map_err(|e| system_error(format!("Failed to set the start type of service {}", name), &e))?;
+
+        // delayed is a flag on top of automatic
+        if native == SERVICE_AUTO_START {
+            let info = SERVICE_DELAYED_AUTO_START_INFO {
+                fDelayedAutostart: (start_type == StartType::Delayed).into(),
+            };
+            unsafe {
+                ChangeServiceConfig2W(
+                    service.0,
+                    SERVICE_CONFIG_DELAYED_AUTO_START_INFO,
+                    Some(&info as *const _ as *const std::ffi::c_void),
+                )
+            }
+            .map_err(|e| system_error(format!("Failed to set the start type of service {}", name), &e))?;
+        }
+        debug!("Set the start type of service {} to {}", name, start_type);
+        Ok(())
+    }
+
+    fn stop(&mut self, name: &str) -> Result<(), DejunkerError> {
+        let service = self.open_existing(name, SERVICE_STOP)?;
+        let mut status = SERVICE_STATUS::default();
+        match unsafe { ControlService(service.0, SERVICE_CONTROL_STOP, &mut status) } {
+            Ok(()) => {
+                debug!("Asked service {} to stop", name);
+                Ok(())
+            }
+            // stopped in the meantime
+            Err(e) if error_code(&e) == ERROR_SERVICE_NOT_ACTIVE => Ok(()),
+            Err(e) => Err(system_error(format!("Failed to stop service {}", name), &e)),
+        }
+    }
+}
+
+/// Whether an automatic service has its start delayed
+fn is_delayed(service: &ServiceHandle) -> bool {
+    let mut info = SERVICE_DELAYED_AUTO_START_INFO::default();
+    let mut length = 0;
+    let buffer = unsafe {
+        std::slice::from_raw_parts_mut(
+            &mut info as *mut _ as *mut u8,
+            std::mem::size_of::<SERVICE_DELAYED_AUTO_START_INFO>(),
+        )
+    };
+    let result = unsafe {
+        QueryServiceConfig2W(service.0, SERVICE_CONFIG_DELAYED_AUTO_START_INFO, Some(buffer), &mut length)
+    };
+    result.is_ok() && info.fDelayedAutostart.as_bool()
+}
@@ -0,0 +1,153 @@
+use std::fmt;
+
+use crate::files::db::Exec;
+use crate::firewall::recorder::{FirewallChange, RecordingFirewall};
+use crate::firewall::Firewall;
+use crate::hosts::recorder::{HostsChange, RecordingHosts};
+use crate::hosts::HostsFile;
+use crate::packages::recorder::{PackageChange, RecordingPackages};
+use crate::packages::{PackageManager, PackageScope};
+use crate::registry::RegistryBackend;
+use crate::services::recorder::{RecordingServices, ServiceChange};
+use crate::services::ServiceManager;
+use crate::tasks::recorder::{RecordingTasks, TaskChange};
+use crate::tasks::TaskScheduler;
+
+/// The backends of the subsystems rules work on besides the registry. A subsystem without a
+/// backend cannot be reached (e.g. when working on offline hives, or exporting), and rules
+/// using it are skipped like rules for registry paths outside of the mounted hives.
+#[derive(Default)]
+pub struct Subsystems<'a> {
+    /// For exec entries of the service subsystem
+    pub services: Option<&'a mut dyn ServiceManager>,
+    /// For exec entries of the scheduled_task subsystem
+    pub tasks: Option<&'a mut dyn TaskScheduler>,
+    /// For exec entries of the appx subsystem
+    pub packages: Option<&'a mut dyn PackageManager>,
+    /// For exec entries of the hosts subsystem
+    pub hosts: Option<&'a mut dyn HostsFile>,
+    /// For exec entries of the firewall subsystem
+    pub firewall: Option<&'a mut dyn Firewall>,
+    /// Whether HKEY_CURRENT_USER stands for another user than the one we are running as (see
+    /// users::for_each_user). The packages of the current user are not those of that user,
+    /// so appx entries for them cannot be reached.
+    pub other_user: bool,
+    /// Whether only the entries of HKEY_CURRENT_USER are applied, when the machine-wide ones
+    /// (other hives and every other subsystem) were already applied for an earlier user
+    pub user_entries_only: bool,
+}
+
+impl Subsystems<'_> {
+    /// No subsystem besides the registry
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Why an exec entry cannot be reached, None if it can
+    ///
+    /// * registry: the registry for exec entries of the registry subsystem
+    /// * op: the exec entry
+    ///
+    pub fn unreachable(&self, registry: &dyn RegistryBackend, op: &Exec) -> Option<String> {
+        match op.subsystem.as_str() {
+            "registry" if !registry.is_accessible(&op.path) => Some(format!("{} is not accessible", op.path)),
+            "service" if self.services.is_none() => Some(format!("service {} is not accessible", op.path)),
+            "scheduled_task" if self.tasks.is_none() => Some(format!("task {} is not accessible", op.path)),
+            "appx" if self.packages.is_none() => Some(format!("package {} is not accessible", op.path)),
+            "appx" if self.other_user && op.scope != Some(PackageScope::Provisioned) => {
+                Some(format!("package {} can only be removed for the user running dejunker", op.path))
+            }
+            "hosts" if self.hosts.is_none() => Some("the hosts file is not accessible".to_string()),
+            "firewall" if self.firewall.is_none() => Some(format!("firewall rule {} is not accessible", op.path)),
+            _ => None,
+        }
+    }
+}
+
+/// A modification made through a subsystem other than the registry
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SubsystemChange {
+    Service(ServiceChange),
+    Task(TaskChange),
+    Package(PackageChange),
+    Hosts(HostsChange),
+    Firewall(FirewallChange),
+}
+
+impl fmt::Display for SubsystemChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubsystemChange::Service(change) => write!(f, "{}", change),
+            SubsystemChange::Task(change) => write!(f, "{}", change),
+            SubsystemChange::Package(change) => write!(f, "{}", change),
+            SubsystemChange::Hosts(change) => write!(f, "{}", change),
+            SubsystemChange::Firewall(change) => write!(f, "{}", change),
+        }
+    }
+}
+
+/// Wraps the backends of other subsystems, and keeps a record of every modification made
+/// through them, like RecordingRegistry does for the registry. When `apply` is false,
+/// modifications are only recorded.
+pub struct RecordingSubsystems<'a> {
+    services: Option<RecordingServices<'a>>,
+    tasks: Option<RecordingTasks<'a>>,
+    packages: Option<RecordingPackages<'a>>,
+    hosts: Option<RecordingHosts<'a>>,
+    firewall: Option<RecordingFirewall<'a>>,
+    other_user: bool,
+    user_entries_only: bool,
+}
+
+impl<'a> RecordingSubsystems<'a> {
+    pub fn new(inner: &'a mut Subsystems, apply: bool) -> Self {
+        RecordingSubsystems {
+            services: inner
+                .services
+                .as_deref_mut()
+                .map(|services| RecordingServices::new(services, apply)),
+            tasks: inner.tasks.as_deref_mut().map(|tasks| RecordingTasks::new(tasks, apply)),
+            packages: inner
+                .packages
+                .as_deref_mut()
+                .map(|packages| RecordingPackages::new(packages, apply)),
+            hosts: inner.hosts.as_deref_mut().map(|hosts| RecordingHosts::new(hosts, apply)),
+            firewall: inner
+                .firewall
+                .as_deref_mut()
+                .map(|firewall| RecordingFirewall::new(firewall, apply)),
+            other_user: inner.other_user,
+            user_entries_only: inner.user_entries_only,
+        }
+    }
+
+    /// The recording backends, to execute rules with
+    pub fn subsystems(&mut self) -> Subsystems<'_> {
+        Subsystems {
+            services: self.services.as_mut().map(|services| services as &mut dyn ServiceManager),
+            tasks: self.tasks.as_mut().map(|tasks| tasks as &mut dyn TaskScheduler),
+            packages: self.packages.as_mut().map(|packages| packages as &mut dyn PackageManager),
+            hosts: self.hosts.as_mut().map(|hosts| hosts as &mut dyn HostsFile),
+            firewall: self.firewall.as_mut().map(|firewall| firewall as &mut dyn Firewall),
+            other_user: self.other_user,
+            user_entries_only: self.user_entries_only,
+        }
+    }
+
+    /// All modifications, by subsystem, each in the order they were made
+    pub fn into_changes(self) -> Vec<SubsystemChange> {
+        let services = self.services.map(RecordingServices::into_changes).unwrap_or_default();
+        let tasks = self.tasks.map(RecordingTasks::into_changes).unwrap_or_default();
+        let packages = self.packages.map(RecordingPackages::into_changes).unwrap_or_default();
+        let hosts = self.hosts.map(RecordingHosts::into_changes).unwrap_or_default();
+        let firewall = self.firewall.map(RecordingFirewall::into_changes).unwrap_or_default();
+        services
+            .into_iter()
+            .map(SubsystemChange::Service)
+            .chain(tasks.into_iter().map(SubsystemChange::Task))
+            .chain(packages.into_iter().map(SubsystemChange::Package))
+            .chain(hosts.into_iter().map(SubsystemChange::Hosts))
+            .chain(firewall.into_iter().map(SubsystemChange::Firewall))
+            .collect()
+    }
+}
@@ -0,0 +1,48 @@
+use super::{split_task_path, ScheduledTask, TaskScheduler};
+use crate::error::DejunkerError;
+use std::collections::BTreeMap;
+use std::result::Result;
+
+/// Scheduled tasks that only exist in memory, indexed by their lowercase path
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTasks {
+    tasks: BTreeMap<String, ScheduledTask>,
+}
+
+impl MemoryTasks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a task, or replace it if there is one with the same path
+    pub fn insert(&mut self, path: &str, enabled: bool) {
+        self.tasks.insert(
+            path.to_lowercase(),
+            ScheduledTask {
+                path: path.to_string(),
+                enabled,
+            },
+        );
+    }
+}
+
+impl TaskScheduler for MemoryTasks {
+    fn tasks(&self, folder: &str) -> Result<Vec<ScheduledTask>, DejunkerError> {
+        let folder = folder.trim_end_matches('\\');
+        Ok(self
+            .tasks
+            .values()
+            .filter(|task| split_task_path(&task.path).0.trim_end_matches('\\').eq_ignore_ascii_case(folder))
+            .cloned()
+            .collect())
+    }
+
+    fn set_enabled(&mut self, path: &str, enabled: bool) -> Result<(), DejunkerError> {
+        let task = self
+            .tasks
+            .get_mut(&path.to_lowercase())
+            .ok_or_else(|| DejunkerError::system(format!("Scheduled task {} does not exist", path)))?;
+        task.enabled = enabled;
+        Ok(())
+    }
+}
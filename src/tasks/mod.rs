@@ -0,0 +1,199 @@
+use crate::error::DejunkerError;
+use crate::utils::wildcard_pattern;
+use std::result::Result;
+
+mod memory;
+pub mod recorder;
+#[cfg(windows)]
+mod win32;
+
+pub use memory::MemoryTasks;
+#[cfg(windows)]
+pub use win32::Win32Tasks;
+
+/// The data of task entries, as written in the rules database
+pub const ENABLED: &str = "enabled";
+pub const DISABLED: &str = "disabled";
+
+/// A task registered with the task scheduler
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ScheduledTask {
+    /// The full path of the task, e.g. \Microsoft\Windows\Autochk\Proxy
+    pub path: String,
+    pub enabled: bool,
+}
+
+/// Access to the scheduled tasks of a machine. Paths start at the root folder (\), and are
+/// case insensitive.
+pub trait TaskScheduler {
+    /// The tasks of a folder, hidden ones included (not those of its subfolders). A folder that
+    /// does not exist has no tasks.
+    fn tasks(&self, folder: &str) -> Result<Vec<ScheduledTask>, DejunkerError>;
+
+    /// Enable or disable a task
+    fn set_enabled(&mut self, path: &str, enabled: bool) -> Result<(), DejunkerError>;
+}
+
+/// Parse whether a task is enabled, as written in the rules database (enabled or disabled)
+pub fn parse_enabled(name: &str) -> Option<bool> {
+    match name.to_lowercase().as_str() {
+        ENABLED => Some(true),
+        DISABLED => Some(false),
+        _ => None,
+    }
+}
+
+/// The name of an enabled state, as written in the rules database
+pub fn enabled_name(enabled: bool) -> &'static str {
+    if enabled { ENABLED } else { DISABLED }
+}
+
+/// Split a task path into its folder and name, e.g. \Microsoft\Windows\Autochk\Proxy is
+/// (\Microsoft\Windows\Autochk, Proxy). The folder of tasks at the root is \.
+pub fn split_task_path(path: &str) -> (&str, &str) {
+    match path.rfind('\\') {
+        Some(0) | None => ("\\", path.trim_start_matches('\\')),
+        Some(index) => (&path[..index], &path[index + 1..]),
+    }
+}
+
+/// Find the tasks a path matches. The name of the task (not its folder) can have wildcards:
+/// `*` for any number of characters and `?` for a single one, e.g. `\Microsoft\Windows\Feedback\Siuf\*`
+/// matches every task of that folder.
+///
+/// * scheduler: the task scheduler to look in
+/// * pattern: the path of the tasks
+///
+pub fn find_tasks(scheduler: &dyn TaskScheduler, pattern: &str) -> Result<Vec<ScheduledTask>, DejunkerError> {
+    let (folder, name) = split_task_path(pattern);
+    let name = wildcard_pattern(name);
+    let mut tasks: Vec<ScheduledTask> = scheduler
+        .tasks(folder)?
+        .into_iter()
+        .filter(|task| name.is_match(split_task_path(&task.path).1))
+        .collect();
+    tasks.sort_by_key(|task| task.path.to_lowercase());
+    Ok(tasks)
+}
+
+/// The task scheduler of the machine we are running on.
+#[cfg(windows)]
+pub fn local_tasks() -> Result<Box<dyn TaskScheduler>, DejunkerError> {
+    Ok(Box::new(Win32Tasks::new()?))
+}
+
+/// The task scheduler of the machine we are running on. There is no task scheduler outside of
+/// Windows.
+#[cfg(not(windows))]
+pub fn local_tasks() -> Result<Box<dyn TaskScheduler>, DejunkerError> {
+    Err(DejunkerError::system("Scheduled tasks are only available on Windows"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::db::{Exec, Rule};
+    use crate::test_support::{rule, rules, Machine};
+    use crate::types::state::{EntryState, RuleState};
+    use std::collections::HashMap;
+
+    const CEIP: &str = "\\Microsoft\\Windows\\Customer Experience Improvement Program";
+
+    fn task_rule(path: &str) -> HashMap<String, Rule> {
+        let exec = Exec {
+            subsystem: "scheduled_task".to_string(),
+            path: path.to_string(),
+            ..Exec::default()
+        };
+        rules([rule("ceip", "OnOff", vec![exec])])
+    }
+
+    fn ceip_machine() -> Machine {
+        let mut machine = Machine::new();
+        machine.tasks.insert(&format!("{}\\Consolidator", CEIP), true);
+        machine.tasks.insert(&format!("{}\\UsbCeip", CEIP), true);
+        machine.tasks.insert(&format!("{}\\Subfolder\\Other", CEIP), true);
+        machine.tasks.insert("\\Microsoft\\Windows\\Autochk\\Proxy", true);
+        machine
+    }
+
+    fn enabled(tasks: &MemoryTasks, path: &str) -> bool {
+        let (folder, _) = split_task_path(path);
+        tasks.tasks(folder).unwrap().iter().any(|task| task.path == path && task.enabled)
+    }
+
+    #[test]
+    fn task_paths_are_split_at_the_last_folder() {
+        assert_eq!(split_task_path("\\Microsoft\\Windows\\Autochk\\Proxy"), ("\\Microsoft\\Windows\\Autochk", "Proxy"));
+        assert_eq!(split_task_path("\\Task"), ("\\", "Task"));
+    }
+
+    #[test]
+    fn wildcards_match_the_tasks_of_a_folder() {
+        let tasks = ceip_machine().tasks;
+        let names = |pattern: &str| -> Vec<String> {
+            find_tasks(&tasks, pattern).unwrap().into_iter().map(|task| task.path).collect()
+        };
+
+        assert_eq!(
+            names(&format!("{}\\*", CEIP)),
+            vec![format!("{}\\Consolidator", CEIP), format!("{}\\UsbCeip", CEIP)]
+        );
+        assert_eq!(names(&format!("{}\\usb?eip", CEIP)), vec![format!("{}\\UsbCeip", CEIP)]);
+        assert_eq!(names(&format!("{}\\Consolidator", CEIP.to_lowercase())), vec![format!("{}\\Consolidator", CEIP)]);
+        assert!(names("\\Microsoft\\Windows\\Missing\\*").is_empty());
+    }
+
+    #[test]
+    fn off_disables_every_matching_task() {
+        let rules = task_rule(&format!("{}\\*", CEIP));
+        let mut machine = ceip_machine();
+        assert_eq!(machine.state(&rules, "ceip"), RuleState::Value("on".to_string()));
+
+        machine.set(&rules, "ceip", "off").unwrap();
+        assert!(!enabled(&machine.tasks, &format!("{}\\Consolidator", CEIP)));
+        assert!(!enabled(&machine.tasks, &format!("{}\\UsbCeip", CEIP)));
+        // subfolders are not matched
+        assert!(enabled(&machine.tasks, &format!("{}\\Subfolder\\Other", CEIP)));
+        assert_eq!(machine.state(&rules, "ceip"), RuleState::Value("off".to_string()));
+    }
+
+    #[test]
+    fn tasks_in_different_states_are_mixed() {
+        let rules = task_rule(&format!("{}\\*", CEIP));
+        let mut machine = ceip_machine();
+        machine.tasks.set_enabled(&format!("{}\\UsbCeip", CEIP), false).unwrap();
+
+        match machine.state(&rules, "ceip") {
+            RuleState::Mixed(entries) => {
+                let states: Vec<&EntryState> = entries.iter().map(|entry| &entry.state).collect();
+                assert_eq!(
+                    states,
+                    vec![&EntryState::Value("on".to_string()), &EntryState::Value("off".to_string())]
+                );
+            }
+            other => panic!("unexpected state {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_tasks_are_off() {
+        let rules = task_rule("\\Microsoft\\Windows\\Missing\\*");
+        let mut machine = ceip_machine();
+        assert_eq!(machine.state(&rules, "ceip"), RuleState::Value("off".to_string()));
+        machine.set(&rules, "ceip", "on").unwrap();
+    }
+
+    #[test]
+    fn plans_leave_tasks_alone() {
+        let rules = task_rule(&format!("{}\\Consolidator", CEIP));
+        let mut machine = ceip_machine();
+        let plan = machine.plan(&rules, "ceip", "off");
+        assert_eq!(plan.subsystem_changes.len(), 1);
+        assert_eq!(
+            plan.subsystem_changes[0].to_string(),
+            format!("task {}\\Consolidator: enabled -> disabled", CEIP)
+        );
+        assert!(enabled(&machine.tasks, &format!("{}\\Consolidator", CEIP)));
+    }
+}
@@ -0,0 +1,86 @@
+use super::{ScheduledTask, TaskScheduler};
+use crate::error::DejunkerError;
+use std::fmt;
+use std::result::Result;
+
+/// A modification made to a scheduled task
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TaskChange {
+    SetEnabled {
+        path: String,
+        previous: bool,
+        enabled: bool,
+    },
+}
+
+impl fmt::Display for TaskChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TaskChange::SetEnabled {
+                path,
+                previous,
+                enabled,
+            } => write!(
+                f,
+                "task {}: {} -> {}",
+                path,
+                super::enabled_name(*previous),
+                super::enabled_name(*enabled)
+            ),
+        }
+    }
+}
+
+/// Wraps another task scheduler, and keeps a record of every modification made through it.
+/// Queries go to the wrapped task scheduler. When `apply` is false, modifications are only
+/// recorded and the tasks are left alone.
+pub struct RecordingTasks<'a> {
+    inner: &'a mut dyn TaskScheduler,
+    apply: bool,
+    changes: Vec<TaskChange>,
+}
+
+impl<'a> RecordingTasks<'a> {
+    pub fn new(inner: &'a mut dyn TaskScheduler, apply: bool) -> Self {
+        RecordingTasks {
+            inner,
+            apply,
+            changes: Vec::new(),
+        }
+    }
+
+    /// All modifications, in the order they were made
+    pub fn changes(&self) -> &[TaskChange] {
+        &self.changes
+    }
+
+    pub fn into_changes(self) -> Vec<TaskChange> {
+        self.changes
+    }
+}
+
+impl TaskScheduler for RecordingTasks<'_> {
+    fn tasks(&self, folder: &str) -> Result<Vec<ScheduledTask>, DejunkerError> {
+        self.inner.tasks(folder)
+    }
+
+    fn set_enabled(&mut self, path: &str, enabled: bool) -> Result<(), DejunkerError> {
+        let (folder, _) = super::split_task_path(path);
+        let previous = self
+            .inner
+            .tasks(folder)?
+            .into_iter()
+            .find(|task| task.path.eq_ignore_ascii_case(path))
+            .ok_or_else(|| DejunkerError::system(format!("Task {} does not exist", path)))?
+            .enabled;
+        if self.apply {
+            self.inner.set_enabled(path, enabled)?;
+        }
+        self.changes.push(TaskChange::SetEnabled {
+            path: path.to_string(),
+            previous,
+            enabled,
+        });
+        Ok(())
+    }
+}
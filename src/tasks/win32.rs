@@ -0,0 +1,82 @@
+use super::{split_task_path, ScheduledTask, TaskScheduler};
+use crate::error::DejunkerError;
+use crate::utils::{error_code, system_error};
+use log::debug;
+use std::result::Result;
+use windows::core::{BSTR, VARIANT};
+use windows::Win32::Foundation::{ERROR_FILE_NOT_FOUND, ERROR_PATH_NOT_FOUND, VARIANT_BOOL};
+use windows::Win32::System::Com::{CoCreateInstance, CoInitializeEx, CLSCTX_INPROC_SERVER, COINIT_MULTITHREADED};
+use windows::Win32::System::TaskScheduler::{
+    ITaskFolder, ITaskService, TaskScheduler as TaskSchedulerClass, TASK_ENUM_HIDDEN,
+};
+
+/// The task scheduler of the machine we are running on, accessed through the Task Scheduler
+/// 2.0 COM API. Changing Microsoft tasks needs an elevated process.
+pub struct Win32Tasks {
+    service: ITaskService,
+}
+
+impl Win32Tasks {
+    /// Connect to the task scheduler
+    pub fn new() -> Result<Self, DejunkerError> {
+        unsafe {
+            // fails harmlessly if COM was already initialized on this thread
+            _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+            let service: ITaskService = CoCreateInstance(&TaskSchedulerClass, None, CLSCTX_INPROC_SERVER)
+                .map_err(|e| system_error("Failed to create the task scheduler".to_string(), &e))?;
+            let local = VARIANT::default();
+            service
+                .Connect(&local, &local, &local, &local)
+                .map_err(|e| system_error("Failed to connect to the task scheduler".to_string(), &e))?;
+            Ok(Win32Tasks { service })
+        }
+    }
+
+    /// Open a folder. Returns None if there is no such folder.
+    fn folder(&self, path: &str) -> Result<Option<ITaskFolder>, DejunkerError> {
+        match unsafe { self.service.GetFolder(&BSTR::from(path)) } {
+            Ok(folder) => Ok(Some(folder)),
+            Err(e) if [ERROR_FILE_NOT_FOUND, ERROR_PATH_NOT_FOUND].contains(&error_code(&e)) => Ok(None),
+            Err(e) => Err(system_error(format!("Failed to open task folder {}", path), &e)),
+        }
+    }
+}
+
+impl TaskScheduler for Win32Tasks {
+    fn tasks(&self, folder: &str) -> Result<Vec<ScheduledTask>, DejunkerError> {
+        let Some(task_folder) = self.folder(folder)? else {
+            return Ok(Vec::new());
+        };
+        let failed = |e: windows::core::Error| system_error(format!("Failed to list the tasks of {}", folder), &e);
+
+        let mut tasks = Vec::new();
+        unsafe {
+            let collection = task_folder.GetTasks(TASK_ENUM_HIDDEN.0).map_err(failed)?;
+            // the items of COM collections are numbered from 1
+            for index in 1..=collection.Count().map_err(failed)? {
+                let task = collection.get_Item(&VARIANT::from(index)).map_err(failed)?;
+                tasks.push(ScheduledTask {
+                    path: task.Path().map_err(failed)?.to_string(),
+                    enabled: task.Enabled().map_err(failed)?.as_bool(),
+                });
+            }
+        }
+        Ok(tasks)
+    }
+
+    fn set_enabled(&mut self, path: &str, enabled: bool) -> Result<(), DejunkerError> {
+        let (folder, name) = split_task_path(path);
+        let task_folder = self
+            .folder(folder)?
+            .ok_or_else(|| DejunkerError::system(format!("Scheduled task {} does not exist", path)))?;
+        unsafe {
+            let task = task_folder
+                .GetTask(&BSTR::from(name))
+                .map_err(|e| system_error(format!("Failed to open scheduled task {}", path), &e))?;
+            task.SetEnabled(VARIANT_BOOL::from(enabled))
+                .map_err(|e| system_error(format!("Failed to change scheduled task {}", path), &e))?;
+        }
+        debug!("Set scheduled task {} to {}", path, super::enabled_name(enabled));
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+//! Fixtures shared by the unit tests: rules built in code, and a machine made of the memory
+//! backends of every subsystem to evaluate, execute and plan them against.
+
+use std::collections::HashMap;
+
+use crate::error::DejunkerError;
+use crate::files::db::{Exec, Rule, Value};
+use crate::files::settings::{evaluate_rule, execute_rule};
+use crate::firewall::MemoryFirewall;
+use crate::hosts::MemoryHosts;
+use crate::packages::MemoryPackages;
+use crate::plan::{plan_rule, RulePlan};
+use crate::registry::MemoryRegistry;
+use crate::services::MemoryServices;
+use crate::subsystems::Subsystems;
+use crate::tasks::MemoryTasks;
+use crate::types::state::RuleState;
+
+/// A rule of a value type (e.g. OnOff) running exec entries, named (and identified) `name`
+pub fn rule(name: &str, value_type: &str, exec: Vec<Exec>) -> Rule {
+    Rule {
+        id: name.to_string(),
+        name: name.to_string(),
+        description: String::new(),
+        admin_required: false,
+        value: Value {
+            value_type: value_type.to_string(),
+            ..Value::default()
+        },
+        exec,
+    }
+}
+
+/// Rules by name, as read from a database
+pub fn rules(rules: impl IntoIterator<Item = Rule>) -> HashMap<String, Rule> {
+    rules.into_iter().map(|rule| (rule.name.clone(), rule)).collect()
+}
+
+/// An exec entry of the registry subsystem, for a value of a key
+pub fn registry_exec(path: &str, value: &str, value_type: &str) -> Exec {
+    Exec {
+        subsystem: "registry".to_string(),
+        path: path.to_string(),
+        value: value.to_string(),
+        value_type: value_type.to_string(),
+        ..Exec::default()
+    }
+}
+
+/// A registry and the other subsystems, all in memory and empty to begin with
+pub struct Machine {
+    pub registry: MemoryRegistry,
+    pub services: MemoryServices,
+    pub tasks: MemoryTasks,
+    pub packages: MemoryPackages,
+    pub hosts: MemoryHosts,
+    pub firewall: MemoryFirewall,
+    /// See Subsystems::other_user
+    pub other_user: bool,
+    /// See Subsystems::user_entries_only
+    pub user_entries_only: bool,
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        Machine {
+            registry: MemoryRegistry::new(),
+            services: MemoryServices::new(),
+            tasks: MemoryTasks::new(),
+            packages: MemoryPackages::new(),
+            hosts: MemoryHosts::new(""),
+            firewall: MemoryFirewall::new(),
+            other_user: false,
+            user_entries_only: false,
+        }
+    }
+
+    /// The registry, and every other subsystem
+    pub fn split(&mut self) -> (&mut MemoryRegistry, Subsystems<'_>) {
+        let subsystems = Subsystems {
+            services: Some(&mut self.services),
+            tasks: Some(&mut self.tasks),
+            packages: Some(&mut self.packages),
+            hosts: Some(&mut self.hosts),
+            firewall: Some(&mut self.firewall),
+            other_user: self.other_user,
+            user_entries_only: self.user_entries_only,
+        };
+        (&mut self.registry, subsystems)
+    }
+
+    /// The state of a rule
+    pub fn state(&mut self, rules: &HashMap<String, Rule>, name: &str) -> RuleState {
+        let (registry, subsystems) = self.split();
+        evaluate_rule(registry, &subsystems, &rules[name]).unwrap()
+    }
+
+    /// Set a rule to a value
+    pub fn set(&mut self, rules: &HashMap<String, Rule>, name: &str, value: &str) -> Result<(), DejunkerError> {
+        let (registry, mut subsystems) = self.split();
+        execute_rule(registry, &mut subsystems, rules, name, value, false)
+    }
+
+    /// What setting a rule to a value would do
+    pub fn plan(&mut self, rules: &HashMap<String, Rule>, name: &str, value: &str) -> RulePlan {
+        let (registry, mut subsystems) = self.split();
+        plan_rule(registry, &mut subsystems, rules, name, value, false).unwrap()
+    }
+}
@@ -0,0 +1,79 @@
+// the state a rule is found in when reading the registry
+
+use std::fmt;
+
+pub const MIXED: &str = "mixed";
+pub const NOT_CONFIGURED: &str = "not-configured";
+
+/// The state of one exec entry of a rule
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ExecState {
+    pub path: String,
+    pub value: String,
+    pub state: EntryState,
+}
+
+/// What the registry value of an exec entry currently holds
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum EntryState {
+    /// Data that one of the values of the rule maps to
+    Value(String),
+    /// The value does not exist
+    NotConfigured,
+    /// Data that none of the values of the rule maps to
+    Unknown(String),
+}
+
+/// The state of a rule, as evaluated from all its exec entries
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RuleState {
+    /// All entries are set to the same value of the rule (e.g. on, or one of its choices)
+    Value(String),
+    /// The entries disagree (or only some of them are configured), or hold data the rule
+    /// does not know
+    Mixed(Vec<ExecState>),
+    /// None of the entries is configured
+    NotConfigured,
+}
+
+impl RuleState {
+    /// Combine the states of the exec entries of a rule
+    pub fn from_entries(entries: Vec<ExecState>) -> Self {
+        let first = match entries.first() {
+            Some(entry) => &entry.state,
+            None => return RuleState::NotConfigured,
+        };
+
+        match first {
+            _ if entries.iter().any(|entry| &entry.state != first) => RuleState::Mixed(entries),
+            EntryState::Value(value) => RuleState::Value(value.clone()),
+            EntryState::NotConfigured => RuleState::NotConfigured,
+            EntryState::Unknown(_) => RuleState::Mixed(entries),
+        }
+    }
+
+    /// Whether a value (e.g. from a settings file) only reports a state, and cannot be applied
+    pub fn is_report_only(value: &str) -> bool {
+        value == MIXED || value == NOT_CONFIGURED
+    }
+}
+
+impl fmt::Display for RuleState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleState::Value(value) => write!(f, "{}", value),
+            RuleState::Mixed(_) => write!(f, "{}", MIXED),
+            RuleState::NotConfigured => write!(f, "{}", NOT_CONFIGURED),
+        }
+    }
+}
+
+impl fmt::Display for ExecState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.state {
+            EntryState::Value(state) => write!(f, "{} -> {}: {}", self.path, self.value, state),
+            EntryState::NotConfigured => write!(f, "{} -> {}: {}", self.path, self.value, NOT_CONFIGURED),
+            EntryState::Unknown(data) => write!(f, "{} -> {}: unknown ({})", self.path, self.value, data),
+        }
+    }
+}
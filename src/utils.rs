@@ -1,14 +1,23 @@
+#[cfg(windows)]
 use windows::Win32::Foundation::{HANDLE, CloseHandle};
+#[cfg(windows)]
+use windows::core::PCWSTR;
+#[cfg(windows)]
+use windows::Win32::Foundation::{ERROR_ACCESS_DENIED, LUID, WIN32_ERROR};
+#[cfg(windows)]
+use crate::error::DejunkerError;
+#[cfg(windows)]
+use windows::Win32::Security::{
+    AdjustTokenPrivileges, LookupPrivilegeValueW, LUID_AND_ATTRIBUTES, SE_PRIVILEGE_ENABLED, TOKEN_ADJUST_PRIVILEGES,
+    TOKEN_PRIVILEGES,
+};
+#[cfg(windows)]
 use windows::Win32::Security::{TOKEN_ELEVATION, TOKEN_QUERY, TokenElevation, GetTokenInformation};
+#[cfg(windows)]
 use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
 
-/// Test if running on big endian
-pub fn is_big_endian() -> bool {
-    cfg!(target_endian = "big")
-}
-
-
 /// test if running elevated
+#[cfg(windows)]
 pub fn is_elevated() -> bool {
     unsafe {
         let mut token_handle: HANDLE = HANDLE::default();
@@ -31,3 +40,90 @@ pub fn is_elevated() -> bool {
     }
     false
 }
+
+/// Enable a privilege the process holds (e.g. SeRestorePrivilege), some APIs need it enabled
+/// on top of being elevated. Returns whether it could be enabled.
+#[cfg(windows)]
+pub(crate) fn enable_privilege(name: PCWSTR) -> bool {
+    unsafe {
+        let mut token_handle: HANDLE = HANDLE::default();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY, &mut token_handle).is_err() {
+            return false;
+        }
+
+        let mut luid = LUID::default();
+        let mut result = LookupPrivilegeValueW(PCWSTR::null(), name, &mut luid);
+        if result.is_ok() {
+            let privileges = TOKEN_PRIVILEGES {
+                PrivilegeCount: 1,
+                Privileges: [LUID_AND_ATTRIBUTES {
+                    Luid: luid,
+                    Attributes: SE_PRIVILEGE_ENABLED,
+                }],
+            };
+            result = AdjustTokenPrivileges(token_handle, false, Some(&privileges), 0, None, None);
+        }
+        _ = CloseHandle(token_handle);
+        result.is_ok()
+    }
+}
+
+/// Null terminated UTF-16 version of a string, for passing to the Win32 API
+#[cfg(windows)]
+pub(crate) fn to_wide(value: &str) -> Vec<u16> {
+    value.encode_utf16().chain(Some(0)).collect()
+}
+
+/// The Win32 error code of an error (the HRESULT itself if it does not wrap one)
+#[cfg(windows)]
+pub(crate) fn error_code(error: &windows::core::Error) -> WIN32_ERROR {
+    WIN32_ERROR::from_error(error).unwrap_or(WIN32_ERROR(error.code().0 as u32))
+}
+
+/// A failed call of a subsystem other than the registry
+#[cfg(windows)]
+pub(crate) fn system_error(message: String, error: &windows::core::Error) -> DejunkerError {
+    win32_error(message, error_code(error))
+}
+
+/// A failed call of a subsystem other than the registry, from its Win32 error code
+#[cfg(windows)]
+pub(crate) fn win32_error(message: String, result: WIN32_ERROR) -> DejunkerError {
+    if result == ERROR_ACCESS_DENIED {
+        DejunkerError::AccessDenied(message)
+    } else {
+        DejunkerError::System {
+            message,
+            code: Some(result.0),
+        }
+    }
+}
+
+/// test if running elevated (there is no notion of elevation outside of Windows)
+#[cfg(not(windows))]
+pub fn is_elevated() -> bool {
+    false
+}
+
+/// A name with wildcards (`*` for any number of characters, `?` for a single one), as a case
+/// insensitive regular expression matching whole names
+pub(crate) fn wildcard_pattern(name: &str) -> regex::Regex {
+    let pattern = regex::escape(name).replace("\\*", ".*").replace("\\?", ".");
+    regex::Regex::new(&format!("(?i)^(?:{})$", pattern)).expect("escaped names are valid expressions")
+}
+
+/// Bytes as lowercase hex digits, e.g. "0aff"
+pub(crate) fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The inverse of to_hex. None if the string is not an even number of hex digits.
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
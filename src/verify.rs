@@ -0,0 +1,134 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::error::DejunkerError;
+use crate::files::db::Rule;
+use crate::files::settings;
+use crate::registry::RegistryBackend;
+use crate::subsystems::Subsystems;
+use crate::types::state::RuleState;
+use crate::utils;
+
+/// A rule that is not in the state it was set to, e.g. because a policy or some other
+/// software put its values back
+#[derive(Debug, Clone)]
+pub struct RuleDrift {
+    pub rule: String,
+    pub expected: String,
+    pub actual: RuleState,
+}
+
+/// Read back a rule after applying it
+///
+/// * registry: the registry the rule was applied to
+/// * subsystems: the backends of the other subsystems
+/// * rules: the list of known rules
+/// * rule_name: the name of the rule
+/// * expected: the value the rule was set to
+///
+/// Returns None if the rule has the value it was set to, or was not applied (report only
+/// values, and rules skipped because they cannot be applied)
+pub fn verify_rule(
+    registry: &dyn RegistryBackend,
+    subsystems: &Subsystems,
+    rules: &HashMap<String, Rule>,
+    rule_name: &str,
+    expected: &str,
+) -> Result<Option<RuleDrift>, DejunkerError> {
+    let rule = rules
+        .get(rule_name)
+        .ok_or_else(|| DejunkerError::UnknownRule(rule_name.to_string()))?;
+
+    let skipped = (rule.admin_required && registry.requires_elevation() && !utils::is_elevated())
+        || settings::unreachable_entry(registry, subsystems, rule).is_some();
+    if skipped || RuleState::is_report_only(expected) {
+        return Ok(None);
+    }
+
+    let actual = settings::evaluate_rule(registry, subsystems, rule)?;
+    let matches = match &actual {
+        // integers are written as numbers, and read back without e.g. leading zeros
+        RuleState::Value(value) if rule.value.value_type.to_lowercase() == "integer" => {
+            value.parse::<i64>().ok().is_some_and(|value| expected.parse::<i64>() == Ok(value))
+        }
+        RuleState::Value(value) => value == expected,
+        _ => false,
+    };
+
+    Ok(match matches {
+        true => None,
+        false => Some(RuleDrift {
+            rule: rule_name.to_string(),
+            expected: expected.to_string(),
+            actual,
+        }),
+    })
+}
+
+/// Read back rules after applying them
+///
+/// * registry: the registry the rules were applied to
+/// * subsystems: the backends of the other subsystems
+/// * rules: the list of known rules
+/// * requested: the rules that were applied, and the value each was set to
+///
+/// Returns the rules that do not have the value they were set to
+pub fn verify_settings(
+    registry: &dyn RegistryBackend,
+    subsystems: &Subsystems,
+    rules: &HashMap<String, Rule>,
+    requested: &BTreeMap<String, String>,
+) -> Result<Vec<RuleDrift>, DejunkerError> {
+    let mut drifts = Vec::new();
+    for (rule_name, expected) in requested {
+        drifts.extend(verify_rule(registry, subsystems, rules, rule_name, expected)?);
+    }
+    Ok(drifts)
+}
+
+/// Render drifts as text, one line per rule followed by the state of each exec entry of rules
+/// in a mixed state
+pub fn format_drift(drifts: &[RuleDrift]) -> String {
+    let mut output = String::new();
+
+    for drift in drifts {
+        output.push_str(&format!("{}: {} (expected {})\n", drift.rule, drift.actual, drift.expected));
+        if let RuleState::Mixed(entries) = &drift.actual {
+            for entry in entries {
+                output.push_str(&format!("    {}\n", entry));
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::RegistryValue;
+    use crate::test_support::{registry_exec, rule, rules, Machine};
+
+    const KEY: &str = "HKCU\\Software\\Microsoft\\Windows\\CurrentVersion\\AdvertisingInfo";
+
+    #[test]
+    fn values_put_back_after_applying_are_drift() {
+        let rules = rules([rule("advertising-id", "OnOff", vec![registry_exec(KEY, "Enabled", "u32")])]);
+        let requested = BTreeMap::from([("advertising-id".to_string(), "off".to_string())]);
+        let mut machine = Machine::new();
+        machine.set(&rules, "advertising-id", "off").unwrap();
+        let (registry, subsystems) = machine.split();
+        assert!(verify_settings(registry, &subsystems, &rules, &requested).unwrap().is_empty());
+
+        // e.g. a policy writing the value back
+        machine.registry.set_value(KEY, "Enabled", &RegistryValue::Dword(1)).unwrap();
+        let (registry, subsystems) = machine.split();
+        let drifts = verify_settings(registry, &subsystems, &rules, &requested).unwrap();
+        assert_eq!(drifts.len(), 1);
+        assert_eq!(drifts[0].actual, RuleState::Value("on".to_string()));
+        assert_eq!(format_drift(&drifts), "advertising-id: on (expected off)\n");
+
+        // which the command line reports with its own exit code
+        let error = DejunkerError::Drift(drifts.into_iter().map(|drift| drift.rule).collect());
+        assert_eq!(error.exit_code(), 12);
+        assert_eq!(error.to_string(), "Rules not in the requested state after applying: advertising-id");
+    }
+}